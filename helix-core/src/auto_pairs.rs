@@ -1,9 +1,13 @@
-use crate::{Range, Rope, Selection, Tendril, Transaction};
+use crate::{
+    chars::char_is_line_ending, graphemes, Range, Rope, RopeSlice, Selection, Syntax, Tendril,
+    Transaction,
+};
+use once_cell::sync::Lazy;
 use smallvec::SmallVec;
 
 // Heavily based on https://github.com/codemirror/closebrackets/
 
-pub const PAIRS: &[(char, char)] = &[
+pub const DEFAULT_PAIRS: &[(char, char)] = &[
     ('(', ')'),
     ('{', '}'),
     ('[', ']'),
@@ -12,8 +16,59 @@ pub const PAIRS: &[(char, char)] = &[
     ('`', '`'),
 ];
 
+/// The pairs used when a language doesn't configure its own.
+pub static DEFAULT_AUTO_PAIRS: Lazy<AutoPairs> = Lazy::new(AutoPairs::default);
+
 const CLOSE_BEFORE: &str = ")]}'\":;> \n\r\u{000B}\u{000C}\u{0085}\u{2028}\u{2029}"; // includes space and newlines
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pair {
+    pub open: char,
+    pub close: char,
+}
+
+impl Pair {
+    /// Whether the pair opens and closes with the same character, like quotes.
+    pub fn same(&self) -> bool {
+        self.open == self.close
+    }
+}
+
+/// The characters to insert in pairs, configured per language with `auto-pairs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoPairs(Vec<Pair>);
+
+impl AutoPairs {
+    pub fn new(pairs: impl IntoIterator<Item = (char, char)>) -> Self {
+        Self(
+            pairs
+                .into_iter()
+                .map(|(open, close)| Pair { open, close })
+                .collect(),
+        )
+    }
+
+    /// Finds the pair `ch` opens or closes.
+    pub fn get(&self, ch: char) -> Option<&Pair> {
+        self.0
+            .iter()
+            .find(|pair| pair.open == ch)
+            .or_else(|| self.0.iter().find(|pair| pair.close == ch))
+    }
+
+    pub fn contains(&self, open: char, close: char) -> bool {
+        self.0
+            .iter()
+            .any(|pair| pair.open == open && pair.close == close)
+    }
+}
+
+impl Default for AutoPairs {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAIRS.iter().copied())
+    }
+}
+
 // insert hook:
 // Fn(doc, selection, char) => Option<Transaction>
 // problem is, we want to do this per range, so we can call default handler for some ranges
@@ -22,138 +77,184 @@ const CLOSE_BEFORE: &str = ")]}'\":;> \n\r\u{000B}\u{000C}\u{0085}\u{2028}\u{202
 //
 // to simplify, maybe return Option<Transaction> and just reimplement the default
 
-// TODO: delete implementation where it erases the whole bracket (|) -> |
-
+/// Handles typing `ch` when it is part of one of the `pairs`: ranges that span more than a
+/// character get surrounded by the pair, an opening character gets closed right away unless it's
+/// typed in a string or a comment, and typing a closing character in front of the same one moves
+/// over it.
 #[must_use]
-pub fn hook(doc: &Rope, selection: &Selection, ch: char) -> Option<Transaction> {
-    for &(open, close) in PAIRS {
-        if open == ch {
-            if open == close {
-                return handle_same(doc, selection, open);
-            } else {
-                return Some(handle_open(doc, selection, open, close, CLOSE_BEFORE));
-            }
+pub fn hook(
+    doc: &Rope,
+    selection: &Selection,
+    ch: char,
+    pairs: &AutoPairs,
+    syntax: Option<&Syntax>,
+) -> Option<Transaction> {
+    let pair = *pairs.get(ch)?;
+    let text = doc.slice(..);
+
+    let mut ranges = SmallVec::with_capacity(selection.len());
+    let mut changes = Vec::with_capacity(selection.len() * 2);
+    let mut offs = 0;
+
+    for range in selection {
+        // typing the closing character of a selection inserts it at the cursor as usual
+        if ch == pair.open && is_selection(text, range) {
+            // wrap the selection, keeping it selected
+            changes.push((
+                range.from(),
+                range.from(),
+                Some(Tendril::from_char(pair.open)),
+            ));
+            changes.push((range.to(), range.to(), Some(Tendril::from_char(pair.close))));
+            ranges.push(Range::new(range.anchor + offs + 1, range.head + offs + 1));
+            offs += 2;
+            continue;
         }
 
-        if close == ch {
-            // && char_at pos == close
-            return Some(handle_close(doc, selection, open, close));
+        let pos = range.cursor(text);
+        let prev = pos.checked_sub(1).map(|pos| text.char(pos));
+        let next = text.get_char(pos);
+
+        if ch == pair.close && next == Some(ch) {
+            // move over the closing character
+            ranges.push(Range::point(pos + offs + 1));
+            continue;
+        }
+
+        let should_close = ch == pair.open
+            && next.map_or(true, |next| CLOSE_BEFORE.contains(next))
+            && !(pair.same() && prev.map_or(false, |prev| is_quoted_word(ch, prev)))
+            && !syntax.map_or(false, |syntax| in_string_or_comment(syntax, text, pos));
+
+        let mut insert = Tendril::from_char(ch);
+        if should_close {
+            insert.push_char(pair.close);
         }
+        changes.push((pos, pos, Some(insert)));
+        ranges.push(Range::point(pos + offs + 1));
+        offs += if should_close { 2 } else { 1 };
     }
 
-    None
+    Some(
+        Transaction::change(doc, changes.into_iter())
+            .with_selection(Selection::new(ranges, selection.primary_index())),
+    )
 }
 
-// TODO: special handling for lifetimes in rust: if preceeded by & or < don't auto close '
-// for example "&'a mut", or "fn<'a>"
+/// Whether a quote typed after `prev` is part of a word rather than opening a string, like I'm,
+/// or a lifetime like `&'a` or `<'a>`.
+fn is_quoted_word(quote: char, prev: char) -> bool {
+    prev.is_alphanumeric() || prev == '_' || (quote == '\'' && matches!(prev, '&' | '<'))
+}
 
-fn next_char(doc: &Rope, pos: usize) -> Option<char> {
-    if pos >= doc.len_chars() {
-        return None;
-    }
-    Some(doc.char(pos))
+/// Whether the range is a selection rather than just the block cursor.
+fn is_selection(text: RopeSlice, range: &Range) -> bool {
+    graphemes::next_grapheme_boundary(text, range.from()) < range.to()
 }
-// TODO: selections should be extended if range, moved if point.
 
-// TODO: if not cursor but selection, wrap on both sides of selection (surround)
-fn handle_open(
-    doc: &Rope,
-    selection: &Selection,
-    open: char,
-    close: char,
-    close_before: &str,
-) -> Transaction {
-    let mut ranges = SmallVec::with_capacity(selection.len());
+/// Whether `pos` is inside a string or a comment, where pairs aren't closed.
+fn in_string_or_comment(syntax: &Syntax, text: RopeSlice, pos: usize) -> bool {
+    let byte = text.char_to_byte(pos);
+    // a line comment ends right before the line ending, typing there is still in the comment
+    let at_line_end = text.get_char(pos).map_or(true, char_is_line_ending);
+    syntax.layers_for_byte_range(byte, byte).any(|layer| {
+        let mut node = layer
+            .tree()
+            .root_node()
+            .descendant_for_byte_range(byte.saturating_sub(1), byte);
+        while let Some(current) = node {
+            let kind = current.kind();
+            let inside = current.start_byte() < byte && byte < current.end_byte();
+            if (kind.contains("string") && inside)
+                || (kind.contains("comment")
+                    && (inside || at_line_end && byte == current.end_byte()))
+            {
+                return true;
+            }
+            node = current.parent();
+        }
+        false
+    })
+}
 
-    let mut offs = 0;
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    let transaction = Transaction::change_by_selection(doc, selection, |range| {
-        let pos = range.head;
-        let next = next_char(doc, pos);
-
-        let head = pos + offs + open.len_utf8();
-        // if selection, retain anchor, if cursor, move over
-        ranges.push(Range::new(
-            if range.is_empty() {
-                head
-            } else {
-                range.anchor + offs
-            },
-            head,
-        ));
-
-        match next {
-            Some(ch) if !close_before.contains(ch) => {
-                // TODO: else return (use default handler that inserts open)
-                (pos, pos, Some(Tendril::from_char(open)))
-            }
-            // None | Some(ch) if close_before.contains(ch) => {}
-            _ => {
-                // insert open & close
-                let mut pair = Tendril::with_capacity(2);
-                pair.push_char(open);
-                pair.push_char(close);
+    fn apply(text: &str, selection: Selection, ch: char) -> (Rope, Selection) {
+        let mut doc = Rope::from(text);
+        let transaction = hook(&doc, &selection, ch, &DEFAULT_AUTO_PAIRS, None).unwrap();
+        transaction.apply(&mut doc);
+        let selection = transaction.selection().unwrap().clone();
+        (doc, selection)
+    }
 
-                offs += 2;
+    #[test]
+    fn test_close_pairs() {
+        // cursor before a space: close
+        let (doc, selection) = apply("foo ", Selection::point(3), '(');
+        assert_eq!(doc, "foo() ");
+        assert_eq!(selection, Selection::point(4));
 
-                (pos, pos, Some(pair))
-            }
-        }
-    });
+        // cursor before a word: don't close
+        let (doc, _) = apply("foo", Selection::point(0), '[');
+        assert_eq!(doc, "[foo");
 
-    transaction.with_selection(Selection::new(ranges, selection.primary_index()))
-}
+        // move over the closing character
+        let (doc, selection) = apply("()", Selection::point(1), ')');
+        assert_eq!(doc, "()");
+        assert_eq!(selection, Selection::point(2));
 
-fn handle_close(doc: &Rope, selection: &Selection, _open: char, close: char) -> Transaction {
-    let mut ranges = SmallVec::with_capacity(selection.len());
+        // quotes
+        let (doc, _) = apply("x = ", Selection::point(4), '"');
+        assert_eq!(doc, "x = \"\"");
+        let (doc, _) = apply("I", Selection::point(1), '\'');
+        assert_eq!(doc, "I'");
+        let (doc, _) = apply("\"\"", Selection::point(1), '"');
+        assert_eq!(doc, "\"\"");
 
-    let mut offs = 0;
+        // lifetimes and chars
+        let (doc, _) = apply("x: &", Selection::point(4), '\'');
+        assert_eq!(doc, "x: &'");
+        let (doc, _) = apply("Foo<", Selection::point(4), '\'');
+        assert_eq!(doc, "Foo<'");
+        let (doc, _) = apply("c = ", Selection::point(4), '\'');
+        assert_eq!(doc, "c = ''");
+        let (doc, selection) = apply("c = 'x'", Selection::point(6), '\'');
+        assert_eq!(doc, "c = 'x'");
+        assert_eq!(selection, Selection::point(7));
 
-    let transaction = Transaction::change_by_selection(doc, selection, |range| {
-        let pos = range.head;
-        let next = next_char(doc, pos);
-
-        let head = pos + offs + close.len_utf8();
-        // if selection, retain anchor, if cursor, move over
-        ranges.push(Range::new(
-            if range.is_empty() {
-                head
-            } else {
-                range.anchor + offs
-            },
-            head,
-        ));
-
-        if next == Some(close) {
-            //  return transaction that moves past close
-            (pos, pos, None) // no-op
-        } else {
-            offs += close.len_utf8();
-
-            // TODO: else return (use default handler that inserts close)
-            (pos, pos, Some(Tendril::from_char(close)))
-        }
-    });
+        // not a pair
+        assert!(hook(
+            &Rope::from(""),
+            &Selection::point(0),
+            'x',
+            &DEFAULT_AUTO_PAIRS,
+            None
+        )
+        .is_none());
+    }
 
-    transaction.with_selection(Selection::new(ranges, selection.primary_index()))
-}
+    #[test]
+    fn test_surround_selection() {
+        let (doc, selection) = apply(
+            "one two",
+            Selection::new(smallvec::smallvec![Range::new(0, 3), Range::new(7, 4)], 0),
+            '{',
+        );
+        assert_eq!(doc, "{one} {two}");
+        assert_eq!(selection.ranges(), &[Range::new(1, 4), Range::new(10, 7)]);
+    }
 
-// handle cases where open and close is the same, or in triples ("""docstring""")
-fn handle_same(_doc: &Rope, _selection: &Selection, _token: char) -> Option<Transaction> {
-    // if not cursor but selection, wrap
-    // let next = next char
-
-    // if next == bracket {
-    //   // if start of syntax node, insert token twice (new pair because node is complete)
-    //   // elseif colsedBracketAt
-    //      // is_triple == allow triple && next 3 is equal
-    //      // cursor jump over
-    // }
-    //} else if allow_triple && followed by triple {
-    //}
-    //} else if next != word char && prev != bracket && prev != word char {
-    // // condition checks for cases like I' where you don't want I'' (or I'm)
-    //  insert pair ("")
-    //}
-    None
+    #[test]
+    fn test_configured_pairs() {
+        let pairs = AutoPairs::new(vec![('<', '>')]);
+        let doc = Rope::from(" ");
+        assert!(hook(&doc, &Selection::point(0), '(', &pairs, None).is_none());
+        let transaction = hook(&doc, &Selection::point(0), '<', &pairs, None).unwrap();
+        let mut doc = doc;
+        transaction.apply(&mut doc);
+        assert_eq!(doc, "<> ");
+        assert!(pairs.contains('<', '>'));
+    }
 }
@@ -1,6 +1,8 @@
 use crate::{
-    find_first_non_whitespace_char, Change, Rope, RopeSlice, Selection, Tendril, Transaction,
+    find_first_non_whitespace_char, line_ending::line_end_char_index, syntax::BlockCommentToken,
+    Change, Range, Rope, RopeSlice, Selection, Tendril, Transaction,
 };
+use smallvec::SmallVec;
 use std::borrow::Cow;
 
 /// Given text, a comment token, and a set of line indices, returns the following:
@@ -91,6 +93,165 @@ pub fn toggle_line_comments(doc: &Rope, selection: &Selection, token: Option<&st
     Transaction::change(doc, changes.into_iter())
 }
 
+/// A block comment found by [`find_block_comments`], the positions are char indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockComment {
+    /// The text between `start` and `end` is commented with the `token`-th token. The margins
+    /// are the spaces between the tokens and the commented text.
+    Commented {
+        start: usize,
+        end: usize,
+        token: usize,
+        start_margin: bool,
+        end_margin: bool,
+    },
+    /// The text between `start` and `end` is not commented.
+    Uncommented { start: usize, end: usize },
+    /// The range only contains whitespace.
+    Blank,
+}
+
+/// Finds the block comment wrapping each range of the selection, ignoring the whitespace around
+/// it. Returns whether the selection should be considered commented, which is when all of its
+/// non-blank ranges are.
+pub fn find_block_comments(
+    tokens: &[BlockCommentToken],
+    text: RopeSlice,
+    selection: &Selection,
+) -> (bool, Vec<BlockComment>) {
+    let mut commented = true;
+    let mut only_blank = true;
+    let comments = selection
+        .iter()
+        .map(|range| {
+            let slice = text.slice(range.from()..range.to());
+            let start = match find_first_non_whitespace_char(slice) {
+                Some(start) => range.from() + start,
+                None => return BlockComment::Blank,
+            };
+            only_blank = false;
+            let content = Cow::from(text.slice(start..range.to()));
+            let content = content.trim_end();
+            let end = start + content.chars().count();
+
+            // prefer the longest token, `/**` over `/*`
+            let token = tokens
+                .iter()
+                .enumerate()
+                .filter(|(_, token)| {
+                    content.len() >= token.start.len() + token.end.len()
+                        && content.starts_with(&token.start)
+                        && content.ends_with(&token.end)
+                })
+                .max_by_key(|(_, token)| token.start.len())
+                .map(|(i, _)| i);
+            match token {
+                Some(token) => {
+                    let inner = &content
+                        [tokens[token].start.len()..content.len() - tokens[token].end.len()];
+                    let start_margin = inner.starts_with(' ');
+                    // a single space between the tokens is only one margin
+                    let end_margin = inner.ends_with(' ') && inner.len() > start_margin as usize;
+                    BlockComment::Commented {
+                        start,
+                        end,
+                        token,
+                        start_margin,
+                        end_margin,
+                    }
+                }
+                None => {
+                    commented = false;
+                    BlockComment::Uncommented { start, end }
+                }
+            }
+        })
+        .collect();
+    (commented && !only_blank, comments)
+}
+
+/// Wraps each range of the selection in a block comment, or removes the block comments if all of
+/// them already are commented. The ranges are extended to cover the tokens they gain.
+#[must_use]
+pub fn toggle_block_comments(
+    doc: &Rope,
+    selection: &Selection,
+    tokens: &[BlockCommentToken],
+) -> Transaction {
+    let text = doc.slice(..);
+    let (commented, comments) = find_block_comments(tokens, text, selection);
+    let default_token = match tokens.first() {
+        Some(token) => token,
+        None => return Transaction::new(doc),
+    };
+
+    let mut changes: Vec<Change> = Vec::with_capacity(comments.len() * 2);
+    let mut ranges = SmallVec::with_capacity(comments.len());
+    // chars inserted minus chars removed before the current range
+    let mut offs: isize = 0;
+    let shift = |pos: usize, offs: isize| (pos as isize + offs) as usize;
+
+    for (range, comment) in selection.iter().zip(comments) {
+        let (from, to) = match comment {
+            BlockComment::Commented {
+                start,
+                end,
+                token,
+                start_margin,
+                end_margin,
+            } if commented => {
+                let token = &tokens[token];
+                let start_len = token.start.chars().count() + start_margin as usize;
+                let end_len = token.end.chars().count() + end_margin as usize;
+                changes.push((start, start + start_len, None));
+                changes.push((end - end_len, end, None));
+                let from = shift(range.from(), offs);
+                offs -= (start_len + end_len) as isize;
+                (from, shift(range.to(), offs))
+            }
+            BlockComment::Commented { start, end, .. }
+            | BlockComment::Uncommented { start, end } => {
+                let start_token = Tendril::from(format!("{} ", default_token.start));
+                let end_token = Tendril::from(format!(" {}", default_token.end));
+                let len = start_token.chars().count() + end_token.chars().count();
+                changes.push((start, start, Some(start_token)));
+                changes.push((end, end, Some(end_token)));
+                let from = shift(range.from(), offs);
+                offs += len as isize;
+                (from, shift(range.to(), offs))
+            }
+            BlockComment::Blank => (shift(range.from(), offs), shift(range.to(), offs)),
+        };
+        ranges.push(if range.anchor <= range.head {
+            Range::new(from, to)
+        } else {
+            Range::new(to, from)
+        });
+    }
+
+    Transaction::change(doc, changes.into_iter())
+        .with_selection(Selection::new(ranges, selection.primary_index()))
+}
+
+/// Splits the ranges of the selection into one range per line, without the line endings. Lines
+/// covered by several ranges are only included once.
+pub fn split_lines_of_selection(text: RopeSlice, selection: &Selection) -> Selection {
+    let mut ranges = SmallVec::new();
+    let mut min_next_line = 0;
+    for range in selection {
+        let (start, end) = range.line_range(text);
+        for line in start.max(min_next_line)..=end {
+            let line_start = text.line_to_char(line);
+            ranges.push(Range::new(line_start, line_end_char_index(&text, line)));
+        }
+        min_next_line = end + 1;
+    }
+    if ranges.is_empty() {
+        return selection.clone();
+    }
+    Selection::new(ranges, 0)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -137,4 +298,72 @@ mod test {
 
         // TODO: account for uncommenting with uneven comment indentation
     }
+
+    fn block_tokens() -> Vec<BlockCommentToken> {
+        vec![
+            BlockCommentToken {
+                start: "/*".into(),
+                end: "*/".into(),
+            },
+            BlockCommentToken {
+                start: "/**".into(),
+                end: "*/".into(),
+            },
+        ]
+    }
+
+    fn toggle_block(doc: &mut Rope, selection: &Selection) -> Selection {
+        let transaction = toggle_block_comments(doc, selection, &block_tokens());
+        transaction.apply(doc);
+        transaction.selection().unwrap().clone()
+    }
+
+    #[test]
+    fn test_toggle_block_comments() {
+        let mut doc = Rope::from("one two  three\n");
+        let selection = Selection::new(
+            smallvec::smallvec![Range::new(0, 4), Range::new(8, 4), Range::new(14, 15)],
+            1,
+        );
+
+        // comment, ignoring the surrounding whitespace and blank ranges
+        let selection = toggle_block(&mut doc, &selection);
+        assert_eq!(doc, "/* one */ /* two */  three\n");
+        assert_eq!(
+            selection.ranges(),
+            &[Range::new(0, 10), Range::new(20, 10), Range::new(26, 27)]
+        );
+        assert!(find_block_comments(&block_tokens(), doc.slice(..), &selection).0);
+
+        // uncomment
+        let selection = toggle_block(&mut doc, &selection);
+        assert_eq!(doc, "one two  three\n");
+        assert_eq!(
+            selection.ranges(),
+            &[Range::new(0, 4), Range::new(8, 4), Range::new(14, 15)]
+        );
+
+        // a single uncommented range comments all of them again
+        let mut doc = Rope::from("/*a*/ b");
+        let selection = Selection::new(smallvec::smallvec![Range::new(0, 5), Range::new(6, 7)], 0);
+        toggle_block(&mut doc, &selection);
+        assert_eq!(doc, "/* /*a*/ */ /* b */");
+
+        // any of the tokens can be uncommented, margins are optional
+        let mut doc = Rope::from("/**a */ /* */");
+        let selection = Selection::new(smallvec::smallvec![Range::new(0, 7), Range::new(8, 13)], 0);
+        toggle_block(&mut doc, &selection);
+        assert_eq!(doc, "a ");
+    }
+
+    #[test]
+    fn test_split_lines_of_selection() {
+        let text = Rope::from("one\ntwo\r\nthree");
+        let selection = Selection::new(smallvec::smallvec![Range::new(1, 2), Range::new(5, 12)], 0);
+        let lines = split_lines_of_selection(text.slice(..), &selection);
+        assert_eq!(
+            lines.ranges(),
+            &[Range::new(0, 3), Range::new(4, 7), Range::new(9, 14)]
+        );
+    }
 }
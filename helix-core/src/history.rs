@@ -1,8 +1,11 @@
 use crate::{ChangeSet, Rope, State, Transaction};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
 use std::num::NonZeroUsize;
-use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 // Stores the history of changes to a buffer.
 //
@@ -39,14 +42,19 @@ use std::time::{Duration, Instant};
 //    editing sessions.
 //  * Because delete transactions currently don't store the text that they
 //    delete, we also store an inversion of the transaction.
-#[derive(Debug)]
+//
+// The history can be persisted across editor sessions: it is serialized to a
+// file under the cache directory, keyed by the path of the document and a hash
+// of its contents. It is only restored if the file still has the same contents
+// as when the history was stored.
+#[derive(Debug, Clone)]
 pub struct History {
     revisions: Vec<Revision>,
     current: usize,
 }
 
 // A single point in history. See [History] for more information.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Revision {
     parent: usize,
     last_child: Option<NonZeroUsize>,
@@ -111,6 +119,30 @@ impl History {
         self.current == 0
     }
 
+    /// Number of revisions in the history, including the root revision.
+    #[allow(clippy::len_without_is_empty)] // the root revision is always present
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.revisions.len()
+    }
+
+    /// The revision `revision` was committed on top of. The root revision is its own parent.
+    #[inline]
+    pub fn parent(&self, revision: usize) -> usize {
+        self.revisions[revision].parent
+    }
+
+    #[inline]
+    pub fn timestamp(&self, revision: usize) -> Instant {
+        self.revisions[revision].timestamp
+    }
+
+    /// Changes that transition the parent of `revision` to `revision`.
+    #[inline]
+    pub fn changes(&self, revision: usize) -> &ChangeSet {
+        self.revisions[revision].transaction.changes()
+    }
+
     pub fn undo(&mut self) -> Option<&Transaction> {
         if self.at_root() {
             return None;
@@ -158,7 +190,9 @@ impl History {
         path
     }
 
-    fn jump_to(&mut self, to: usize) -> Vec<Transaction> {
+    /// Moves to an arbitrary revision in the tree, returning the transactions that have to be
+    /// applied to get from the current revision there.
+    pub fn jump_to(&mut self, to: usize) -> Vec<Transaction> {
         let lca = self.lowest_common_ancestor(self.current, to);
         let up = self.path_up(self.current, lca);
         let down = self.path_up(to, lca);
@@ -240,6 +274,143 @@ impl History {
     }
 }
 
+// On-disk representation of a [History]. `Instant`s are only meaningful within a single
+// process, so timestamps are stored as system time instead.
+#[derive(Serialize, Deserialize)]
+struct SavedHistory {
+    path: PathBuf,
+    hash: u64,
+    current: usize,
+    revisions: Vec<SavedRevision>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedRevision {
+    parent: usize,
+    last_child: Option<NonZeroUsize>,
+    transaction: Transaction,
+    inversion: Transaction,
+    timestamp: SystemTime,
+}
+
+impl History {
+    /// Serializes the history of the document at `path` for [`History::write_to_cache`].
+    /// `text` should be the contents of the file on disk, which need to match the current
+    /// revision.
+    pub fn serialize_for_cache(&self, path: &Path, text: &Rope) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf, path, text)?;
+        Ok(buf)
+    }
+
+    /// Stores a history serialized with [`History::serialize_for_cache`] in the cache directory.
+    pub fn write_to_cache(path: &Path, data: &[u8]) -> io::Result<()> {
+        let cache_path = cache_path(path);
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(cache_path, data)
+    }
+
+    /// Restores the history of the document at `path` from the cache directory. Returns
+    /// `None` if no history was stored or if `text` changed since it was.
+    pub fn load_from_cache(path: &Path, text: &Rope) -> Option<Self> {
+        let file = std::fs::File::open(cache_path(path)).ok()?;
+        Self::read_from(io::BufReader::new(file), path, text)
+            .ok()
+            .flatten()
+    }
+
+    fn write_to<W: Write>(&self, writer: W, path: &Path, text: &Rope) -> io::Result<()> {
+        let (now, system_now) = (Instant::now(), SystemTime::now());
+        let revisions = self
+            .revisions
+            .iter()
+            .map(|revision| SavedRevision {
+                parent: revision.parent,
+                last_child: revision.last_child,
+                transaction: revision.transaction.clone(),
+                inversion: revision.inversion.clone(),
+                timestamp: system_now
+                    .checked_sub(now.saturating_duration_since(revision.timestamp))
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+            })
+            .collect();
+
+        let saved = SavedHistory {
+            path: path.to_path_buf(),
+            hash: hash(text.chunks().map(str::as_bytes)),
+            current: self.current,
+            revisions,
+        };
+        serde_json::to_writer(writer, &saved)?;
+        Ok(())
+    }
+
+    fn read_from<R: Read>(reader: R, path: &Path, text: &Rope) -> io::Result<Option<Self>> {
+        let saved: SavedHistory = serde_json::from_reader(reader)?;
+
+        if saved.path != path || saved.hash != hash(text.chunks().map(str::as_bytes)) {
+            return Ok(None);
+        }
+        if saved.revisions.is_empty() || saved.current >= saved.revisions.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid history",
+            ));
+        }
+
+        // Revisions are ordered by timestamp. Convert them back from the newest one down, and
+        // clamp those too old to be represented as an `Instant` to keep that order intact.
+        let (now, system_now) = (Instant::now(), SystemTime::now());
+        let mut oldest = now;
+        let mut revisions: Vec<_> = saved
+            .revisions
+            .into_iter()
+            .rev()
+            .map(|revision| {
+                let age = system_now
+                    .duration_since(revision.timestamp)
+                    .unwrap_or_default();
+                let timestamp = now.checked_sub(age).unwrap_or(oldest).min(oldest);
+                oldest = timestamp;
+                Revision {
+                    parent: revision.parent,
+                    last_child: revision.last_child,
+                    transaction: revision.transaction,
+                    inversion: revision.inversion,
+                    timestamp,
+                }
+            })
+            .collect();
+        revisions.reverse();
+
+        Ok(Some(Self {
+            revisions,
+            current: saved.current,
+        }))
+    }
+}
+
+/// Location of the cached history of the document at `path`.
+fn cache_path(path: &Path) -> PathBuf {
+    let key = hash(std::iter::once(path.to_string_lossy().as_bytes()));
+    crate::cache_dir()
+        .join("history")
+        .join(format!("{:016x}.json", key))
+}
+
+/// 64-bit FNV-1a hash. Unlike `DefaultHasher`, the output is stable across builds, which is
+/// required for anything written to disk.
+fn hash<'a>(chunks: impl Iterator<Item = &'a [u8]>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in chunks.flatten() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
 #[derive(Debug, PartialEq)]
 pub enum UndoKind {
     Steps(usize),
@@ -551,4 +722,58 @@ mod test {
             Err("duration too large".to_string())
         );
     }
+
+    #[test]
+    fn test_save_load() {
+        let mut history = History::default();
+        let mut state = State::new(Rope::from("hello"));
+
+        for change in [(5, 5, " world"), (0, 5, "goodbye"), (7, 7, ",")] {
+            let (from, to, text) = change;
+            let txn =
+                Transaction::change(&state.doc, vec![(from, to, Some(text.into()))].into_iter());
+            history.commit_revision(&txn, &state);
+            txn.apply(&mut state.doc);
+        }
+        // create a branch
+        let txn = history.undo().unwrap().clone();
+        txn.apply(&mut state.doc);
+        assert_eq!("goodbye world", state.doc);
+
+        let path = Path::new("/tmp/hello.txt");
+        let mut buf = Vec::new();
+        history.write_to(&mut buf, path, &state.doc).unwrap();
+
+        // the history is only restored for the same file with the same contents
+        let other = Path::new("/tmp/other.txt");
+        assert!(History::read_from(&buf[..], other, &state.doc)
+            .unwrap()
+            .is_none());
+        let modified = Rope::from("goodbye world!");
+        assert!(History::read_from(&buf[..], path, &modified)
+            .unwrap()
+            .is_none());
+
+        let mut restored = History::read_from(&buf[..], path, &state.doc)
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.current_revision(), history.current_revision());
+
+        // timestamps keep their order
+        assert!(restored
+            .revisions
+            .windows(2)
+            .all(|revs| revs[0].timestamp <= revs[1].timestamp));
+
+        let txn = restored.redo().unwrap().clone();
+        txn.apply(&mut state.doc);
+        assert_eq!("goodbye, world", state.doc);
+
+        for _ in 0..3 {
+            let txn = restored.undo().unwrap().clone();
+            txn.apply(&mut state.doc);
+        }
+        assert_eq!("hello", state.doc);
+        assert!(restored.at_root());
+    }
 }
@@ -0,0 +1,545 @@
+use crate::{chars::char_is_line_ending, Range, RopeSlice, Tendril};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use std::convert::TryFrom;
+
+/// Finds the number, date, time or boolean under the cursor of `range`, or the first one after
+/// it on the same line, and returns the span it covers along with its value incremented by
+/// `amount`. Booleans are toggled whatever the amount.
+pub fn increment(text: RopeSlice, range: Range, amount: i64) -> Option<(usize, usize, Tendril)> {
+    let pos = range.cursor(text);
+    let line_start = text.line_to_char(text.char_to_line(pos));
+    let chars: Vec<char> = text
+        .slice(line_start..)
+        .chars()
+        .take_while(|ch| !char_is_line_ending(*ch))
+        .collect();
+    let offset = pos - line_start;
+
+    let mut start = 0;
+    while start < chars.len() {
+        let literal = DateTime::parse(&chars, start)
+            .map(Literal::DateTime)
+            .or_else(|| Number::parse(&chars, start).map(Literal::Number))
+            .or_else(|| Boolean::parse(&chars, start).map(Literal::Boolean));
+
+        match literal {
+            Some(literal) if start + literal.len() > offset => {
+                let end = line_start + start + literal.len();
+                let replacement = match literal {
+                    Literal::DateTime(date_time) => {
+                        date_time.increment(offset.saturating_sub(start), amount)?
+                    }
+                    Literal::Number(number) => number.increment(amount)?,
+                    Literal::Boolean(boolean) => boolean.toggle(),
+                };
+                return Some((line_start + start, end, replacement.into()));
+            }
+            Some(literal) => start += literal.len(),
+            None => start += 1,
+        }
+    }
+
+    None
+}
+
+enum Literal {
+    DateTime(DateTime),
+    Number(Number),
+    Boolean(Boolean),
+}
+
+impl Literal {
+    /// The length of the literal in chars.
+    fn len(&self) -> usize {
+        match self {
+            Self::DateTime(date_time) => date_time.pattern.len(),
+            Self::Number(number) => number.len,
+            Self::Boolean(boolean) => boolean.word.len(),
+        }
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// An integer literal: decimal, or hexadecimal, octal and binary with a `0x`, `0o` or `0b`
+/// prefix. Digits may be grouped with `_` and followed by a type suffix, like in Rust.
+#[derive(Debug, PartialEq, Eq)]
+struct Number {
+    len: usize,
+    negative: bool,
+    radix: u32,
+    prefix: String,
+    /// The digits, including separators.
+    digits: String,
+    suffix: String,
+}
+
+const INTEGER_SUFFIXES: &[&str] = &[
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+];
+const FLOAT_SUFFIXES: &[&str] = &["f32", "f64"];
+
+impl Number {
+    fn parse(chars: &[char], start: usize) -> Option<Self> {
+        let mut pos = start;
+        let negative = chars[pos] == '-';
+        if negative {
+            // a minus after a word is a subtraction
+            if start > 0 && (is_word_char(chars[start - 1]) || chars[start - 1] == ')') {
+                return None;
+            }
+            pos += 1;
+        }
+        if !chars.get(pos)?.is_ascii_digit() {
+            return None;
+        }
+
+        let radix = match (chars[pos], chars.get(pos + 1)) {
+            ('0', Some('x' | 'X')) => 16,
+            ('0', Some('o' | 'O')) => 8,
+            ('0', Some('b' | 'B')) => 2,
+            _ => 10,
+        };
+        let prefix: String = if radix == 10 {
+            String::new()
+        } else if negative {
+            return None;
+        } else {
+            pos += 2;
+            chars[start..pos].iter().collect()
+        };
+
+        let digits_start = pos;
+        while pos < chars.len() && (chars[pos].is_digit(radix) || chars[pos] == '_') {
+            pos += 1;
+        }
+        let digits: String = chars[digits_start..pos].iter().collect();
+        if !digits.chars().any(|ch| ch.is_digit(radix)) {
+            return None;
+        }
+
+        let rest: String = chars[pos..].iter().collect();
+        let suffix = INTEGER_SUFFIXES
+            .iter()
+            .chain(if radix == 10 { FLOAT_SUFFIXES } else { &[] })
+            .filter(|suffix| rest.starts_with(*suffix))
+            .find(|suffix| !rest[suffix.len()..].starts_with(is_word_char))
+            .map_or_else(String::new, |suffix| suffix.to_string());
+
+        // a trailing separator is only part of the number when a suffix follows it
+        let digits = if suffix.is_empty() {
+            digits.trim_end_matches('_').to_string()
+        } else {
+            digits
+        };
+
+        Some(Self {
+            len: usize::from(negative) + prefix.len() + digits.len() + suffix.len(),
+            negative,
+            radix,
+            prefix,
+            digits,
+            suffix,
+        })
+    }
+
+    /// The bits of the integer type of the suffix, `usize` and `isize` count as 64 bits.
+    fn bits(&self) -> Option<u32> {
+        let bits = self.suffix.strip_prefix(|ch| ch == 'u' || ch == 'i')?;
+        Some(bits.parse().unwrap_or(64))
+    }
+
+    fn increment(&self, amount: i64) -> Option<String> {
+        let digits = self.digits.trim_end_matches('_');
+        let value = u128::from_str_radix(&digits.replace('_', ""), self.radix).ok()?;
+        let width = digits.chars().filter(|ch| *ch != '_').count();
+
+        let (negative, value, width) = if self.radix == 10 {
+            let value = i128::try_from(value).ok()?;
+            let value = if self.negative { -value } else { value };
+            let value = value.checked_add(i128::from(amount))?;
+            // numbers with a type suffix wrap around within the range of their type
+            let value = match self.bits() {
+                Some(bits) if bits < 128 => {
+                    let value = value.rem_euclid(1 << bits);
+                    if self.suffix.starts_with('i') && value >= 1 << (bits - 1) {
+                        value - (1 << bits)
+                    } else {
+                        value
+                    }
+                }
+                _ => value,
+            };
+            // only keep the width of zero padded numbers
+            let width = if digits.starts_with('0') { width } else { 0 };
+            (value < 0, value.unsigned_abs(), width)
+        } else {
+            // numbers with a radix prefix wrap around within the bits of their type
+            let bits = self.bits().unwrap_or(64);
+            let value = (value as i128).wrapping_add(i128::from(amount)) as u128;
+            let value = if bits < 128 {
+                value & ((1 << bits) - 1)
+            } else {
+                value
+            };
+            (false, value, width)
+        };
+
+        let mut new_digits = match self.radix {
+            2 => format!("{:0width$b}", value, width = width),
+            8 => format!("{:0width$o}", value, width = width),
+            16 if digits.chars().any(|ch| ch.is_ascii_uppercase()) => {
+                format!("{:0width$X}", value, width = width)
+            }
+            16 => format!("{:0width$x}", value, width = width),
+            _ => format!("{:0width$}", value, width = width),
+        };
+
+        // regroup the digits the way the last group was separated
+        if let Some(group) = digits.rfind('_').map(|idx| digits.len() - idx - 1) {
+            if group > 0 {
+                new_digits = group_digits(&new_digits, group);
+            }
+        }
+        if self.digits.ends_with('_') {
+            new_digits.push('_');
+        }
+
+        Some(format!(
+            "{}{}{}{}",
+            if negative { "-" } else { "" },
+            self.prefix,
+            new_digits,
+            self.suffix
+        ))
+    }
+}
+
+fn group_digits(digits: &str, group: usize) -> String {
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / group);
+    for (i, ch) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % group == 0 {
+            grouped.push('_');
+        }
+        grouped.push(ch);
+    }
+    grouped
+}
+
+/// ISO 8601 dates and times, `d` stands for a digit. The longest patterns come first.
+const DATE_TIME_PATTERNS: &[&str] = &[
+    "dddd-dd-ddTdd:dd:dd",
+    "dddd-dd-dd dd:dd:dd",
+    "dddd-dd-ddTdd:dd",
+    "dddd-dd-dd dd:dd",
+    "dddd-dd-dd",
+    "dd:dd:dd",
+    "dd:dd",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct DateTime {
+    pattern: &'static str,
+    date: Option<NaiveDate>,
+    time: Option<NaiveTime>,
+}
+
+impl DateTime {
+    fn parse(chars: &[char], start: usize) -> Option<Self> {
+        if start > 0 && chars[start - 1].is_ascii_digit() {
+            return None;
+        }
+
+        let pattern = DATE_TIME_PATTERNS.iter().find(|pattern| {
+            let end = start + pattern.len();
+            end <= chars.len()
+                && pattern.chars().zip(&chars[start..end]).all(|(p, ch)| {
+                    if p == 'd' {
+                        ch.is_ascii_digit()
+                    } else {
+                        p == *ch
+                    }
+                })
+                && !chars.get(end).map_or(false, char::is_ascii_digit)
+        })?;
+
+        let text: String = chars[start..start + pattern.len()].iter().collect();
+        let number = |range: std::ops::Range<usize>| text[range].parse::<u32>().ok();
+
+        let (date, time_start) = if pattern.starts_with("dddd") {
+            let date =
+                NaiveDate::from_ymd_opt(number(0..4)? as i32, number(5..7)?, number(8..10)?)?;
+            (Some(date), 11)
+        } else {
+            (None, 0)
+        };
+        let time = if pattern.len() > time_start {
+            let seconds = if pattern.len() >= time_start + 8 {
+                number(time_start + 6..time_start + 8)?
+            } else {
+                0
+            };
+            Some(NaiveTime::from_hms_opt(
+                number(time_start..time_start + 2)?,
+                number(time_start + 3..time_start + 5)?,
+                seconds,
+            )?)
+        } else {
+            None
+        };
+
+        Some(Self {
+            pattern,
+            date,
+            time,
+        })
+    }
+
+    /// The fields of the pattern with the offsets they end at.
+    fn fields(&self) -> Vec<(usize, Field)> {
+        let mut fields = Vec::new();
+        let mut time_start = 0;
+        if self.date.is_some() {
+            fields.extend_from_slice(&[(4, Field::Year), (7, Field::Month), (10, Field::Day)]);
+            time_start = 11;
+        }
+        if self.time.is_some() {
+            fields.extend_from_slice(&[
+                (time_start + 2, Field::Hour),
+                (time_start + 5, Field::Minute),
+            ]);
+            if self.pattern.len() == time_start + 8 {
+                fields.push((time_start + 8, Field::Second));
+            }
+        }
+        fields
+    }
+
+    /// Increments the field at `offset` within the literal.
+    fn increment(&self, offset: usize, amount: i64) -> Option<String> {
+        let fields = self.fields();
+        let field = fields.iter().find(|(end, _)| *end > offset)?.1;
+
+        let (date, time) = match field {
+            Field::Year | Field::Month => {
+                let months = if field == Field::Year {
+                    amount.checked_mul(12)?
+                } else {
+                    amount
+                };
+                (Some(add_months(self.date?, months)?), self.time)
+            }
+            Field::Day => (
+                Some(
+                    self.date?
+                        .checked_add_signed(seconds(amount, 24 * 60 * 60)?)?,
+                ),
+                self.time,
+            ),
+            Field::Hour | Field::Minute | Field::Second => {
+                let unit = match field {
+                    Field::Hour => 60 * 60,
+                    Field::Minute => 60,
+                    _ => 1,
+                };
+                let duration = seconds(amount, unit)?;
+                match self.date {
+                    Some(date) => {
+                        let date_time =
+                            NaiveDateTime::new(date, self.time?).checked_add_signed(duration)?;
+                        (Some(date_time.date()), Some(date_time.time()))
+                    }
+                    // times without a date wrap around midnight
+                    None => (None, Some(self.time?.overflowing_add_signed(duration).0)),
+                }
+            }
+        };
+
+        let mut text = String::with_capacity(self.pattern.len());
+        if let Some(date) = date {
+            text.push_str(&format!(
+                "{:04}-{:02}-{:02}",
+                date.year(),
+                date.month(),
+                date.day()
+            ));
+        }
+        if let Some(time) = time {
+            if date.is_some() {
+                text.push_str(&self.pattern[10..11]);
+            }
+            text.push_str(&format!("{:02}:{:02}", time.hour(), time.minute()));
+            if fields.last().map(|(_, field)| *field) == Some(Field::Second) {
+                text.push_str(&format!(":{:02}", time.second()));
+            }
+        }
+        Some(text)
+    }
+}
+
+fn seconds(amount: i64, unit: i64) -> Option<Duration> {
+    amount
+        .checked_mul(unit)
+        // the largest duration chrono can represent
+        .filter(|seconds| seconds.abs() <= i64::MAX / 1000)
+        .map(Duration::seconds)
+}
+
+/// Adds `months` to `date`, the day is clamped to the length of the resulting month.
+fn add_months(date: NaiveDate, months: i64) -> Option<NaiveDate> {
+    let month = i64::from(date.year()) * 12 + i64::from(date.month0()) + months;
+    let year = i32::try_from(month.div_euclid(12)).ok()?;
+    let month = month.rem_euclid(12) as u32 + 1;
+    (1..=date.day())
+        .rev()
+        .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Boolean {
+    word: &'static str,
+}
+
+/// The boolean words with their toggled counterparts.
+const BOOLEANS: &[(&str, &str)] = &[
+    ("true", "false"),
+    ("false", "true"),
+    ("True", "False"),
+    ("False", "True"),
+    ("TRUE", "FALSE"),
+    ("FALSE", "TRUE"),
+];
+
+impl Boolean {
+    fn parse(chars: &[char], start: usize) -> Option<Self> {
+        if start > 0 && is_word_char(chars[start - 1]) {
+            return None;
+        }
+        BOOLEANS
+            .iter()
+            .map(|(word, _)| *word)
+            .find(|word| {
+                let end = start + word.len();
+                end <= chars.len()
+                    && word.chars().eq(chars[start..end].iter().copied())
+                    && !chars.get(end).copied().map_or(false, is_word_char)
+            })
+            .map(|word| Self { word })
+    }
+
+    fn toggle(&self) -> String {
+        BOOLEANS
+            .iter()
+            .find(|(word, _)| *word == self.word)
+            .map(|(_, toggled)| toggled.to_string())
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Rope;
+
+    fn test(text: &str, cursor: usize, amount: i64) -> Option<String> {
+        let doc = Rope::from(text);
+        let (from, to, replacement) = increment(doc.slice(..), Range::point(cursor), amount)?;
+        let mut result = doc.slice(..from).to_string();
+        result.push_str(&replacement);
+        result.push_str(&doc.slice(to..).to_string());
+        Some(result)
+    }
+
+    #[test]
+    fn test_increment_numbers() {
+        let tests = [
+            ("x = 41;", 0, 1, "x = 42;"),
+            ("x = 41;", 5, -2, "x = 39;"),
+            ("x = -1;", 4, 3, "x = 2;"),
+            ("x = 1;", 4, -3, "x = -2;"),
+            ("a-1", 0, 1, "a-2"),
+            ("007", 0, 1, "008"),
+            ("1_000_000", 0, 1, "1_000_001"),
+            ("9_999", 0, 1, "10_000"),
+            ("255u8", 0, 1, "0u8"),
+            ("0u8", 0, -1, "255u8"),
+            ("127i8", 0, 1, "-128i8"),
+            ("-128i8", 0, -1, "127i8"),
+            ("-1i64", 0, 2, "1i64"),
+            ("4_294_967_295_usize", 0, 1, "4_294_967_296_usize"),
+            ("1_000_u32", 0, 1, "1_001_u32"),
+            ("1.5f64", 0, 1, "2.5f64"),
+            ("1.5f64", 2, 1, "1.6f64"),
+            ("0xff", 0, 1, "0x100"),
+            ("0x00FF", 3, 1, "0x0100"),
+            ("0xffff_ffff", 0, 1, "0x1_0000_0000"),
+            ("0xffu8", 0, 1, "0x00u8"),
+            ("0x00u8", 0, -1, "0xffu8"),
+            ("0x0", 0, -1, "0xffffffffffffffff"),
+            ("0o17", 0, 1, "0o20"),
+            ("0b0111", 0, 1, "0b1000"),
+            ("v1 2", 3, 1, "v1 3"),
+        ];
+
+        for (text, cursor, amount, expected) in tests {
+            assert_eq!(
+                test(text, cursor, amount).as_deref(),
+                Some(expected),
+                "{:?}",
+                text
+            );
+        }
+
+        assert_eq!(test("no numbers here", 0, 1), None);
+        // the search doesn't go past the line
+        assert_eq!(test("x\n1", 0, 1), None);
+        assert_eq!(test("1 x", 2, 1), None);
+    }
+
+    #[test]
+    fn test_increment_dates_and_times() {
+        let tests = [
+            ("2021-01-31", 9, 1, "2021-02-01"),
+            ("2021-01-31", 6, 1, "2021-02-28"),
+            ("2020-02-29", 0, 1, "2021-02-28"),
+            ("2021-03-01", 9, -1, "2021-02-28"),
+            ("on 2021-12-31", 0, 1, "on 2022-12-31"),
+            ("2021-12-31T23:59:59", 18, 1, "2022-01-01T00:00:00"),
+            ("2021-12-31 23:30", 12, 2, "2022-01-01 01:30"),
+            ("23:59", 4, 2, "00:01"),
+            ("10:30:00", 0, -11, "23:30:00"),
+        ];
+
+        for (text, cursor, amount, expected) in tests {
+            assert_eq!(
+                test(text, cursor, amount).as_deref(),
+                Some(expected),
+                "{:?}",
+                text
+            );
+        }
+
+        // not a valid date, so the year is a number
+        assert_eq!(test("2021-13-01", 0, 1).as_deref(), Some("2022-13-01"));
+    }
+
+    #[test]
+    fn test_toggle_booleans() {
+        assert_eq!(test("x = true;", 0, 1).as_deref(), Some("x = false;"));
+        assert_eq!(test("False", 2, -1).as_deref(), Some("True"));
+        assert_eq!(test("TRUE", 0, 1).as_deref(), Some("FALSE"));
+        assert_eq!(test("untrue", 0, 1), None);
+    }
+}
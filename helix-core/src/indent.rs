@@ -2,8 +2,8 @@ use crate::{
     chars::{char_is_line_ending, char_is_whitespace},
     find_first_non_whitespace_char,
     syntax::{IndentQuery, LanguageConfiguration, Syntax},
-    tree_sitter::Node,
-    Rope, RopeSlice,
+    tree_sitter::{Node, Tree},
+    Change, Rope, RopeSlice, Selection, Tendril, Transaction,
 };
 
 /// Enum representing indentation style.
@@ -190,9 +190,7 @@ fn indent_level_for_line(line: RopeSlice, tab_width: usize) -> usize {
 
 /// Find the highest syntax node at position.
 /// This is to identify the column where this node (e.g., an HTML closing tag) ends.
-fn get_highest_syntax_node_at_bytepos(syntax: &Syntax, pos: usize) -> Option<Node> {
-    let tree = syntax.tree();
-
+fn get_highest_syntax_node_at_bytepos(tree: &Tree, pos: usize) -> Option<Node> {
     // named_descendant
     let mut node = match tree.root_node().descendant_for_byte_range(pos, pos) {
         Some(node) => node,
@@ -295,22 +293,75 @@ pub fn suggested_indent_for_pos(
     pos: usize,
     new_line: bool,
 ) -> usize {
-    if let (Some(query), Some(syntax)) = (
-        language_config.and_then(|config| config.indent_query()),
-        syntax,
-    ) {
-        let byte_start = text.char_to_byte(pos);
-        let node = get_highest_syntax_node_at_bytepos(syntax, byte_start);
-
-        // let config = load indentation query config from Syntax(should contain language_config)
-
-        // TODO: special case for comments
-        // TODO: if preserve_leading_whitespace
-        calculate_indentation(query, node, new_line)
-    } else {
+    let syntax = match syntax {
+        Some(syntax) => syntax,
         // TODO: heuristics for non-tree sitter grammars
-        0
+        None => return 0,
+    };
+
+    let mut byte_pos = text.char_to_byte(pos);
+    let mut new_line = new_line;
+    let mut indent = 0;
+
+    // Indent according to the language at the position. An injected language is indented
+    // relative to where it's injected in the parent language.
+    for layer in syntax.layers_for_byte_range(byte_pos, byte_pos) {
+        let query = layer
+            .language_config()
+            .map(AsRef::as_ref)
+            .or(language_config)
+            .and_then(|config| config.indent_query());
+
+        if let Some(query) = query {
+            let node = get_highest_syntax_node_at_bytepos(layer.tree(), byte_pos);
+
+            // TODO: special case for comments
+            // TODO: if preserve_leading_whitespace
+            indent += calculate_indentation(query, node, new_line);
+        }
+
+        if let Some(range) = layer.ranges.first() {
+            byte_pos = range.start_byte;
+        }
+        new_line = false;
     }
+
+    indent
+}
+
+/// Recomputes the indentation of every line covered by the selection from the indent queries,
+/// using `indent_unit` for each level. Blank lines are left untouched.
+#[must_use]
+pub fn reindent(
+    doc: &Rope,
+    selection: &Selection,
+    language_config: Option<&LanguageConfiguration>,
+    syntax: Option<&Syntax>,
+    indent_unit: &str,
+) -> Transaction {
+    let text = doc.slice(..);
+
+    let mut changes: Vec<Change> = Vec::new();
+    let mut min_next_line = 0;
+    for range in selection {
+        let (start, end) = range.line_range(text);
+        for line in start.max(min_next_line)..=end {
+            let line_start = text.line_to_char(line);
+            let first_char = match find_first_non_whitespace_char(text.line(line)) {
+                Some(pos) => line_start + pos,
+                None => continue,
+            };
+
+            let level = suggested_indent_for_pos(language_config, syntax, text, first_char, false);
+            let indent = indent_unit.repeat(level);
+            if text.slice(line_start..first_char) != indent.as_str() {
+                changes.push((line_start, first_char, Some(Tendril::from(indent))));
+            }
+        }
+        min_next_line = end + 1;
+    }
+
+    Transaction::change(doc, changes.into_iter())
 }
 
 #[cfg(test)]
@@ -421,7 +472,8 @@ where
             Configuration, IndentationConfiguration, LanguageConfiguration, Loader,
         };
         use once_cell::sync::OnceCell;
-        let loader = Loader::new(Configuration {
+        use std::sync::Arc;
+        let loader = Arc::new(Loader::new(Configuration {
             language: vec![LanguageConfiguration {
                 scope: "source.rust".to_string(),
                 file_types: vec!["rs".to_string()],
@@ -430,16 +482,24 @@ where
                 config: None,
                 //
                 roots: vec![],
+                shebangs: Vec::new(),
                 comment_token: None,
+                block_comment_tokens: None,
                 auto_format: false,
+                soft_wrap: false,
+                auto_pairs: None,
+                surround_pairs: None,
+                injection_regex: None,
+                first_line_regex: None,
                 language_server: None,
                 indent: Some(IndentationConfiguration {
                     tab_width: 4,
                     unit: String::from("    "),
                 }),
                 indent_query: OnceCell::new(),
+                textobject_query: OnceCell::new(),
             }],
-        });
+        }));
 
         // set runtime path so we can find the queries
         let mut runtime = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -448,7 +508,7 @@ where
 
         let language_config = loader.language_config_for_scope("source.rust").unwrap();
         let highlight_config = language_config.highlight_config(&[]).unwrap();
-        let syntax = Syntax::new(&doc, highlight_config.clone());
+        let syntax = Syntax::new(&doc, highlight_config.clone(), loader.clone());
         let text = doc.slice(..);
         let tab_width = 4;
 
@@ -463,5 +523,26 @@ where
                 line
             );
         }
+
+        // reindenting the document with its indentation stripped restores it
+        let mut stripped = Rope::from(
+            doc.lines()
+                .map(|line| {
+                    line.to_string()
+                        .trim_start_matches(&[' ', '\t'][..])
+                        .to_string()
+                })
+                .collect::<String>(),
+        );
+        let syntax = Syntax::new(&stripped, highlight_config, loader);
+        let transaction = reindent(
+            &stripped,
+            &Selection::single(0, stripped.len_chars()),
+            Some(&language_config),
+            Some(&syntax),
+            "    ",
+        );
+        transaction.apply(&mut stripped);
+        assert_eq!(stripped, doc);
     }
 }
@@ -5,6 +5,7 @@ pub mod diagnostic;
 pub mod diff;
 pub mod graphemes;
 pub mod history;
+pub mod increment;
 pub mod indent;
 pub mod line_ending;
 pub mod macros;
@@ -15,11 +16,14 @@ mod position;
 pub mod register;
 pub mod search;
 pub mod selection;
+pub mod snippets;
 mod state;
+pub mod substitute;
 pub mod surround;
 pub mod syntax;
 pub mod textobject;
 mod transaction;
+pub mod wrap;
 
 pub mod unicode {
     pub use unicode_general_category as category;
@@ -109,7 +113,7 @@ pub use tendril::StrTendril as Tendril;
 pub use {regex, tree_sitter};
 
 pub use graphemes::RopeGraphemes;
-pub use position::{coords_at_pos, pos_at_coords, Position};
+pub use position::{coords_at_pos, pos_at_coords, visual_coords_at_pos, Position};
 pub use selection::{Range, Selection};
 pub use smallvec::SmallVec;
 pub use syntax::Syntax;
@@ -1,8 +1,22 @@
-use crate::{Rope, Syntax};
+use crate::{chars::char_is_line_ending, Rope, RopeSlice, Syntax};
 
 const PAIRS: &[(char, char)] = &[('(', ')'), ('{', '}'), ('[', ']'), ('<', '>')];
 // limit matching pairs to only ( ) { } [ ] < >
 
+// limit the distance the plaintext fallback scans for a matching bracket
+const MAX_PLAINTEXT_SCAN: usize = 10_000;
+
+/// Find the bracket matching the one at `pos`. The syntax tree is used when there is one without
+/// errors, the text is scanned when there is none or it has no matching bracket node at `pos`,
+/// like inside an injected language.
+#[must_use]
+pub fn find_matching_bracket(syntax: Option<&Syntax>, doc: &Rope, pos: usize) -> Option<usize> {
+    syntax
+        .filter(|syntax| !syntax.tree().root_node().has_error())
+        .and_then(|syntax| find(syntax, doc, pos))
+        .or_else(|| find_matching_bracket_plaintext(doc.slice(..), pos, true))
+}
+
 #[must_use]
 pub fn find(syntax: &Syntax, doc: &Rope, pos: usize) -> Option<usize> {
     let tree = syntax.tree();
@@ -46,3 +60,135 @@ pub fn find(syntax: &Syntax, doc: &Rope, pos: usize) -> Option<usize> {
 
     None
 }
+
+/// Find the bracket matching the one at `pos` by scanning the text, skipping nested pairs. With
+/// `skip_quotes`, brackets inside a double quoted string only match brackets in the same kind of
+/// place: quoted ones with quoted ones, unquoted ones with unquoted ones.
+#[must_use]
+pub fn find_matching_bracket_plaintext(
+    text: RopeSlice,
+    pos: usize,
+    skip_quotes: bool,
+) -> Option<usize> {
+    let bracket = text.get_char(pos)?;
+    let (pair, forward) = PAIRS.iter().find_map(|&(open, close)| {
+        if bracket == open {
+            Some(((open, close), true))
+        } else if bracket == close {
+            Some(((open, close), false))
+        } else {
+            None
+        }
+    })?;
+
+    // the brackets of the pair in scanning order, starting with the one at `pos`
+    let mut brackets: Box<dyn Iterator<Item = (usize, char, bool)>> = if forward {
+        let end = (pos + MAX_PLAINTEXT_SCAN + 1).min(text.len_chars());
+        Box::new(brackets(text, pos, end, pair, skip_quotes))
+    } else {
+        let start = pos.saturating_sub(MAX_PLAINTEXT_SCAN);
+        let brackets: Vec<_> = brackets(text, start, pos + 1, pair, skip_quotes).collect();
+        Box::new(brackets.into_iter().rev())
+    };
+    let (_, _, quoted) = brackets.next()?;
+
+    let mut depth = 0;
+    for (cur, ch, cur_quoted) in brackets {
+        if cur_quoted != quoted {
+            continue;
+        }
+
+        if ch == bracket {
+            depth += 1;
+        } else if depth == 0 {
+            return Some(cur);
+        } else {
+            depth -= 1;
+        }
+    }
+
+    None
+}
+
+/// The brackets of `pair` from `start` to `end`, along with whether they're inside a double
+/// quoted string when `skip_quotes` is set. Strings don't span lines, so the quotes are tracked
+/// from the start of the line, or from `MAX_PLAINTEXT_SCAN` chars before `start` in longer lines.
+fn brackets(
+    text: RopeSlice,
+    start: usize,
+    end: usize,
+    pair: (char, char),
+    skip_quotes: bool,
+) -> impl Iterator<Item = (usize, char, bool)> + '_ {
+    let scan_start = if skip_quotes {
+        let line_start = text.line_to_char(text.char_to_line(start));
+        line_start.max(start.saturating_sub(MAX_PLAINTEXT_SCAN))
+    } else {
+        start
+    };
+
+    // whether the current char is quoted, and whether it's escaped
+    let mut state = (false, false);
+    (scan_start..end)
+        .zip(text.slice(scan_start..end).chars())
+        .map(move |(pos, ch)| {
+            let (quoted, escaped) = state;
+            state = if !skip_quotes || char_is_line_ending(ch) {
+                (false, false)
+            } else {
+                (quoted != (ch == '"' && !escaped), ch == '\\' && !escaped)
+            };
+            (pos, ch, quoted)
+        })
+        .filter(move |&(pos, ch, _)| pos >= start && (ch == pair.0 || ch == pair.1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_matching_bracket_plaintext() {
+        let doc = Rope::from("(a [b] {c (d)})\n");
+        let text = doc.slice(..);
+
+        assert_eq!(find_matching_bracket_plaintext(text, 0, true), Some(14));
+        assert_eq!(find_matching_bracket_plaintext(text, 14, true), Some(0));
+        assert_eq!(find_matching_bracket_plaintext(text, 3, true), Some(5));
+        assert_eq!(find_matching_bracket_plaintext(text, 13, true), Some(7));
+        assert_eq!(find_matching_bracket_plaintext(text, 10, true), Some(12));
+        // not on a bracket
+        assert_eq!(find_matching_bracket_plaintext(text, 1, true), None);
+
+        // unbalanced
+        let doc = Rope::from("((a)\n");
+        assert_eq!(
+            find_matching_bracket_plaintext(doc.slice(..), 0, true),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_matching_bracket_plaintext_quotes() {
+        let doc = Rope::from("(\")\" \"(\\\"\")\n");
+        let text = doc.slice(..);
+
+        // the brackets in the strings are skipped
+        assert_eq!(find_matching_bracket_plaintext(text, 0, true), Some(10));
+        assert_eq!(find_matching_bracket_plaintext(text, 10, true), Some(0));
+        // quoted brackets only match quoted ones
+        assert_eq!(find_matching_bracket_plaintext(text, 2, true), None);
+        assert_eq!(find_matching_bracket_plaintext(text, 6, true), None);
+
+        assert_eq!(find_matching_bracket_plaintext(text, 0, false), Some(2));
+        assert_eq!(find_matching_bracket_plaintext(text, 10, false), Some(6));
+
+        // an unterminated string ends with the line
+        let doc = Rope::from("(\"\n)\"(\n)\n");
+        let text = doc.slice(..);
+        assert_eq!(find_matching_bracket_plaintext(text, 0, true), Some(3));
+        assert_eq!(find_matching_bracket_plaintext(text, 3, true), Some(0));
+        assert_eq!(find_matching_bracket_plaintext(text, 5, true), None);
+        assert_eq!(find_matching_bracket_plaintext(text, 7, true), None);
+    }
+}
@@ -1,6 +1,7 @@
 use std::iter;
 
 use ropey::iter::Chars;
+use tree_sitter::{Node, QueryCursor};
 
 use crate::{
     chars::{categorize_char, char_is_line_ending, CharCategory},
@@ -9,7 +10,10 @@ use crate::{
         next_grapheme_boundary, nth_next_grapheme_boundary, nth_prev_grapheme_boundary,
         prev_grapheme_boundary,
     },
-    pos_at_coords, Position, Range, RopeSlice,
+    pos_at_coords,
+    syntax::LanguageConfiguration,
+    wrap::WrappedLine,
+    Position, Range, RopeSlice,
 };
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -74,6 +78,53 @@ pub fn move_vertically(
     new_range
 }
 
+/// Like [`move_vertically`], but moves by the rows of soft wrapped lines, `width` columns wide.
+pub fn move_vertically_visual(
+    slice: RopeSlice,
+    range: Range,
+    dir: Direction,
+    count: usize,
+    behaviour: Movement,
+    width: usize,
+    tab_width: usize,
+) -> Range {
+    let pos = range.cursor(slice);
+    let mut line = slice.char_to_line(pos);
+    let mut wrapped = WrappedLine::new(slice.line(line), width, tab_width);
+
+    // Compute the current position's visual row within its line and column.
+    let Position { mut row, col } = wrapped.visual_coords(pos - slice.line_to_char(line));
+    let horiz = range.horiz.unwrap_or(col as u32);
+
+    for _ in 0..count {
+        match dir {
+            Direction::Forward if row + 1 < wrapped.rows() => row += 1,
+            Direction::Forward if line + 1 < slice.len_lines() => {
+                line += 1;
+                wrapped = WrappedLine::new(slice.line(line), width, tab_width);
+                row = 0;
+            }
+            Direction::Backward if row > 0 => row -= 1,
+            Direction::Backward if line > 0 => {
+                line -= 1;
+                wrapped = WrappedLine::new(slice.line(line), width, tab_width);
+                row = wrapped.rows() - 1;
+            }
+            _ => break,
+        }
+    }
+
+    // Special-case to avoid moving to the end of the last non-empty line.
+    if behaviour == Movement::Extend && slice.line(line).len_chars() == 0 {
+        return range;
+    }
+
+    let new_pos = slice.line_to_char(line) + wrapped.offset_at(row, horiz as usize);
+    let mut new_range = range.put_cursor(slice, new_pos, behaviour == Movement::Extend);
+    new_range.horiz = Some(horiz);
+    new_range
+}
+
 pub fn move_next_word_start(slice: RopeSlice, range: Range, count: usize) -> Range {
     word_move(slice, range, count, WordMotionTarget::NextWordStart)
 }
@@ -102,12 +153,25 @@ pub fn move_prev_word_end(slice: RopeSlice, range: Range, count: usize) -> Range
     word_move(slice, range, count, WordMotionTarget::PrevWordEnd)
 }
 
+pub fn move_next_sub_word_start(slice: RopeSlice, range: Range, count: usize) -> Range {
+    word_move(slice, range, count, WordMotionTarget::NextSubWordStart)
+}
+
+pub fn move_next_sub_word_end(slice: RopeSlice, range: Range, count: usize) -> Range {
+    word_move(slice, range, count, WordMotionTarget::NextSubWordEnd)
+}
+
+pub fn move_prev_sub_word_start(slice: RopeSlice, range: Range, count: usize) -> Range {
+    word_move(slice, range, count, WordMotionTarget::PrevSubWordStart)
+}
+
 fn word_move(slice: RopeSlice, range: Range, count: usize, target: WordMotionTarget) -> Range {
     let is_prev = matches!(
         target,
         WordMotionTarget::PrevWordStart
             | WordMotionTarget::PrevLongWordStart
             | WordMotionTarget::PrevWordEnd
+            | WordMotionTarget::PrevSubWordStart
     );
 
     // Special-case early-out.
@@ -141,6 +205,55 @@ fn word_move(slice: RopeSlice, range: Range, count: usize, target: WordMotionTar
     })
 }
 
+/// Move to the start of the `count`-th next or previous textobject captured as
+/// `@{object_name}.around` by the language's `textobjects.scm` query. The whole object is
+/// selected, with the cursor at its start when moving backwards.
+pub fn goto_treesitter_object(
+    slice: RopeSlice,
+    range: Range,
+    object_name: &str,
+    dir: Direction,
+    slice_tree: Node,
+    lang_config: &LanguageConfiguration,
+    count: usize,
+) -> Range {
+    let get_range = move || -> Option<Range> {
+        let byte_pos = slice.char_to_byte(range.cursor(slice));
+
+        let capture_name = format!("{}.around", object_name);
+        let mut cursor = QueryCursor::new();
+        let nodes = lang_config.textobject_query()?.capture_nodes(
+            &capture_name,
+            slice_tree,
+            slice,
+            &mut cursor,
+        )?;
+
+        let mut nodes: Vec<_> = match dir {
+            Direction::Forward => {
+                let mut nodes: Vec<_> = nodes.filter(|n| n.start_byte() > byte_pos).collect();
+                nodes.sort_by_key(|n| n.start_byte());
+                nodes
+            }
+            Direction::Backward => {
+                let mut nodes: Vec<_> = nodes.filter(|n| n.start_byte() < byte_pos).collect();
+                nodes.sort_by_key(|n| std::cmp::Reverse(n.start_byte()));
+                nodes
+            }
+        };
+        nodes.dedup_by_key(|n| n.start_byte());
+        let node = nodes.into_iter().take(count).last()?;
+
+        let start = slice.byte_to_char(node.start_byte());
+        let end = slice.byte_to_char(node.end_byte());
+        Some(match dir {
+            Direction::Forward => Range::new(start, end),
+            Direction::Backward => Range::new(end, start),
+        })
+    };
+    get_range().unwrap_or(range)
+}
+
 // ---- util ------------
 
 #[inline]
@@ -188,6 +301,11 @@ pub enum WordMotionTarget {
     NextLongWordStart,
     NextLongWordEnd,
     PrevLongWordStart,
+    // A "sub word" is a part of a word, delimited by camelCase humps,
+    // snake_case underscores and changes between letters and digits.
+    NextSubWordStart,
+    NextSubWordEnd,
+    PrevSubWordStart,
 }
 
 pub trait CharHelpers {
@@ -204,6 +322,7 @@ impl CharHelpers for Chars<'_> {
             WordMotionTarget::PrevWordStart
                 | WordMotionTarget::PrevLongWordStart
                 | WordMotionTarget::PrevWordEnd
+                | WordMotionTarget::PrevSubWordStart
         );
 
         // Reverse the iterator if needed for the motion direction.
@@ -280,6 +399,18 @@ fn is_long_word_boundary(a: char, b: char) -> bool {
     }
 }
 
+/// Whether there's a sub word boundary between `a` and the `b` following it in the text.
+pub(crate) fn is_sub_word_boundary(a: char, b: char) -> bool {
+    is_word_boundary(a, b)
+        || (a == '_') != (b == '_')
+        || (a.is_lowercase() && b.is_uppercase())
+        || (a.is_alphanumeric() && b.is_alphanumeric() && a.is_numeric() != b.is_numeric())
+}
+
+fn is_sub_word_separator(ch: char) -> bool {
+    ch.is_whitespace() || ch == '_'
+}
+
 fn reached_target(target: WordMotionTarget, prev_ch: char, next_ch: char) -> bool {
     match target {
         WordMotionTarget::NextWordStart | WordMotionTarget::PrevWordEnd => {
@@ -298,6 +429,19 @@ fn reached_target(target: WordMotionTarget, prev_ch: char, next_ch: char) -> boo
             is_long_word_boundary(prev_ch, next_ch)
                 && (!prev_ch.is_whitespace() || char_is_line_ending(next_ch))
         }
+        WordMotionTarget::NextSubWordStart => {
+            is_sub_word_boundary(prev_ch, next_ch)
+                && (char_is_line_ending(next_ch) || !is_sub_word_separator(next_ch))
+        }
+        WordMotionTarget::NextSubWordEnd => {
+            is_sub_word_boundary(prev_ch, next_ch)
+                && (!is_sub_word_separator(prev_ch) || char_is_line_ending(next_ch))
+        }
+        // moving backwards, `next_ch` comes before `prev_ch` in the text
+        WordMotionTarget::PrevSubWordStart => {
+            is_sub_word_boundary(next_ch, prev_ch)
+                && (!is_sub_word_separator(prev_ch) || char_is_line_ending(next_ch))
+        }
     }
 }
 
@@ -339,6 +483,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_vertical_move_visual() {
+        // wrapped into "one two " | "↪ three" with a width of 8
+        let text = Rope::from("one two three\nab\n");
+        let slice = text.slice(..);
+        let move_visual =
+            |range, dir| move_vertically_visual(slice, range, dir, 1, Movement::Move, 8, 4);
+
+        let range = move_visual(Range::point(5), Direction::Forward);
+        assert_eq!(range.head, 11);
+        assert_eq!(range.horiz, Some(5));
+        // the column sticks across shorter rows
+        let range = move_visual(range, Direction::Forward);
+        assert_eq!(range.head, 16);
+        let range = move_visual(range, Direction::Backward);
+        assert_eq!(range.head, 11);
+        let range = move_visual(range, Direction::Backward);
+        assert_eq!(range.head, 5);
+        let range = move_visual(range, Direction::Backward);
+        assert_eq!(range.head, 5);
+    }
+
     #[test]
     fn horizontal_moves_through_single_line_text() {
         let text = Rope::from(SINGLE_LINE_SAMPLE);
@@ -1123,4 +1289,49 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_behaviour_when_moving_through_sub_words() {
+        let sample = Rope::from("fooBar_baz42 qux");
+        let text = sample.slice(..);
+
+        // (count, begin, expected end)
+        let next_starts = [
+            (1, Range::new(0, 0), Range::new(0, 3)),
+            (1, Range::new(0, 3), Range::new(3, 7)),
+            (1, Range::new(3, 7), Range::new(7, 10)),
+            (1, Range::new(7, 10), Range::new(10, 13)),
+            (3, Range::new(0, 0), Range::new(7, 10)),
+        ];
+        for (count, begin, expected_end) in next_starts {
+            let range = move_next_sub_word_start(text, begin, count);
+            assert_eq!(range, expected_end, "Case failed: {:?}", begin);
+        }
+
+        let next_ends = [
+            (1, Range::new(0, 0), Range::new(0, 3)),
+            (1, Range::new(0, 3), Range::new(3, 6)),
+            (1, Range::new(3, 6), Range::new(6, 10)),
+        ];
+        for (count, begin, expected_end) in next_ends {
+            let range = move_next_sub_word_end(text, begin, count);
+            assert_eq!(range, expected_end, "Case failed: {:?}", begin);
+        }
+
+        let prev_starts = [
+            (1, Range::new(9, 9), Range::new(10, 7)),
+            (1, Range::new(10, 7), Range::new(7, 3)),
+            (1, Range::new(7, 3), Range::new(3, 0)),
+        ];
+        for (count, begin, expected_end) in prev_starts {
+            let range = move_prev_sub_word_start(text, begin, count);
+            assert_eq!(range, expected_end, "Case failed: {:?}", begin);
+        }
+
+        assert!(is_sub_word_boundary('o', 'B'));
+        assert!(is_sub_word_boundary('z', '4'));
+        assert!(is_sub_word_boundary('_', 'b'));
+        assert!(!is_sub_word_boundary('B', 'a'));
+        assert!(!is_sub_word_boundary('A', 'B'));
+    }
 }
@@ -1,26 +1,70 @@
 use crate::{Range, RopeSlice, Selection, Syntax};
+use tree_sitter::Node;
 
-// TODO: to contract_selection we'd need to store the previous ranges before expand.
-// Maybe just contract to the first child node?
 pub fn expand_selection(syntax: &Syntax, text: RopeSlice, selection: &Selection) -> Selection {
+    select_node_impl(syntax, text, selection, |node| {
+        // skip the parents spanning the same text, the selection wouldn't change
+        let mut parent = node.parent()?;
+        while parent.byte_range() == node.byte_range() {
+            parent = parent.parent()?;
+        }
+        Some(parent)
+    })
+}
+
+/// Select the first child of the syntax node of each range. Restoring the selection from before
+/// an expansion is up to the caller.
+pub fn select_first_child(syntax: &Syntax, text: RopeSlice, selection: &Selection) -> Selection {
+    select_node_impl(syntax, text, selection, |node| node.named_child(0))
+}
+
+pub fn select_next_sibling(syntax: &Syntax, text: RopeSlice, selection: &Selection) -> Selection {
+    select_node_impl(syntax, text, selection, |mut node| {
+        // the last node of a block moves on to the node following the block
+        while node.next_named_sibling().is_none() {
+            node = node.parent()?;
+        }
+        node.next_named_sibling()
+    })
+}
+
+pub fn select_prev_sibling(syntax: &Syntax, text: RopeSlice, selection: &Selection) -> Selection {
+    select_node_impl(syntax, text, selection, |mut node| {
+        while node.prev_named_sibling().is_none() {
+            node = node.parent()?;
+        }
+        node.prev_named_sibling()
+    })
+}
+
+/// Select the node `select_fn` picks relative to the smallest syntax node covering each range,
+/// keeping the direction of the range. Ranges are kept as they are when there's no such node.
+fn select_node_impl<F>(
+    syntax: &Syntax,
+    text: RopeSlice,
+    selection: &Selection,
+    select_fn: F,
+) -> Selection
+where
+    F: Fn(Node) -> Option<Node>,
+{
     let tree = syntax.tree();
 
     selection.clone().transform(|range| {
         let from = text.char_to_byte(range.from());
         let to = text.char_to_byte(range.to());
 
-        // find parent of a descendant that matches the range
-        let parent = match tree
+        let node = match tree
             .root_node()
             .descendant_for_byte_range(from, to)
-            .and_then(|node| node.parent())
+            .and_then(&select_fn)
         {
-            Some(parent) => parent,
+            Some(node) => node,
             None => return range,
         };
 
-        let from = text.byte_to_char(parent.start_byte());
-        let to = text.byte_to_char(parent.end_byte());
+        let from = text.byte_to_char(node.start_byte());
+        let to = text.byte_to_char(node.end_byte());
 
         if range.head < range.anchor {
             Range::new(to, from)
@@ -29,3 +73,84 @@ pub fn expand_selection(syntax: &Syntax, text: RopeSlice, selection: &Selection)
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::{Configuration, Loader};
+    use crate::Rope;
+    use std::sync::Arc;
+
+    fn rust_syntax(doc: &Rope) -> Syntax {
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "rust"
+            scope = "source.rust"
+            file-types = ["rs"]
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let loader = Arc::new(Loader::new(config));
+
+        // set runtime path so we can find the queries
+        let mut runtime = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        runtime.push("../runtime");
+        std::env::set_var("HELIX_RUNTIME", runtime.to_str().unwrap());
+
+        let language_config = loader.language_config_for_scope("source.rust").unwrap();
+        let highlight_config = language_config.highlight_config(&[]).unwrap();
+        Syntax::new(doc, highlight_config, loader)
+    }
+
+    #[test]
+    fn test_select_nodes() {
+        let doc = Rope::from("fn main() {\n    foo(a, bar(b), c);\n}\n");
+        let text = doc.slice(..);
+        let syntax = rust_syntax(&doc);
+
+        let arguments = Selection::single(19, 33);
+        let call = Selection::single(23, 29);
+
+        let expanded = expand_selection(&syntax, text, &Selection::single(23, 26));
+        assert_eq!(expanded, call);
+        let expanded = expand_selection(&syntax, text, &expanded);
+        assert_eq!(expanded, arguments);
+
+        assert_eq!(
+            select_first_child(&syntax, text, &arguments),
+            Selection::single(20, 21)
+        );
+
+        let bar = Selection::single(23, 26);
+        assert_eq!(
+            select_next_sibling(&syntax, text, &call),
+            Selection::single(31, 32)
+        );
+        assert_eq!(
+            select_prev_sibling(&syntax, text, &call),
+            Selection::single(20, 21)
+        );
+        // `bar` is followed by its arguments
+        assert_eq!(
+            select_next_sibling(&syntax, text, &bar),
+            Selection::single(26, 29)
+        );
+        // there's no node before `fn main`, the selection stays
+        let function = Selection::single(0, 36);
+        assert_eq!(select_prev_sibling(&syntax, text, &function), function);
+
+        // multiple ranges and their directions
+        let selection = Selection::new(
+            smallvec::smallvec![Range::new(7, 3), Range::new(20, 21), Range::new(31, 32)],
+            2,
+        );
+        let result = select_next_sibling(&syntax, text, &selection);
+        assert_eq!(
+            result.ranges(),
+            // `c` is the last node of the function
+            &[Range::new(9, 7), Range::new(23, 29), Range::new(31, 32)]
+        );
+    }
+}
@@ -1,6 +1,8 @@
+use std::borrow::Cow;
+
 use crate::{
     chars::char_is_line_ending,
-    graphemes::{ensure_grapheme_boundary_prev, RopeGraphemes},
+    graphemes::{ensure_grapheme_boundary_prev, grapheme_width, RopeGraphemes},
     line_ending::line_end_char_index,
     RopeSlice,
 };
@@ -69,6 +71,28 @@ pub fn coords_at_pos(text: RopeSlice, pos: usize) -> Position {
     Position::new(line, col)
 }
 
+/// Convert a character index to (line, column) coordinates visually.
+///
+/// Takes the width of the graphemes into account, and expands tabs to `tab_width` columns, like
+/// they are rendered.
+pub fn visual_coords_at_pos(text: RopeSlice, pos: usize, tab_width: usize) -> Position {
+    let line = text.char_to_line(pos);
+
+    let line_start = text.line_to_char(line);
+    let pos = ensure_grapheme_boundary_prev(text, pos);
+    let col = RopeGraphemes::new(text.slice(line_start..pos))
+        .map(|grapheme| {
+            if grapheme == "\t" {
+                tab_width
+            } else {
+                grapheme_width(&Cow::from(grapheme))
+            }
+        })
+        .sum();
+
+    Position::new(line, col)
+}
+
 /// Convert (line, column) coordinates to a character index.
 ///
 /// If the `line` coordinate is beyond the end of the file, the EOF
@@ -169,6 +193,19 @@ mod test {
         assert_eq!(coords_at_pos(slice, 2), (0, 2).into());
     }
 
+    #[test]
+    fn test_visual_coords_at_pos() {
+        let text = Rope::from("\tab\n今日は a̐é\n");
+        let slice = text.slice(..);
+        assert_eq!(visual_coords_at_pos(slice, 0, 4), (0, 0).into());
+        assert_eq!(visual_coords_at_pos(slice, 1, 4), (0, 4).into()); // position on a
+        assert_eq!(visual_coords_at_pos(slice, 2, 8), (0, 9).into()); // position on b
+        assert_eq!(visual_coords_at_pos(slice, 4, 4), (1, 0).into());
+        assert_eq!(visual_coords_at_pos(slice, 6, 4), (1, 4).into()); // wide characters
+        assert_eq!(visual_coords_at_pos(slice, 8, 4), (1, 7).into()); // position on a̐
+        assert_eq!(visual_coords_at_pos(slice, 10, 4), (1, 8).into()); // position on é
+    }
+
     #[test]
     fn test_pos_at_coords() {
         let text = Rope::from("ḧëḷḷö\nẅöṛḷḋ");
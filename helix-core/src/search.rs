@@ -1,4 +1,10 @@
-use crate::RopeSlice;
+use crate::{regex::Regex, RopeSlice};
+use regex_syntax::{
+    hir::{Anchor, Class, Hir, HirKind, Literal},
+    Parser,
+};
+use ropey::iter::Chunks;
+use std::borrow::Cow;
 
 pub fn find_nth_next(text: RopeSlice, ch: char, mut pos: usize, n: usize) -> Option<usize> {
     if pos >= text.len_chars() || n == 0 {
@@ -43,3 +49,598 @@ pub fn find_nth_prev(text: RopeSlice, ch: char, mut pos: usize, n: usize) -> Opt
 
     Some(pos)
 }
+
+/// A match of a regex in a [`RopeSlice`], as byte offsets into the slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    start: usize,
+    end: usize,
+}
+
+impl Match {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+}
+
+/// Returns an iterator over the successive non-overlapping matches of `regex` in `text`.
+///
+/// Unless the pattern can match a line break or anchors to the start or end of the text, the text
+/// is searched a few lines at a time, directly on the rope chunks; only lines that straddle two
+/// chunks get copied. Otherwise the whole text is searched at once. Only the flags within the
+/// pattern are taken into account, like `(?m)` for `^` and `$` to match at line boundaries, those
+/// set with a [`RegexBuilder`](crate::regex::RegexBuilder) can't be seen here.
+pub fn find_iter<'r, 't>(regex: &'r Regex, text: RopeSlice<'t>) -> Matches<'r, 't> {
+    find_iter_at(regex, text, 0)
+}
+
+/// Like [`find_iter`], but starts searching at byte `start`. Just like [`Regex::find_at`], the
+/// text before `start` is still taken into account for anchors and word boundaries.
+pub fn find_iter_at<'r, 't>(
+    regex: &'r Regex,
+    text: RopeSlice<'t>,
+    start: usize,
+) -> Matches<'r, 't> {
+    let windows = Windows::new(regex, text, start);
+    let pos = start - windows.offset();
+    Matches {
+        regex,
+        windows,
+        len: text.len_bytes(),
+        window: None,
+        pos,
+        last_match: None,
+    }
+}
+
+/// Returns an iterator over the matches of `regex` in `text` that start before byte `end`, from
+/// the last one to the first. These are the same matches [`find_iter`] returns.
+pub fn rfind_iter_at<'r, 't>(
+    regex: &'r Regex,
+    text: RopeSlice<'t>,
+    end: usize,
+) -> MatchesRev<'r, 't> {
+    MatchesRev {
+        regex,
+        windows: WindowsRev::new(regex, text, end),
+        len: text.len_bytes(),
+        end,
+        matches: Vec::new(),
+    }
+}
+
+/// Finds the first match starting at or after byte `pos`, wrapping around to the start of the
+/// text if there is none.
+pub fn find_next(regex: &Regex, text: RopeSlice, pos: usize) -> Option<Match> {
+    find_iter_at(regex, text, pos)
+        .next()
+        .or_else(|| find_iter(regex, text).next())
+}
+
+/// Finds the last match starting before byte `pos`, wrapping around to the end of the text if
+/// there is none.
+pub fn find_prev(regex: &Regex, text: RopeSlice, pos: usize) -> Option<Match> {
+    rfind_iter_at(regex, text, pos)
+        .next()
+        .or_else(|| rfind_iter_at(regex, text, text.len_bytes()).next())
+}
+
+/// Returns true if `regex` matches anywhere in `text`.
+pub fn is_match(regex: &Regex, text: RopeSlice) -> bool {
+    find_iter(regex, text).next().is_some()
+}
+
+/// How the text gets split up to search it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Split {
+    /// A few lines at a time, no match of the pattern can contain a line break.
+    Lines,
+    /// All of the text at once, the pattern can match a line break or depends on the start or
+    /// end of the text.
+    Whole,
+}
+
+fn split(hir: &Hir) -> Split {
+    match hir.kind() {
+        HirKind::Empty | HirKind::WordBoundary(_) => Split::Lines,
+        HirKind::Literal(Literal::Unicode('\n')) | HirKind::Literal(Literal::Byte(b'\n')) => {
+            Split::Whole
+        }
+        HirKind::Literal(_) => Split::Lines,
+        HirKind::Class(Class::Unicode(class)) => {
+            if class
+                .iter()
+                .any(|range| range.start() <= '\n' && '\n' <= range.end())
+            {
+                Split::Whole
+            } else {
+                Split::Lines
+            }
+        }
+        HirKind::Class(Class::Bytes(class)) => {
+            if class
+                .iter()
+                .any(|range| range.start() <= b'\n' && b'\n' <= range.end())
+            {
+                Split::Whole
+            } else {
+                Split::Lines
+            }
+        }
+        HirKind::Anchor(Anchor::StartLine) | HirKind::Anchor(Anchor::EndLine) => Split::Lines,
+        HirKind::Anchor(_) => Split::Whole,
+        HirKind::Repetition(repetition) => split(&repetition.hir),
+        HirKind::Group(group) => split(&group.hir),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+            hirs.iter().map(split).max().unwrap_or(Split::Lines)
+        }
+    }
+}
+
+fn split_for(regex: &Regex, text: RopeSlice) -> Split {
+    // an empty text still has an (empty) match for some patterns, but no lines to search
+    if text.len_bytes() == 0 {
+        return Split::Whole;
+    }
+    Parser::new()
+        .parse(regex.as_str())
+        .map_or(Split::Whole, |hir| split(&hir))
+}
+
+/// Start of the line containing `byte`. Only `\n` counts as a line break here, the same as for
+/// the regex line anchors.
+fn line_start(text: RopeSlice, byte: usize) -> usize {
+    let mut line = text.byte_to_line(byte);
+    loop {
+        let start = text.line_to_byte(line);
+        if start == 0 || text.byte(start - 1) == b'\n' {
+            return start;
+        }
+        line -= 1;
+    }
+}
+
+/// End of the line containing `byte`, past the `\n`.
+fn line_end(text: RopeSlice, byte: usize) -> usize {
+    let mut line = text.byte_to_line(byte);
+    loop {
+        line += 1;
+        if line >= text.len_lines() {
+            return text.len_bytes();
+        }
+        let start = text.line_to_byte(line);
+        if text.byte(start - 1) == b'\n' {
+            return start;
+        }
+    }
+}
+
+/// The pieces of text that get searched: runs of whole lines, borrowed from the rope chunks
+/// where possible, or the whole text at once.
+enum Windows<'t> {
+    Lines {
+        chunks: Chunks<'t>,
+        /// The part of the current chunk that hasn't been returned yet.
+        pending: &'t str,
+        /// Byte offset of `pending`.
+        offset: usize,
+    },
+    Whole(Option<Cow<'t, str>>),
+}
+
+impl<'t> Windows<'t> {
+    fn new(regex: &Regex, text: RopeSlice<'t>, start: usize) -> Self {
+        if split_for(regex, text) == Split::Whole {
+            return Self::Whole(Some(text.into()));
+        }
+
+        let offset = line_start(text, start);
+        let (mut chunks, chunk_start, _, _) = text.chunks_at_byte(offset);
+        let pending = chunks
+            .next()
+            .map_or("", |chunk| &chunk[offset - chunk_start..]);
+        Self::Lines {
+            chunks,
+            pending,
+            offset,
+        }
+    }
+
+    /// Byte offset of the next window.
+    fn offset(&self) -> usize {
+        match self {
+            Self::Lines { offset, .. } => *offset,
+            Self::Whole(_) => 0,
+        }
+    }
+}
+
+impl<'t> Iterator for Windows<'t> {
+    type Item = (usize, Cow<'t, str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (chunks, pending, offset) = match self {
+            Self::Lines {
+                chunks,
+                pending,
+                offset,
+            } => (chunks, pending, offset),
+            Self::Whole(text) => return text.take().map(|text| (0, text)),
+        };
+
+        while pending.is_empty() {
+            *pending = chunks.next()?;
+        }
+
+        let window = match pending.rfind('\n') {
+            Some(i) => {
+                let (lines, rest) = pending.split_at(i + 1);
+                *pending = rest;
+                Cow::Borrowed(lines)
+            }
+            None => {
+                // the line continues in the next chunks
+                let mut line = String::from(*pending);
+                *pending = "";
+                for chunk in chunks {
+                    match chunk.find('\n') {
+                        Some(i) => {
+                            line.push_str(&chunk[..=i]);
+                            *pending = &chunk[i + 1..];
+                            break;
+                        }
+                        None => line.push_str(chunk),
+                    }
+                }
+                Cow::Owned(line)
+            }
+        };
+
+        let start = *offset;
+        *offset += window.len();
+        Some((start, window))
+    }
+}
+
+/// Same as [`Windows`], going backwards.
+enum WindowsRev<'t> {
+    Lines {
+        chunks: Chunks<'t>,
+        /// The part of the current chunk that hasn't been returned yet.
+        pending: &'t str,
+        /// Byte offset of the end of `pending`.
+        offset: usize,
+    },
+    Whole(Option<Cow<'t, str>>),
+}
+
+impl<'t> WindowsRev<'t> {
+    fn new(regex: &Regex, text: RopeSlice<'t>, end: usize) -> Self {
+        if split_for(regex, text) == Split::Whole {
+            return Self::Whole(Some(text.into()));
+        }
+
+        let offset = line_end(text, end);
+        let (mut chunks, chunk_start, _, _) = text.chunks_at_byte(offset);
+        let pending = if offset > chunk_start {
+            let chunk = chunks.next().unwrap();
+            // step back over the chunk we just took
+            chunks.prev();
+            &chunk[..offset - chunk_start]
+        } else {
+            ""
+        };
+        Self::Lines {
+            chunks,
+            pending,
+            offset,
+        }
+    }
+
+    fn prev(&mut self) -> Option<(usize, Cow<'t, str>)> {
+        let (chunks, pending, offset) = match self {
+            Self::Lines {
+                chunks,
+                pending,
+                offset,
+            } => (chunks, pending, offset),
+            Self::Whole(text) => return text.take().map(|text| (0, text)),
+        };
+
+        while pending.is_empty() {
+            *pending = chunks.prev()?;
+        }
+
+        // `pending` always ends at the end of a line, find the start of the first whole line
+        let last_line_break = pending.strip_suffix('\n').unwrap_or(pending);
+        let window = match last_line_break.find('\n') {
+            Some(i) => {
+                let (rest, lines) = pending.split_at(i + 1);
+                *pending = rest;
+                Cow::Borrowed(lines)
+            }
+            None => {
+                // the line starts in the previous chunks
+                let mut pieces = vec![*pending];
+                *pending = "";
+                while let Some(chunk) = chunks.prev() {
+                    match chunk.rfind('\n') {
+                        Some(i) => {
+                            pieces.push(&chunk[i + 1..]);
+                            *pending = &chunk[..=i];
+                            break;
+                        }
+                        None => pieces.push(chunk),
+                    }
+                }
+                pieces.reverse();
+                Cow::Owned(pieces.concat())
+            }
+        };
+
+        *offset -= window.len();
+        Some((*offset, window))
+    }
+}
+
+/// Finds the next match in `window` at or after `pos`, stepping over empty matches the same way
+/// [`Regex::find_iter`] does.
+fn next_in_window(
+    regex: &Regex,
+    window: &str,
+    is_last: bool,
+    pos: &mut usize,
+    last_match: &mut Option<usize>,
+) -> Option<(usize, usize)> {
+    while *pos <= window.len() {
+        let mat = regex.find_at(window, *pos)?;
+        if mat.start() == mat.end() {
+            *pos = mat.end() + window[mat.end()..].chars().next().map_or(1, char::len_utf8);
+            // an empty match right after the previous one, or at the end of the window which is
+            // the start of the next one, where it will be found again
+            if *last_match == Some(mat.end()) || (mat.end() == window.len() && !is_last) {
+                continue;
+            }
+        } else {
+            *pos = mat.end();
+        }
+        *last_match = Some(mat.end());
+        return Some((mat.start(), mat.end()));
+    }
+    None
+}
+
+/// Iterator over the matches of a regex in a [`RopeSlice`], see [`find_iter`].
+pub struct Matches<'r, 't> {
+    regex: &'r Regex,
+    windows: Windows<'t>,
+    len: usize,
+    window: Option<(usize, Cow<'t, str>)>,
+    /// Position in the current window to continue searching from.
+    pos: usize,
+    last_match: Option<usize>,
+}
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        loop {
+            if let Some((start, window)) = &self.window {
+                let is_last = start + window.len() == self.len;
+                if let Some((from, to)) = next_in_window(
+                    self.regex,
+                    window,
+                    is_last,
+                    &mut self.pos,
+                    &mut self.last_match,
+                ) {
+                    return Some(Match {
+                        start: start + from,
+                        end: start + to,
+                    });
+                }
+                self.pos = 0;
+                self.last_match = None;
+            }
+            self.window = Some(self.windows.next()?);
+        }
+    }
+}
+
+/// Iterator over the matches of a regex in a [`RopeSlice`] in reverse, see [`rfind_iter_at`].
+pub struct MatchesRev<'r, 't> {
+    regex: &'r Regex,
+    windows: WindowsRev<'t>,
+    len: usize,
+    end: usize,
+    /// Matches of the current window, in order.
+    matches: Vec<Match>,
+}
+
+impl<'r, 't> Iterator for MatchesRev<'r, 't> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        while self.matches.is_empty() {
+            let (start, window) = self.windows.prev()?;
+            let is_last = start + window.len() == self.len;
+            let mut pos = 0;
+            let mut last_match = None;
+            while let Some((from, to)) =
+                next_in_window(self.regex, &window, is_last, &mut pos, &mut last_match)
+            {
+                if start + from >= self.end {
+                    break;
+                }
+                self.matches.push(Match {
+                    start: start + from,
+                    end: start + to,
+                });
+            }
+        }
+        self.matches.pop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Rope;
+
+    fn sample() -> String {
+        let mut text = String::new();
+        for i in 0..400 {
+            text.push_str(&format!("line {} föö bar{}\n", i, "x".repeat(i % 7)));
+            if i % 50 == 0 {
+                // lines spanning several chunks
+                text.push_str(&"baz ü ".repeat(500));
+                text.push('\n');
+            }
+            if i % 30 == 0 {
+                text.push('\n');
+            }
+        }
+        text.push_str("end without line break");
+        text
+    }
+
+    const PATTERNS: &[&str] = &[
+        "föö",
+        r"\bbar\w*",
+        r"^line \d+",
+        r"\d*$",
+        "x*",
+        r"ü b",
+        "^$",
+        "(?m)^$",
+        r"(?m)^line \d+",
+        r"(?m)\d*$",
+        r"bar\s+line",
+        r"\Aline",
+        "break$",
+        "missing",
+        // classes matching line breaks
+        r"\s+",
+        r"[^ ]+",
+        r"\d\s+line",
+    ];
+
+    #[test]
+    fn test_find_iter() {
+        let text = sample();
+        let rope = Rope::from(text.as_str());
+        assert!(rope.chunks().count() > 10);
+
+        for pattern in PATTERNS {
+            let regex = Regex::new(pattern).unwrap();
+            let expected: Vec<_> = regex.find_iter(&text).map(|m| m.range()).collect();
+            let matches: Vec<_> = find_iter(&regex, rope.slice(..))
+                .map(|m| m.range())
+                .collect();
+            assert_eq!(matches, expected, "{}", pattern);
+
+            let mut reversed: Vec<_> = rfind_iter_at(&regex, rope.slice(..), text.len())
+                .map(|m| m.range())
+                .collect();
+            reversed.reverse();
+            let before_end: Vec<_> = expected
+                .iter()
+                .filter(|m| m.start < text.len())
+                .cloned()
+                .collect();
+            assert_eq!(reversed, before_end, "{}", pattern);
+
+            for &pos in &[0, 1, 2000, 3001, 7777, text.len() / 2, text.len()] {
+                let pos = (pos..).find(|&pos| text.is_char_boundary(pos)).unwrap();
+                assert_eq!(
+                    find_iter_at(&regex, rope.slice(..), pos)
+                        .next()
+                        .map(|m| m.range()),
+                    regex.find_at(&text, pos).map(|m| m.range()),
+                    "{} at {}",
+                    pattern,
+                    pos
+                );
+                assert_eq!(
+                    rfind_iter_at(&regex, rope.slice(..), pos)
+                        .next()
+                        .map(|m| m.range()),
+                    expected.iter().rev().find(|m| m.start < pos).cloned(),
+                    "{} before {}",
+                    pattern,
+                    pos
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let rope = Rope::from("foo\n bar\n");
+        let text = rope.slice(..);
+        let regex = |pattern| Regex::new(pattern).unwrap();
+
+        assert_eq!(split_for(&regex(r"(?m)^foo$"), text), Split::Lines);
+        assert_eq!(split_for(&regex(r"\bfoo\w+"), text), Split::Lines);
+        assert_eq!(split_for(&regex(r"\s+"), text), Split::Whole);
+        assert_eq!(split_for(&regex(r"[^x]\D\W"), text), Split::Whole);
+        assert_eq!(split_for(&regex(r"(?s)."), text), Split::Whole);
+        assert_eq!(split_for(&regex(r"foo\nbar"), text), Split::Whole);
+        assert_eq!(split_for(&regex(r"\Afoo"), text), Split::Whole);
+        // without the flag, `^` is the start of the text
+        assert_eq!(split_for(&regex("^foo"), text), Split::Whole);
+
+        // matches run across lines
+        assert_eq!(
+            find_iter(&regex(r"foo\s+bar"), text)
+                .map(|m| m.range())
+                .collect::<Vec<_>>(),
+            vec![0..8]
+        );
+        assert_eq!(
+            find_iter(&regex(r"\s+"), text)
+                .map(|m| m.range())
+                .collect::<Vec<_>>(),
+            vec![3..5, 8..9]
+        );
+    }
+
+    #[test]
+    fn test_find_wrap_around() {
+        let rope = Rope::from("one two\none two\n");
+        let text = rope.slice(..);
+        let regex = Regex::new("one").unwrap();
+
+        assert_eq!(find_next(&regex, text, 1).map(|m| m.range()), Some(8..11));
+        assert_eq!(find_next(&regex, text, 9).map(|m| m.range()), Some(0..3));
+        assert_eq!(find_prev(&regex, text, 9).map(|m| m.range()), Some(8..11));
+        assert_eq!(find_prev(&regex, text, 8).map(|m| m.range()), Some(0..3));
+        assert_eq!(find_prev(&regex, text, 0).map(|m| m.range()), Some(8..11));
+
+        let regex = Regex::new("three").unwrap();
+        assert_eq!(find_next(&regex, text, 3), None);
+        assert_eq!(find_prev(&regex, text, 3), None);
+        assert!(!is_match(&regex, text));
+    }
+
+    #[test]
+    fn test_empty_text() {
+        let rope = Rope::from("");
+        let regex = Regex::new("x*").unwrap();
+        assert_eq!(
+            find_iter(&regex, rope.slice(..))
+                .map(|m| m.range())
+                .collect::<Vec<_>>(),
+            vec![0..0]
+        );
+    }
+}
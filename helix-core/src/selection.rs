@@ -7,8 +7,9 @@ use crate::{
         ensure_grapheme_boundary_next, ensure_grapheme_boundary_prev, next_grapheme_boundary,
         prev_grapheme_boundary,
     },
-    Assoc, ChangeSet, RopeSlice,
+    search, Assoc, ChangeSet, RopeSlice,
 };
+use serde::{Deserialize, Serialize};
 use smallvec::{smallvec, SmallVec};
 use std::borrow::Cow;
 
@@ -46,7 +47,7 @@ use std::borrow::Cow;
 /// single grapheme inward from the range's edge.  There are a
 /// variety of helper methods on `Range` for working in terms of
 /// that block cursor, all of which have `cursor` in their name.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Range {
     /// The anchor of the range: the side that doesn't move when extending.
     pub anchor: usize,
@@ -319,7 +320,7 @@ impl From<(usize, usize)> for Range {
 
 /// A selection consists of one or more selection ranges.
 /// invariant: A selection can never be empty (always contains at least primary range).
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Selection {
     ranges: SmallVec<[Range; 1]>,
     primary_index: usize,
@@ -360,6 +361,28 @@ impl Selection {
         self.normalize()
     }
 
+    /// Removes the range at `index`. When it was the primary range, the range before it becomes
+    /// the primary one, wrapping around to the last range.
+    ///
+    /// Panics if it is the only range, a selection can't be empty.
+    pub fn remove(mut self, index: usize) -> Self {
+        use std::cmp::Ordering;
+
+        assert!(
+            self.ranges.len() > 1,
+            "can't remove the only range of a selection"
+        );
+        self.ranges.remove(index);
+        match index.cmp(&self.primary_index) {
+            Ordering::Less => self.primary_index -= 1,
+            Ordering::Equal => {
+                self.primary_index = index.checked_sub(1).unwrap_or(self.ranges.len() - 1)
+            }
+            Ordering::Greater => (),
+        }
+        self
+    }
+
     /// Map selections over a set of changes. Useful for adjusting the selection position after
     /// applying changes to a document.
     pub fn map(self, changes: &ChangeSet) -> Self {
@@ -495,6 +518,14 @@ impl Selection {
     pub fn len(&self) -> usize {
         self.ranges.len()
     }
+
+    /// Whether every range of `other` lies within one of the ranges of this selection.
+    pub fn contains(&self, other: &Selection) -> bool {
+        other.iter().all(|range| {
+            self.iter()
+                .any(|own| own.from() <= range.from() && range.to() <= own.to())
+        })
+    }
 }
 
 impl<'a> IntoIterator for &'a Selection {
@@ -515,7 +546,7 @@ pub fn keep_matches(
 ) -> Option<Selection> {
     let result: SmallVec<_> = selection
         .iter()
-        .filter(|range| regex.is_match(&range.fragment(text)))
+        .filter(|range| search::is_match(regex, text.slice(range.from()..range.to())))
         .copied()
         .collect();
 
@@ -534,13 +565,10 @@ pub fn select_on_matches(
     let mut result = SmallVec::with_capacity(selection.len());
 
     for sel in selection {
-        // TODO: can't avoid occasional allocations since Regex can't operate on chunks yet
-        let fragment = sel.fragment(text);
-
         let sel_start = sel.from();
         let start_byte = text.char_to_byte(sel_start);
 
-        for mat in regex.find_iter(&fragment) {
+        for mat in search::find_iter(regex, text.slice(sel_start..sel.to())) {
             // TODO: retain range direction
 
             let start = text.byte_to_char(start_byte + mat.start());
@@ -557,6 +585,22 @@ pub fn select_on_matches(
     None
 }
 
+/// Finds the first non-empty match of `regex` after the primary range that doesn't overlap any
+/// range of the selection, wrapping around the end of the text.
+pub fn find_next_match(
+    text: RopeSlice,
+    selection: &Selection,
+    regex: &crate::regex::Regex,
+) -> Option<Range> {
+    let start = text.char_to_byte(selection.primary().to());
+
+    search::find_iter_at(regex, text, start)
+        .chain(search::find_iter(regex, text).take_while(|mat| mat.start() < start))
+        .filter(|mat| mat.start() < mat.end())
+        .map(|mat| Range::new(text.byte_to_char(mat.start()), text.byte_to_char(mat.end())))
+        .find(|range| !selection.iter().any(|own| own.overlaps(range)))
+}
+
 // TODO: support to split on capture #N instead of whole match
 pub fn split_on_matches(
     text: RopeSlice,
@@ -572,9 +616,6 @@ pub fn split_on_matches(
             continue;
         }
 
-        // TODO: can't avoid occasional allocations since Regex can't operate on chunks yet
-        let fragment = sel.fragment(text);
-
         let sel_start = sel.from();
         let sel_end = sel.to();
 
@@ -582,7 +623,7 @@ pub fn split_on_matches(
 
         let mut start = sel_start;
 
-        for mat in regex.find_iter(&fragment) {
+        for mat in search::find_iter(regex, text.slice(sel_start..sel_end)) {
             // TODO: retain range direction
             let end = text.byte_to_char(start_byte + mat.start());
             result.push(Range::new(start, end));
@@ -690,6 +731,22 @@ mod test {
         assert_eq!(range.contains(6), true);
     }
 
+    #[test]
+    fn test_selection_contains() {
+        let selection = Selection::new(smallvec![Range::new(0, 5), Range::new(10, 20)], 0);
+
+        assert!(selection.contains(&Selection::single(1, 3)));
+        assert!(selection.contains(&Selection::new(
+            smallvec![Range::new(5, 0), Range::new(12, 15)],
+            1
+        )));
+        assert!(!selection.contains(&Selection::single(4, 11)));
+        assert!(!selection.contains(&Selection::new(
+            smallvec![Range::new(0, 1), Range::new(20, 21)],
+            0
+        )));
+    }
+
     #[test]
     fn test_overlaps() {
         fn overlaps(a: (usize, usize), b: (usize, usize)) -> bool {
@@ -895,6 +952,51 @@ mod test {
         assert_eq!(Range::new(6, 3).put_cursor(s, 8, true), Range::new(4, 9));
     }
 
+    #[test]
+    fn test_remove() {
+        let selection = Selection::new(
+            smallvec![Range::new(0, 1), Range::new(2, 3), Range::new(4, 5)],
+            1,
+        );
+        let result = selection.clone().remove(1);
+        assert_eq!(result.ranges(), &[Range::new(0, 1), Range::new(4, 5)]);
+        assert_eq!(result.primary(), Range::new(0, 1));
+        assert_eq!(selection.clone().remove(0).primary(), Range::new(2, 3));
+        assert_eq!(selection.remove(2).primary(), Range::new(2, 3));
+
+        let result = Selection::new(smallvec![Range::new(0, 1), Range::new(2, 3)], 0).remove(0);
+        assert_eq!(result.primary(), Range::new(2, 3));
+    }
+
+    #[test]
+    fn test_find_next_match() {
+        use crate::regex::Regex;
+
+        let text = Rope::from("foo bar foo\nfoobar foo");
+        let text = text.slice(..);
+        let regex = Regex::new("foo").unwrap();
+
+        let selection = Selection::single(8, 11);
+        assert_eq!(
+            find_next_match(text, &selection, &regex),
+            Some(Range::new(12, 15))
+        );
+
+        // wraps around, skipping the selected matches
+        let selection = Selection::new(smallvec![Range::new(12, 15), Range::new(19, 22)], 1);
+        assert_eq!(
+            find_next_match(text, &selection, &regex),
+            Some(Range::new(0, 3))
+        );
+
+        let regex = Regex::new(r"\bfoo\b").unwrap();
+        let selection = Selection::new(
+            smallvec![Range::new(0, 3), Range::new(8, 11), Range::new(19, 22)],
+            0,
+        );
+        assert_eq!(find_next_match(text, &selection, &regex), None);
+    }
+
     #[test]
     fn test_split_on_matches() {
         use crate::regex::Regex;
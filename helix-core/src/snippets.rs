@@ -0,0 +1,829 @@
+//! Parsing and expansion of LSP snippets, and tracking of the tabstops of an inserted snippet.
+//!
+//! See <https://microsoft.github.io/language-server-protocol/specifications/specification-current/#snippet_syntax>
+//! for the grammar. Like other editors, the parser is lenient: anything that isn't a valid
+//! construct is inserted as plain text.
+
+use crate::{
+    regex::RegexBuilder,
+    selection::{Range, Selection},
+    Assoc, ChangeSet,
+};
+use smallvec::SmallVec;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnippetElement {
+    /// `$1` or `${1}`.
+    Tabstop {
+        tabstop: usize,
+    },
+    /// `${1:value}`.
+    Placeholder {
+        tabstop: usize,
+        value: Vec<SnippetElement>,
+    },
+    /// `${1|one,two,three|}`.
+    Choice {
+        tabstop: usize,
+        choices: Vec<String>,
+    },
+    /// `$name`, `${name}`, `${name:default}` or `${name/regex/format/options}`.
+    Variable {
+        name: String,
+        default: Option<Vec<SnippetElement>>,
+        transform: Option<Transform>,
+    },
+    Text(String),
+}
+
+/// A regex replacement applied to the value of a variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transform {
+    pub regex: String,
+    pub format: Vec<FormatItem>,
+    pub options: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatItem {
+    Text(String),
+    /// `$1` or `${1}`.
+    Capture(usize),
+    /// `${1:/upcase}`, `${1:/downcase}` or `${1:/capitalize}`.
+    CaseChange(usize, CaseChange),
+    /// `${1:+if}`, `${1:?if:else}`, `${1:-else}` or `${1:else}`: inserts the first text if the
+    /// group matched, the second one otherwise.
+    Conditional(usize, Option<String>, Option<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseChange {
+    Upcase,
+    Downcase,
+    Capitalize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub elements: Vec<SnippetElement>,
+}
+
+pub fn parse(snippet: &str) -> Snippet {
+    let mut parser = Parser {
+        input: snippet,
+        pos: 0,
+    };
+    Snippet {
+        elements: parser.parse_any(false),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        if self.peek() == Some(ch) {
+            self.pos += ch.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while self.peek().map_or(false, &f) {
+            self.bump();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn int(&mut self) -> Option<usize> {
+        self.eat_while(|ch| ch.is_ascii_digit()).parse().ok()
+    }
+
+    fn var(&mut self) -> Option<String> {
+        match self.peek() {
+            Some(ch) if ch == '_' || ch.is_ascii_alphabetic() => Some(
+                self.eat_while(|ch| ch == '_' || ch.is_ascii_alphanumeric())
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Reads text up to one of the unescaped `stops`, which is not consumed. A backslash
+    /// escapes `$`, `\` and the stops, and is kept as is before any other character.
+    fn text(&mut self, stops: &[char]) -> String {
+        let mut text = String::new();
+        while let Some(ch) = self.peek() {
+            if stops.contains(&ch) {
+                break;
+            }
+            self.bump();
+            if ch == '\\' {
+                match self.peek() {
+                    Some(next) if next == '$' || next == '\\' || stops.contains(&next) => {
+                        text.push(next);
+                        self.bump();
+                    }
+                    _ => text.push(ch),
+                }
+            } else {
+                text.push(ch);
+            }
+        }
+        text
+    }
+
+    /// Parses elements up to the end of the input, or up to an unmatched `}` when `nested`.
+    fn parse_any(&mut self, nested: bool) -> Vec<SnippetElement> {
+        let stops: &[char] = if nested { &['$', '}'] } else { &['$'] };
+        let mut elements = Vec::new();
+        loop {
+            let text = self.text(stops);
+            if !text.is_empty() {
+                push_text(&mut elements, &text);
+            }
+            match self.peek() {
+                Some('$') => match self.parse_dollar() {
+                    Some(element) => elements.push(element),
+                    None => {
+                        self.bump();
+                        push_text(&mut elements, "$");
+                    }
+                },
+                _ => return elements,
+            }
+        }
+    }
+
+    /// Parses a construct starting with `$`. Leaves the position untouched if it isn't valid.
+    fn parse_dollar(&mut self) -> Option<SnippetElement> {
+        let start = self.pos;
+        let element = self.parse_dollar_impl();
+        if element.is_none() {
+            self.pos = start;
+        }
+        element
+    }
+
+    fn parse_dollar_impl(&mut self) -> Option<SnippetElement> {
+        self.bump(); // $
+
+        if let Some(tabstop) = self.int() {
+            return Some(SnippetElement::Tabstop { tabstop });
+        }
+        if let Some(name) = self.var() {
+            return Some(SnippetElement::Variable {
+                name,
+                default: None,
+                transform: None,
+            });
+        }
+        if !self.eat('{') {
+            return None;
+        }
+
+        if let Some(tabstop) = self.int() {
+            if self.eat('}') {
+                return Some(SnippetElement::Tabstop { tabstop });
+            }
+            if self.eat(':') {
+                let value = self.parse_any(true);
+                return self
+                    .eat('}')
+                    .then(|| SnippetElement::Placeholder { tabstop, value });
+            }
+            if self.eat('|') {
+                let mut choices = Vec::new();
+                loop {
+                    choices.push(self.text(&[',', '|']));
+                    if !self.eat(',') {
+                        break;
+                    }
+                }
+                return (self.eat('|') && self.eat('}'))
+                    .then(|| SnippetElement::Choice { tabstop, choices });
+            }
+            return None;
+        }
+
+        let name = self.var()?;
+        let mut default = None;
+        let mut transform = None;
+        if self.eat(':') {
+            default = Some(self.parse_any(true));
+        } else if self.eat('/') {
+            let regex = self.regex();
+            if !self.eat('/') {
+                return None;
+            }
+            let format = self.format();
+            if !self.eat('/') {
+                return None;
+            }
+            let options = self.eat_while(|ch| ch.is_ascii_alphabetic()).to_string();
+            transform = Some(Transform {
+                regex,
+                format,
+                options,
+            });
+        }
+        self.eat('}').then(|| SnippetElement::Variable {
+            name,
+            default,
+            transform,
+        })
+    }
+
+    /// Reads a regex up to the unescaped `/`. Escapes other than `\/` are left to the regex.
+    fn regex(&mut self) -> String {
+        let mut regex = String::new();
+        while let Some(ch) = self.peek() {
+            if ch == '/' {
+                break;
+            }
+            self.bump();
+            if ch == '\\' && self.eat('/') {
+                regex.push('/');
+            } else {
+                regex.push(ch);
+                if ch == '\\' {
+                    regex.extend(self.bump());
+                }
+            }
+        }
+        regex
+    }
+
+    fn format(&mut self) -> Vec<FormatItem> {
+        let mut items = Vec::new();
+        loop {
+            let text = self.text(&['$', '/']);
+            if !text.is_empty() {
+                items.push(FormatItem::Text(text));
+            }
+            if self.peek() != Some('$') {
+                return items;
+            }
+
+            let start = self.pos;
+            match self.format_item() {
+                Some(item) => items.push(item),
+                None => {
+                    self.pos = start + 1;
+                    items.push(FormatItem::Text("$".to_string()));
+                }
+            }
+        }
+    }
+
+    fn format_item(&mut self) -> Option<FormatItem> {
+        self.bump(); // $
+
+        if let Some(group) = self.int() {
+            return Some(FormatItem::Capture(group));
+        }
+        if !self.eat('{') {
+            return None;
+        }
+        let group = self.int()?;
+        if self.eat('}') {
+            return Some(FormatItem::Capture(group));
+        }
+        if !self.eat(':') {
+            return None;
+        }
+
+        let item = if self.eat('/') {
+            let case_change = match self.var()?.as_str() {
+                "upcase" => CaseChange::Upcase,
+                "downcase" => CaseChange::Downcase,
+                "capitalize" => CaseChange::Capitalize,
+                _ => return None,
+            };
+            FormatItem::CaseChange(group, case_change)
+        } else if self.eat('+') {
+            FormatItem::Conditional(group, Some(self.text(&['}'])), None)
+        } else if self.eat('?') {
+            let if_text = self.text(&[':', '}']);
+            if !self.eat(':') {
+                return None;
+            }
+            FormatItem::Conditional(group, Some(if_text), Some(self.text(&['}'])))
+        } else {
+            self.eat('-');
+            FormatItem::Conditional(group, None, Some(self.text(&['}'])))
+        };
+        self.eat('}').then(|| item)
+    }
+}
+
+fn push_text(elements: &mut Vec<SnippetElement>, text: &str) {
+    match elements.last_mut() {
+        Some(SnippetElement::Text(last)) => last.push_str(text),
+        _ => elements.push(SnippetElement::Text(text.to_string())),
+    }
+}
+
+impl Transform {
+    /// Replaces the matches of the regex in `value` according to the format. Returns `value`
+    /// unchanged if the regex is invalid.
+    pub fn apply(&self, value: &str) -> String {
+        let regex = match RegexBuilder::new(&self.regex)
+            .case_insensitive(self.options.contains('i'))
+            .multi_line(self.options.contains('m'))
+            .build()
+        {
+            Ok(regex) => regex,
+            Err(_) => return value.to_string(),
+        };
+
+        let count = if self.options.contains('g') {
+            usize::MAX
+        } else {
+            1
+        };
+        let mut result = String::new();
+        let mut last = 0;
+        for captures in regex.captures_iter(value).take(count) {
+            let mat = captures.get(0).unwrap();
+            result.push_str(&value[last..mat.start()]);
+            for item in &self.format {
+                let group = |group: usize| captures.get(group).map(|group| group.as_str());
+                match item {
+                    FormatItem::Text(text) => result.push_str(text),
+                    FormatItem::Capture(i) => result.push_str(group(*i).unwrap_or_default()),
+                    FormatItem::CaseChange(i, case_change) => {
+                        let text = group(*i).unwrap_or_default();
+                        match case_change {
+                            CaseChange::Upcase => result.push_str(&text.to_uppercase()),
+                            CaseChange::Downcase => result.push_str(&text.to_lowercase()),
+                            CaseChange::Capitalize => {
+                                let mut chars = text.chars();
+                                result
+                                    .extend(chars.next().into_iter().flat_map(char::to_uppercase));
+                                result.push_str(chars.as_str());
+                            }
+                        }
+                    }
+                    FormatItem::Conditional(i, if_text, else_text) => {
+                        let text = match group(*i) {
+                            Some(group) if !group.is_empty() => if_text,
+                            _ => else_text,
+                        };
+                        result.push_str(text.as_deref().unwrap_or_default());
+                    }
+                }
+            }
+            last = mat.end();
+        }
+        result.push_str(&value[last..]);
+        result
+    }
+}
+
+/// The text of a snippet with its variables resolved, and where its tabstops ended up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedSnippet {
+    pub text: String,
+    /// Char ranges of each tabstop, relative to the start of the text, in the order they're
+    /// visited. The last one is the final cursor position, `$0`.
+    pub tabstops: Vec<SmallVec<[(usize, usize); 1]>>,
+}
+
+impl Snippet {
+    /// Expands the snippet. `newline` replaces every line break of the snippet's text, so it can
+    /// carry the document's line ending and the indentation of the line the snippet is inserted
+    /// on. Variables are looked up with `resolve`; unknown variables without a default value
+    /// turn into a placeholder of their name.
+    pub fn render(
+        &self,
+        newline: &str,
+        mut resolve: impl FnMut(&str) -> Option<String>,
+    ) -> RenderedSnippet {
+        let mut placeholders = HashMap::new();
+        let mut max_tabstop = 0;
+        collect_placeholders(&self.elements, &mut placeholders, &mut max_tabstop);
+
+        let mut renderer = Renderer {
+            placeholders,
+            next_tabstop: max_tabstop + 1,
+            newline,
+            resolve: &mut resolve,
+            text: String::new(),
+            len: 0,
+            tabstops: HashMap::new(),
+            stack: Vec::new(),
+        };
+        renderer.render(&self.elements);
+
+        let Renderer {
+            text,
+            len,
+            tabstops,
+            ..
+        } = renderer;
+        let has_final = tabstops.contains_key(&0);
+        let mut tabstops: Vec<_> = tabstops.into_iter().collect();
+        // visit the tabstops in order, with $0 last
+        tabstops.sort_by_key(|(tabstop, _)| if *tabstop == 0 { usize::MAX } else { *tabstop });
+        let mut tabstops: Vec<_> = tabstops.into_iter().map(|(_, ranges)| ranges).collect();
+        if !has_final {
+            // without $0, the cursor ends up after the snippet
+            tabstops.push(SmallVec::from_elem((len, len), 1));
+        }
+
+        RenderedSnippet { text, tabstops }
+    }
+}
+
+/// Finds the first placeholder or choice of each tabstop, whose value the other occurrences of
+/// the tabstop mirror.
+fn collect_placeholders<'a>(
+    elements: &'a [SnippetElement],
+    placeholders: &mut HashMap<usize, &'a SnippetElement>,
+    max_tabstop: &mut usize,
+) {
+    for element in elements {
+        match element {
+            SnippetElement::Tabstop { tabstop } => *max_tabstop = (*max_tabstop).max(*tabstop),
+            SnippetElement::Placeholder { tabstop, value } => {
+                *max_tabstop = (*max_tabstop).max(*tabstop);
+                placeholders.entry(*tabstop).or_insert(element);
+                collect_placeholders(value, placeholders, max_tabstop);
+            }
+            SnippetElement::Choice { tabstop, .. } => {
+                *max_tabstop = (*max_tabstop).max(*tabstop);
+                placeholders.entry(*tabstop).or_insert(element);
+            }
+            SnippetElement::Variable {
+                default: Some(default),
+                ..
+            } => collect_placeholders(default, placeholders, max_tabstop),
+            _ => (),
+        }
+    }
+}
+
+struct Renderer<'a, 'r> {
+    placeholders: HashMap<usize, &'a SnippetElement>,
+    /// Tabstop given to the next unknown variable.
+    next_tabstop: usize,
+    newline: &'r str,
+    resolve: &'r mut dyn FnMut(&str) -> Option<String>,
+    text: String,
+    /// Length of `text` in chars.
+    len: usize,
+    tabstops: HashMap<usize, SmallVec<[(usize, usize); 1]>>,
+    /// Tabstops being rendered, to cut off placeholders that mirror themselves.
+    stack: Vec<usize>,
+}
+
+impl<'a, 'r> Renderer<'a, 'r> {
+    fn push_str(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.text.push_str(self.newline);
+                self.len += self.newline.chars().count();
+            }
+            self.text.push_str(line);
+            self.len += line.chars().count();
+        }
+    }
+
+    fn render(&mut self, elements: &[SnippetElement]) {
+        for element in elements {
+            match element {
+                SnippetElement::Text(text) => self.push_str(text),
+                SnippetElement::Tabstop { tabstop }
+                | SnippetElement::Placeholder { tabstop, .. }
+                | SnippetElement::Choice { tabstop, .. } => self.render_tabstop(*tabstop),
+                SnippetElement::Variable {
+                    name,
+                    default,
+                    transform,
+                } => match (self.resolve)(name) {
+                    Some(value) => {
+                        let value = match transform {
+                            Some(transform) => transform.apply(&value),
+                            None => value,
+                        };
+                        self.push_str(&value);
+                    }
+                    None => match default {
+                        Some(default) => self.render(default),
+                        None => {
+                            let start = self.len;
+                            self.push_str(name);
+                            let tabstop = self.next_tabstop;
+                            self.next_tabstop += 1;
+                            self.tabstops
+                                .entry(tabstop)
+                                .or_default()
+                                .push((start, self.len));
+                        }
+                    },
+                },
+            }
+        }
+    }
+
+    fn render_tabstop(&mut self, tabstop: usize) {
+        let start = self.len;
+        if !self.stack.contains(&tabstop) {
+            self.stack.push(tabstop);
+            match self.placeholders.get(&tabstop) {
+                Some(SnippetElement::Placeholder { value, .. }) => self.render(value),
+                Some(SnippetElement::Choice { choices, .. }) => {
+                    self.push_str(choices.first().map_or("", String::as_str))
+                }
+                _ => (),
+            }
+            self.stack.pop();
+        }
+        self.tabstops
+            .entry(tabstop)
+            .or_default()
+            .push((start, self.len));
+    }
+}
+
+/// The tabstops of a snippet that has been inserted in a document, to jump between them while
+/// filling them in.
+#[derive(Debug, Clone)]
+pub struct ActiveSnippet {
+    /// Ranges of each tabstop, the last one being the final cursor position.
+    tabstops: Vec<SmallVec<[Range; 1]>>,
+    current: usize,
+}
+
+impl ActiveSnippet {
+    /// Starts tracking the tabstops of `snippet`, inserted at char `offset`. Returns `None` if
+    /// the snippet has nothing to fill in.
+    pub fn new(snippet: &RenderedSnippet, offset: usize) -> Option<Self> {
+        if snippet.tabstops.len() < 2 {
+            return None;
+        }
+
+        let tabstops = snippet
+            .tabstops
+            .iter()
+            .map(|ranges| {
+                ranges
+                    .iter()
+                    .map(|(start, end)| Range::new(offset + start, offset + end))
+                    .collect()
+            })
+            .collect();
+        Some(Self {
+            tabstops,
+            current: 0,
+        })
+    }
+
+    /// Map the tabstops through `changes`. Text inserted at either end of a tabstop becomes part
+    /// of it, so that it covers what was typed into it.
+    pub fn map(&mut self, changes: &ChangeSet) {
+        for range in self.tabstops.iter_mut().flatten() {
+            *range = Range::new(
+                changes.map_pos(range.from(), Assoc::Before),
+                changes.map_pos(range.to(), Assoc::After),
+            );
+        }
+    }
+
+    /// Selects all the ranges of the current tabstop, with the cursor at their start.
+    pub fn selection(&self) -> Selection {
+        let ranges = self.tabstops[self.current]
+            .iter()
+            .map(|range| Range::new(range.to(), range.from()))
+            .collect();
+        Selection::new(ranges, 0)
+    }
+
+    /// Whether `selection` is still exactly the (non-empty) current tabstop, which typing should
+    /// then replace.
+    pub fn is_placeholder_selected(&self, selection: &Selection) -> bool {
+        let ranges = &self.tabstops[self.current];
+        selection.len() == ranges.len()
+            && selection
+                .iter()
+                .zip(ranges)
+                .all(|(a, b)| !b.is_empty() && a.from() == b.from() && a.to() == b.to())
+    }
+
+    /// Moves to the next tabstop. Returns false if there is none.
+    pub fn next_tabstop(&mut self) -> bool {
+        if self.current + 1 < self.tabstops.len() {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Moves to the previous tabstop. Returns false if there is none.
+    pub fn prev_tabstop(&mut self) -> bool {
+        if self.current > 0 {
+            self.current -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the current tabstop is the final cursor position, which ends the snippet.
+    pub fn is_final(&self) -> bool {
+        self.current + 1 == self.tabstops.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use SnippetElement::*;
+
+    fn text(text: &str) -> SnippetElement {
+        Text(text.to_string())
+    }
+
+    fn tabstops(rendered: &RenderedSnippet) -> Vec<Vec<(usize, usize)>> {
+        rendered
+            .tabstops
+            .iter()
+            .map(|ranges| ranges.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn parse_tabstops_and_placeholders() {
+        assert_eq!(
+            parse("fn ${1:name}($2) {\n\t$0\n}").elements,
+            vec![
+                text("fn "),
+                Placeholder {
+                    tabstop: 1,
+                    value: vec![text("name")]
+                },
+                text("("),
+                Tabstop { tabstop: 2 },
+                text(") {\n\t"),
+                Tabstop { tabstop: 0 },
+                text("\n}"),
+            ]
+        );
+
+        assert_eq!(
+            parse("${1:outer ${2:inner}}${3}").elements,
+            vec![
+                Placeholder {
+                    tabstop: 1,
+                    value: vec![
+                        text("outer "),
+                        Placeholder {
+                            tabstop: 2,
+                            value: vec![text("inner")]
+                        }
+                    ]
+                },
+                Tabstop { tabstop: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_choices_and_variables() {
+        assert_eq!(
+            parse("${1|one,t\\,wo,three|} $TM_FILENAME ${TM_LINE_NUMBER:${2:1}}").elements,
+            vec![
+                Choice {
+                    tabstop: 1,
+                    choices: vec!["one".to_string(), "t,wo".to_string(), "three".to_string()]
+                },
+                text(" "),
+                Variable {
+                    name: "TM_FILENAME".to_string(),
+                    default: None,
+                    transform: None
+                },
+                text(" "),
+                Variable {
+                    name: "TM_LINE_NUMBER".to_string(),
+                    default: Some(vec![Placeholder {
+                        tabstop: 2,
+                        value: vec![text("1")]
+                    }]),
+                    transform: None
+                },
+            ]
+        );
+
+        assert_eq!(
+            parse("${TM_FILENAME/(.*)\\.(rs)/${1:/upcase}${2:+!}/g}").elements,
+            vec![Variable {
+                name: "TM_FILENAME".to_string(),
+                default: None,
+                transform: Some(Transform {
+                    regex: "(.*)\\.(rs)".to_string(),
+                    format: vec![
+                        FormatItem::CaseChange(1, CaseChange::Upcase),
+                        FormatItem::Conditional(2, Some("!".to_string()), None),
+                    ],
+                    options: "g".to_string(),
+                })
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_as_text() {
+        assert_eq!(parse("a $ b").elements, vec![text("a $ b")]);
+        assert_eq!(parse("${1:unclosed").elements, vec![text("${1:unclosed")]);
+        assert_eq!(parse("cost: \\$5 }").elements, vec![text("cost: $5 }")]);
+        assert_eq!(parse("${}").elements, vec![text("${}")]);
+    }
+
+    #[test]
+    fn render_mirrors() {
+        let snippet = parse("let ${1:x} = $2; $1 + ${1:y}$0");
+        let rendered = snippet.render("\n", |_| None);
+        assert_eq!(rendered.text, "let x = ; x + x");
+        assert_eq!(
+            tabstops(&rendered),
+            vec![
+                vec![(4, 5), (10, 11), (14, 15)],
+                vec![(8, 8)],
+                vec![(15, 15)]
+            ]
+        );
+    }
+
+    #[test]
+    fn render_variables_and_newlines() {
+        let snippet = parse("fn ${TM_FILENAME/(\\w+)\\.rs/${1:/capitalize}/}() {\n\t$UNKNOWN\n}");
+        let rendered = snippet.render("\n    ", |name| match name {
+            "TM_FILENAME" => Some("main.rs".to_string()),
+            _ => None,
+        });
+        assert_eq!(rendered.text, "fn Main() {\n    \tUNKNOWN\n    }");
+        // the unknown variable became a placeholder, and the final position is at the end
+        assert_eq!(tabstops(&rendered), vec![vec![(17, 24)], vec![(30, 30)]]);
+    }
+
+    #[test]
+    fn active_snippet() {
+        use crate::{Rope, Transaction};
+
+        let mut doc = Rope::from("(");
+        let rendered = parse("${1:a}, ${2:b}, $1").render("\n", |_| None);
+        let transaction = Transaction::change(
+            &doc,
+            vec![(1, 1, Some(rendered.text.as_str().into()))].into_iter(),
+        );
+        transaction.apply(&mut doc);
+        assert_eq!(doc, "(a, b, a");
+
+        let mut snippet = ActiveSnippet::new(&rendered, 1).unwrap();
+        let selection = snippet.selection();
+        assert_eq!(selection.ranges(), &[Range::new(2, 1), Range::new(8, 7)]);
+        assert!(snippet.is_placeholder_selected(&selection));
+
+        // type over the first tabstop, in both places at once
+        let transaction = Transaction::change(
+            &doc,
+            vec![(1, 2, Some("xyz".into())), (7, 8, Some("xyz".into()))].into_iter(),
+        );
+        transaction.apply(&mut doc);
+        snippet.map(transaction.changes());
+        assert_eq!(doc, "(xyz, b, xyz");
+        assert_eq!(
+            snippet.selection().ranges(),
+            &[Range::new(4, 1), Range::new(12, 9)]
+        );
+
+        assert!(snippet.next_tabstop());
+        assert_eq!(snippet.selection().ranges(), &[Range::new(7, 6)]);
+        assert!(snippet.next_tabstop());
+        assert!(snippet.is_final());
+        assert_eq!(snippet.selection().ranges(), &[Range::new(12, 12)]);
+        assert!(!snippet.next_tabstop());
+        assert!(snippet.prev_tabstop());
+        assert!(!snippet.is_final());
+    }
+}
@@ -0,0 +1,380 @@
+//! Regex substitutions in the style of vim's `:substitute`, written `/pattern/replacement/flags`.
+//!
+//! The replacement can refer to capture groups with `$1`, `${1}`, `$name` or `${name}`, `$0`
+//! being the whole match and `$$` a literal `$`. Case conversions apply to the text that follows
+//! them, captures included: `\u` and `\l` change the case of the next character, `\U` and `\L`
+//! of everything up to `\E`.
+
+use crate::{
+    graphemes::next_grapheme_boundary,
+    regex::{Captures, Regex, RegexBuilder},
+    Change, Range, RopeSlice, Selection,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseChange {
+    UpperNext,
+    LowerNext,
+    Upper,
+    Lower,
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Group {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Group(Group),
+    CaseChange(CaseChange),
+}
+
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    regex: Regex,
+    replacement: Vec<Piece>,
+    /// Replace every match instead of only the first one of each line.
+    global: bool,
+}
+
+impl Substitution {
+    /// Parses `/pattern/replacement/flags`. Any punctuation can delimit the parts instead of `/`,
+    /// a delimiter preceded by a backslash is taken literally. The replacement and the flags can
+    /// be left out, and so can the trailing delimiter. The flags are `g` to replace every match
+    /// instead of the first one of each line, and `i` to ignore case.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut chars = input.chars();
+        let delimiter = match chars.next() {
+            Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+            Some(c) => return Err(format!("invalid delimiter '{}'", c)),
+            None => return Err("missing pattern".to_string()),
+        };
+
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            if c == delimiter && parts.len() < 3 {
+                parts.push(String::new());
+                continue;
+            }
+
+            let part = parts.last_mut().unwrap();
+            match c {
+                '\\' => match chars.next() {
+                    Some(c) if c == delimiter => part.push(c),
+                    Some(c) => {
+                        part.push('\\');
+                        part.push(c);
+                    }
+                    None => part.push('\\'),
+                },
+                c => part.push(c),
+            }
+        }
+
+        let pattern = &parts[0];
+        if pattern.is_empty() {
+            return Err("missing pattern".to_string());
+        }
+
+        let mut global = false;
+        let mut case_insensitive = false;
+        for flag in parts.get(2).map_or("", |flags| flags.as_str()).chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                flag => return Err(format!("invalid flag '{}'", flag)),
+            }
+        }
+
+        let regex = RegexBuilder::new(pattern)
+            .multi_line(true)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Ok(Self {
+            regex,
+            replacement: parse_replacement(parts.get(1).map_or("", |part| part.as_str())),
+            global,
+        })
+    }
+
+    /// The replacement of a match.
+    pub fn expand(&self, captures: &Captures) -> String {
+        let mut result = String::new();
+        // the conversion up to `\E`, and the one of the next character which takes precedence
+        let mut case = None;
+        let mut next = None;
+
+        for piece in &self.replacement {
+            let text = match piece {
+                Piece::Text(text) => text.as_str(),
+                Piece::Group(Group::Index(i)) => captures.get(*i).map_or("", |m| m.as_str()),
+                Piece::Group(Group::Name(name)) => captures.name(name).map_or("", |m| m.as_str()),
+                Piece::CaseChange(change) => {
+                    match change {
+                        CaseChange::UpperNext | CaseChange::LowerNext => next = Some(*change),
+                        CaseChange::Upper | CaseChange::Lower => case = Some(*change),
+                        CaseChange::End => case = None,
+                    }
+                    continue;
+                }
+            };
+
+            for c in text.chars() {
+                match next.take().or(case) {
+                    Some(CaseChange::UpperNext) | Some(CaseChange::Upper) => {
+                        result.extend(c.to_uppercase())
+                    }
+                    Some(CaseChange::LowerNext) | Some(CaseChange::Lower) => {
+                        result.extend(c.to_lowercase())
+                    }
+                    _ => result.push(c),
+                }
+            }
+        }
+        result
+    }
+
+    /// The changes replacing the matches within each range of `selection`, or within the whole
+    /// text when the selection only holds cursors.
+    pub fn changes(&self, text: RopeSlice, selection: &Selection) -> Vec<Change> {
+        let is_cursor = |range: &Range| next_grapheme_boundary(text, range.from()) >= range.to();
+        let ranges: Vec<Range> = if selection.iter().all(is_cursor) {
+            vec![Range::new(0, text.len_chars())]
+        } else {
+            selection.iter().copied().collect()
+        };
+
+        let mut changes = Vec::new();
+        for range in ranges {
+            let fragment = range.fragment(text);
+            // the char and byte offsets of the end of the last match
+            let mut pos = range.from();
+            let mut byte_pos = 0;
+            // matches starting before this byte are on a line that already had one
+            let mut next_line = 0;
+
+            for captures in self.regex.captures_iter(&fragment) {
+                let mat = captures.get(0).unwrap();
+                if !self.global && mat.start() < next_line {
+                    continue;
+                }
+
+                let start = pos + fragment[byte_pos..mat.start()].chars().count();
+                pos = start + mat.as_str().chars().count();
+                byte_pos = mat.end();
+
+                let replacement = self.expand(&captures);
+                if start < pos || !replacement.is_empty() {
+                    let replacement = Some(replacement).filter(|text| !text.is_empty());
+                    changes.push((start, pos, replacement.map(Into::into)));
+                }
+
+                if !self.global {
+                    next_line = fragment[mat.start()..]
+                        .find('\n')
+                        .map_or(fragment.len(), |i| mat.start() + i + 1)
+                        .max(mat.end());
+                }
+            }
+        }
+        changes
+    }
+}
+
+fn parse_replacement(replacement: &str) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let change = match chars.next() {
+                    Some('u') => CaseChange::UpperNext,
+                    Some('l') => CaseChange::LowerNext,
+                    Some('U') => CaseChange::Upper,
+                    Some('L') => CaseChange::Lower,
+                    Some('E') | Some('e') => CaseChange::End,
+                    Some('n') => {
+                        text.push('\n');
+                        continue;
+                    }
+                    Some('t') => {
+                        text.push('\t');
+                        continue;
+                    }
+                    Some('r') => {
+                        text.push('\r');
+                        continue;
+                    }
+                    // any other escaped character stands for itself
+                    Some(c) => {
+                        text.push(c);
+                        continue;
+                    }
+                    None => {
+                        text.push('\\');
+                        continue;
+                    }
+                };
+                push_piece(&mut pieces, &mut text, Piece::CaseChange(change));
+            }
+            '$' => {
+                let braced = chars.peek() == Some(&'{');
+                if braced {
+                    chars.next();
+                }
+
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+
+                if braced && chars.peek() == Some(&'}') && !name.is_empty() {
+                    chars.next();
+                } else if braced || name.is_empty() {
+                    // not a group after all, `$$` included
+                    if chars.peek() == Some(&'$') && !braced {
+                        chars.next();
+                    }
+                    text.push('$');
+                    if braced {
+                        text.push('{');
+                    }
+                    text.push_str(&name);
+                    continue;
+                }
+
+                let group = match name.parse() {
+                    Ok(i) => Group::Index(i),
+                    Err(_) => Group::Name(name),
+                };
+                push_piece(&mut pieces, &mut text, Piece::Group(group));
+            }
+            c => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    pieces
+}
+
+/// Pushes `piece` after the text preceding it.
+fn push_piece(pieces: &mut Vec<Piece>, text: &mut String, piece: Piece) {
+    if !text.is_empty() {
+        pieces.push(Piece::Text(std::mem::take(text)));
+    }
+    pieces.push(piece);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Rope, Transaction};
+
+    fn substitute(input: &str, text: &str, selection: &Selection) -> String {
+        let substitution = Substitution::parse(input).unwrap();
+        let mut doc = Rope::from(text);
+        let changes = substitution.changes(doc.slice(..), selection);
+        let transaction = Transaction::change(&doc, changes.into_iter());
+        assert!(transaction.apply(&mut doc));
+        doc.to_string()
+    }
+
+    #[test]
+    fn test_parse_replacement() {
+        assert_eq!(
+            parse_replacement(r"$1-${2}x${name}$$ $a_b\$\u$0\n$"),
+            vec![
+                Piece::Group(Group::Index(1)),
+                Piece::Text("-".to_string()),
+                Piece::Group(Group::Index(2)),
+                Piece::Text("x".to_string()),
+                Piece::Group(Group::Name("name".to_string())),
+                Piece::Text("$ ".to_string()),
+                Piece::Group(Group::Name("a_b".to_string())),
+                Piece::Text("$".to_string()),
+                Piece::CaseChange(CaseChange::UpperNext),
+                Piece::Group(Group::Index(0)),
+                Piece::Text("\n$".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_replacement("${1"),
+            vec![Piece::Text("${1".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Substitution::parse("").is_err());
+        assert!(Substitution::parse("/").is_err());
+        assert!(Substitution::parse("a/b/").is_err());
+        assert!(Substitution::parse("/(/b/").is_err());
+        assert!(Substitution::parse("/a/b/x").is_err());
+        assert!(Substitution::parse("/a").is_ok());
+        assert!(Substitution::parse("|a/b|c|gi").is_ok());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let text = "foo bar foo\nbaz foo\n";
+        let cursor = Selection::point(0);
+
+        // the first match of each line, or all of them with `g`
+        assert_eq!(
+            substitute("/foo/qux/", text, &cursor),
+            "qux bar foo\nbaz qux\n"
+        );
+        assert_eq!(
+            substitute("/foo/qux/g", text, &cursor),
+            "qux bar qux\nbaz qux\n"
+        );
+        assert_eq!(
+            substitute("/FOO/qux/gi", text, &cursor),
+            substitute("/foo/qux/g", text, &cursor)
+        );
+        // deleting, and escaped delimiters
+        assert_eq!(substitute("/ foo", text, &cursor), "foo bar\nbaz\n");
+        assert_eq!(substitute(r"#o#\##g", "a/o", &cursor), "a/#");
+
+        // captures and case conversions
+        assert_eq!(
+            substitute(r"/(\w+) (?P<second>\w+)/${second} $1/", text, &cursor),
+            "bar foo foo\nfoo baz\n"
+        );
+        assert_eq!(
+            substitute(r"/(\w)(\w+)/\u$1\U$2\E!/g", "foo bar", &cursor),
+            "FOO! BAR!"
+        );
+        assert_eq!(substitute(r"/\w+/\L\u$0/g", "FOO bAR", &cursor), "Foo Bar");
+        assert_eq!(
+            substitute(r"/_(\w)/\u$1/g", "snake_case_name", &cursor),
+            "snakeCaseName"
+        );
+
+        // only within the selections, empty matches insert
+        let selection =
+            Selection::new(smallvec::smallvec![Range::new(0, 7), Range::new(12, 19)], 0);
+        assert_eq!(
+            substitute("/^/> /g", text, &selection),
+            "> foo bar foo\n> baz foo\n"
+        );
+        assert_eq!(
+            substitute("/o/0/g", text, &selection),
+            "f00 bar foo\nbaz f00\n"
+        );
+    }
+}
@@ -1,5 +1,10 @@
-use crate::{search, Selection};
+use crate::{search, syntax::Syntax, Selection};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use ropey::RopeSlice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tree_sitter::Node;
 
 pub const PAIRS: &[(char, char)] = &[
     ('(', ')'),
@@ -29,6 +34,65 @@ pub fn get_pair(ch: char) -> (char, char) {
         .unwrap_or((ch, ch))
 }
 
+/// A pair of strings to surround text with, like `Some(` and `)`. Languages can configure them
+/// with `surround-pairs` in `languages.toml`, by the key used to pick them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurroundPair {
+    pub open: String,
+    pub close: String,
+}
+
+impl SurroundPair {
+    pub fn new(open: impl Into<String>, close: impl Into<String>) -> Self {
+        Self {
+            open: open.into(),
+            close: close.into(),
+        }
+    }
+}
+
+/// What the surround commands add, replace or delete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Surround {
+    Pair(SurroundPair),
+    /// An HTML or XML tag. The name is only used to add a tag, any tag is found.
+    Tag(String),
+}
+
+impl Surround {
+    /// The surround picked with `ch`: a key of the language's `pairs` picks that pair, `t` a tag,
+    /// and any other char the pair from [`get_pair`].
+    pub fn from_char(ch: char, pairs: Option<&HashMap<char, SurroundPair>>) -> Self {
+        if let Some(pair) = pairs.and_then(|pairs| pairs.get(&ch)) {
+            return Self::Pair(pair.clone());
+        }
+        if ch == 't' {
+            return Self::Tag(String::new());
+        }
+        let (open, close) = get_pair(ch);
+        Self::Pair(SurroundPair::new(open, close))
+    }
+
+    /// The text inserted before and after the surrounded text.
+    pub fn open_close(&self) -> (String, String) {
+        match self {
+            Self::Pair(pair) => (pair.open.clone(), pair.close.clone()),
+            Self::Tag(tag) => {
+                // attributes only go in the opening tag
+                let name = tag.split_whitespace().next().unwrap_or_default();
+                (format!("<{}>", tag), format!("</{}>", name))
+            }
+        }
+    }
+}
+
+/// The char ranges `(from, to)` of the opening and the closing part of a surround pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SurroundPos {
+    pub open: (usize, usize),
+    pub close: (usize, usize),
+}
+
 /// Find the position of surround pairs of `ch` which can be either a closing
 /// or opening pair. `n` will skip n - 1 pairs (eg. n=2 will discard (only)
 /// the first pair found and keep looking)
@@ -39,7 +103,16 @@ pub fn find_nth_pairs_pos(
     n: usize,
 ) -> Option<(usize, usize)> {
     let (open, close) = get_pair(ch);
+    find_nth_char_pairs_pos(text, open, close, pos, n)
+}
 
+fn find_nth_char_pairs_pos(
+    text: RopeSlice,
+    open: char,
+    close: char,
+    pos: usize,
+    n: usize,
+) -> Option<(usize, usize)> {
     if text.len_chars() < 2 || pos >= text.len_chars() {
         return None;
     }
@@ -75,7 +148,7 @@ fn find_nth_open_pair(
     open: char,
     close: char,
     mut pos: usize,
-    n: usize,
+    mut n: usize,
 ) -> Option<usize> {
     let mut chars = text.chars_at(pos + 1);
 
@@ -83,7 +156,11 @@ fn find_nth_open_pair(
     // cursor being *on* the close character which will get falsely stepped over
     // if not skipped here
     if chars.prev()? == open {
-        return Some(pos);
+        // the pair opened by the cursor is the first one
+        if n == 1 {
+            return Some(pos);
+        }
+        n -= 1;
     }
 
     for _ in 0..n {
@@ -114,7 +191,7 @@ fn find_nth_close_pair(
     open: char,
     close: char,
     mut pos: usize,
-    n: usize,
+    mut n: usize,
 ) -> Option<usize> {
     if pos >= text.len_chars() {
         return None;
@@ -123,7 +200,10 @@ fn find_nth_close_pair(
     let mut chars = text.chars_at(pos);
 
     if chars.next()? == close {
-        return Some(pos);
+        if n == 1 {
+            return Some(pos);
+        }
+        n -= 1;
     }
 
     for _ in 0..n {
@@ -148,24 +228,219 @@ fn find_nth_close_pair(
     Some(pos)
 }
 
-/// Find position of surround characters around every cursor. Returns None
-/// if any positions overlap. Note that the positions are in a flat Vec.
-/// Use get_surround_pos().chunks(2) to get matching pairs of surround positions.
-/// `ch` can be either closing or opening pair.
+/// Find the position of the `n`-th `surround` around `pos`. Tags are looked up in the syntax
+/// tree when there is one.
+pub fn find_nth_surround_pos(
+    text: RopeSlice,
+    surround: &Surround,
+    syntax: Option<&Syntax>,
+    pos: usize,
+    n: usize,
+) -> Option<SurroundPos> {
+    find_nth_surround_pos_impl(text, surround, syntax, &mut None, pos, n)
+}
+
+/// Like [find_nth_surround_pos], `text_tags` holds the tag pairs of the text once it has been
+/// scanned for them, so the text is scanned at most once for several positions.
+fn find_nth_surround_pos_impl(
+    text: RopeSlice,
+    surround: &Surround,
+    syntax: Option<&Syntax>,
+    text_tags: &mut Option<Vec<SurroundPos>>,
+    pos: usize,
+    n: usize,
+) -> Option<SurroundPos> {
+    match surround {
+        Surround::Pair(pair) => {
+            let mut open = pair.open.chars();
+            let mut close = pair.close.chars();
+            match (open.next(), open.next(), close.next(), close.next()) {
+                (Some(open), None, Some(close), None) => {
+                    let (open, close) = find_nth_char_pairs_pos(text, open, close, pos, n)?;
+                    Some(SurroundPos {
+                        open: (open, open + 1),
+                        close: (close, close + 1),
+                    })
+                }
+                _ => find_nth_str_pairs_pos(text, pair, pos, n),
+            }
+        }
+        Surround::Tag(_) => syntax
+            .and_then(|syntax| find_nth_tag_pos_in_tree(text, syntax, pos, n))
+            .or_else(|| {
+                let tags = text_tags.get_or_insert_with(|| tag_pairs(text));
+                find_nth_tag_pos(tags, pos, n)
+            }),
+    }
+}
+
+/// Whether the text at `pos` starts with `s`.
+fn matches_at(text: RopeSlice, pos: usize, s: &str) -> bool {
+    let end = pos + s.chars().count();
+    end <= text.len_chars() && text.slice(pos..end) == s
+}
+
+fn find_nth_str_pairs_pos(
+    text: RopeSlice,
+    pair: &SurroundPair,
+    pos: usize,
+    n: usize,
+) -> Option<SurroundPos> {
+    let open_len = pair.open.chars().count();
+    let close_len = pair.close.chars().count();
+    let open_last = pair.open.chars().last()?;
+    let close_first = pair.close.chars().next()?;
+
+    // a cursor on the opening part, like the `Some` of `Some(x)`, is on its last char
+    let pos = (pos.saturating_sub(open_len - 1)..=pos)
+        .find(|start| matches_at(text, *start, &pair.open))
+        .map_or(pos, |start| start + open_len - 1);
+
+    if close_len == 1 && open_last != close_first && get_pair(open_last) == (open_last, close_first)
+    {
+        // the opening part ends with a bracket: find the enclosing brackets so nested ones are
+        // skipped, and keep those preceded by the rest of the opening part
+        let mut found = 0;
+        for skip in 1.. {
+            let (open, close) = find_nth_char_pairs_pos(text, open_last, close_first, pos, skip)?;
+            let start = (open + 1).checked_sub(open_len);
+            if start.map_or(false, |start| matches_at(text, start, &pair.open)) {
+                found += 1;
+                if found == n {
+                    return Some(SurroundPos {
+                        open: (open + 1 - open_len, open + 1),
+                        close: (close, close + 1),
+                    });
+                }
+            }
+        }
+    }
+
+    // otherwise pairs don't nest, and the n-th occurrences on either side are used
+    let open = (0..=pos)
+        .rev()
+        .filter(|start| matches_at(text, *start, &pair.open))
+        .nth(n - 1)?;
+    let close = (pos.max(open + open_len)..text.len_chars())
+        .filter(|start| matches_at(text, *start, &pair.close))
+        .nth(n - 1)?;
+    Some(SurroundPos {
+        open: (open, open + open_len),
+        close: (close, close + close_len),
+    })
+}
+
+/// The start and end tag of an element node, like the `element` of HTML or the `jsx_element`
+/// of JSX.
+fn tag_children(node: Node) -> Option<(Node, Node)> {
+    let start = node.child(0)?;
+    let end = node.child(node.child_count().checked_sub(1)?)?;
+    let is_start = start.kind().ends_with("start_tag") || start.kind().ends_with("opening_element");
+    let is_end = end.kind().ends_with("end_tag") || end.kind().ends_with("closing_element");
+    if is_start && is_end && start != end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+fn find_nth_tag_pos_in_tree(
+    text: RopeSlice,
+    syntax: &Syntax,
+    pos: usize,
+    n: usize,
+) -> Option<SurroundPos> {
+    let byte = text.char_to_byte(pos);
+    let mut tags = syntax.layers_for_byte_range(byte, byte).flat_map(|layer| {
+        let node = layer
+            .tree()
+            .root_node()
+            .descendant_for_byte_range(byte, byte);
+        std::iter::successors(node, |node| node.parent()).filter_map(tag_children)
+    });
+
+    let (start, end) = tags.nth(n - 1)?;
+    Some(SurroundPos {
+        open: (
+            text.byte_to_char(start.start_byte()),
+            text.byte_to_char(start.end_byte()),
+        ),
+        close: (
+            text.byte_to_char(end.start_byte()),
+            text.byte_to_char(end.end_byte()),
+        ),
+    })
+}
+
+static TAG_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<(/)?([A-Za-z][\w:.-]*)[^<>]*?(/)?>").unwrap());
+
+/// Find the pairs of matching tags by scanning the text, in the order they're closed. Tags that
+/// are never closed, like `<br>`, are skipped.
+fn tag_pairs(text: RopeSlice) -> Vec<SurroundPos> {
+    let string = text.to_string();
+    // the name and char range of the tags not closed yet
+    let mut open_tags: Vec<(&str, (usize, usize))> = Vec::new();
+    let mut pairs = Vec::new();
+
+    for captures in TAG_REGEX.captures_iter(&string) {
+        let tag = captures.get(0).unwrap();
+        let range = (text.byte_to_char(tag.start()), text.byte_to_char(tag.end()));
+        let name = captures.get(2).unwrap().as_str();
+
+        if captures.get(3).is_some() {
+            // self-closing
+            continue;
+        }
+        if captures.get(1).is_none() {
+            open_tags.push((name, range));
+            continue;
+        }
+        if let Some(idx) = open_tags.iter().rposition(|(open, _)| *open == name) {
+            let open = open_tags[idx].1;
+            open_tags.truncate(idx);
+            pairs.push(SurroundPos { open, close: range });
+        }
+    }
+
+    pairs
+}
+
+/// Find the `n`-th pair of `tags` around `pos`.
+fn find_nth_tag_pos(tags: &[SurroundPos], pos: usize, n: usize) -> Option<SurroundPos> {
+    // inner pairs close first
+    tags.iter()
+        .filter(|tag| tag.open.0 <= pos && pos < tag.close.1)
+        .nth(n - 1)
+        .copied()
+}
+
+/// Find the position of `surround` around every cursor. Returns None
+/// if any positions overlap.
 pub fn get_surround_pos(
     text: RopeSlice,
     selection: &Selection,
-    ch: char,
+    surround: &Surround,
+    syntax: Option<&Syntax>,
     skip: usize,
-) -> Option<Vec<usize>> {
-    let mut change_pos = Vec::new();
+) -> Option<Vec<SurroundPos>> {
+    let mut change_pos: Vec<SurroundPos> = Vec::new();
+    let mut text_tags = None;
 
     for range in selection {
-        let (open_pos, close_pos) = find_nth_pairs_pos(text, ch, range.head, skip)?;
-        if change_pos.contains(&open_pos) || change_pos.contains(&close_pos) {
+        let cursor = range.cursor(text);
+        let pos = find_nth_surround_pos_impl(text, surround, syntax, &mut text_tags, cursor, skip)?;
+        let overlaps = |(from, to): (usize, usize)| {
+            change_pos.iter().any(|other| {
+                [other.open, other.close]
+                    .iter()
+                    .any(|other| from < other.1 && other.0 < to)
+            })
+        };
+        if overlaps(pos.open) || overlaps(pos.close) {
             return None;
         }
-        change_pos.extend_from_slice(&[open_pos, close_pos]);
+        change_pos.push(pos);
     }
     Some(change_pos)
 }
@@ -202,6 +477,9 @@ mod test {
         assert_eq!(find_nth_pairs_pos(slice, '(', 13, 1), Some((10, 15)));
         assert_eq!(find_nth_pairs_pos(slice, '(', 13, 2), Some((4, 21)));
         assert_eq!(find_nth_pairs_pos(slice, '(', 13, 3), Some((0, 27)));
+        // cursor on the brackets of (good)
+        assert_eq!(find_nth_pairs_pos(slice, '(', 10, 2), Some((4, 21)));
+        assert_eq!(find_nth_pairs_pos(slice, '(', 15, 2), Some((4, 21)));
     }
 
     #[test]
@@ -252,9 +530,11 @@ mod test {
 
         // cursor on s[o]me, c[h]ars, newl[i]ne
         assert_eq!(
-            get_surround_pos(slice, &selection, '(', 1)
+            get_surround_pos(slice, &selection, &Surround::from_char('(', None), None, 1)
                 .unwrap()
-                .as_slice(),
+                .iter()
+                .flat_map(|pos| vec![pos.open.0, pos.close.0])
+                .collect::<Vec<_>>(),
             &[0, 5, 7, 13, 15, 23]
         );
     }
@@ -269,7 +549,7 @@ mod test {
 
         // cursor on s[o]me, c[h]ars
         assert_eq!(
-            get_surround_pos(slice, &selection, '(', 1),
+            get_surround_pos(slice, &selection, &Surround::from_char('(', None), None, 1),
             None // different surround chars
         );
 
@@ -279,8 +559,130 @@ mod test {
         );
         // cursor on [x]x, newli[n]e
         assert_eq!(
-            get_surround_pos(slice, &selection, '(', 1),
+            get_surround_pos(slice, &selection, &Surround::from_char('(', None), None, 1),
             None // overlapping surround chars
         );
     }
+
+    fn pos(open: (usize, usize), close: (usize, usize)) -> Option<SurroundPos> {
+        Some(SurroundPos { open, close })
+    }
+
+    #[test]
+    fn test_find_nth_str_pairs_pos() {
+        let doc = Rope::from("Some(foo(Some(x)), **bold**)");
+        let slice = doc.slice(..);
+        let some = Surround::Pair(SurroundPair::new("Some(", ")"));
+
+        // cursor on [x]
+        assert_eq!(
+            find_nth_surround_pos(slice, &some, None, 14, 1),
+            pos((9, 14), (15, 16))
+        );
+        // the parentheses of foo(...) are skipped
+        assert_eq!(
+            find_nth_surround_pos(slice, &some, None, 14, 2),
+            pos((0, 5), (27, 28))
+        );
+        // cursor on the name
+        assert_eq!(
+            find_nth_surround_pos(slice, &some, None, 10, 1),
+            pos((9, 14), (15, 16))
+        );
+        assert_eq!(find_nth_surround_pos(slice, &some, None, 14, 3), None);
+
+        let bold = Surround::Pair(SurroundPair::new("**", "**"));
+        assert_eq!(
+            find_nth_surround_pos(slice, &bold, None, 22, 1),
+            pos((19, 21), (25, 27))
+        );
+    }
+
+    #[test]
+    fn test_find_nth_tag_pos() {
+        let doc = Rope::from("<div class=\"a\"><p>one<br>two<img/></p></div>");
+        let slice = doc.slice(..);
+        let tag = Surround::from_char('t', None);
+
+        // cursor on t[w]o
+        assert_eq!(
+            find_nth_surround_pos(slice, &tag, None, 26, 1),
+            pos((15, 18), (34, 38))
+        );
+        assert_eq!(
+            find_nth_surround_pos(slice, &tag, None, 26, 2),
+            pos((0, 15), (38, 44))
+        );
+        assert_eq!(find_nth_surround_pos(slice, &tag, None, 26, 3), None);
+        // cursor on the opening tag
+        assert_eq!(
+            find_nth_surround_pos(slice, &tag, None, 3, 1),
+            pos((0, 15), (38, 44))
+        );
+        // a cursor in each of two sibling pairs
+        let doc = Rope::from("<p>a</p><b>b</b>");
+        let selection = Selection::new(
+            SmallVec::from_slice(&[Range::point(3), Range::point(11)]),
+            0,
+        );
+        assert_eq!(
+            get_surround_pos(doc.slice(..), &selection, &tag, None, 1),
+            [pos((0, 3), (4, 8)), pos((8, 11), (12, 16))]
+                .iter()
+                .copied()
+                .collect()
+        );
+
+        assert_eq!(tag.open_close(), ("<>".to_string(), "</>".to_string()));
+        assert_eq!(
+            Surround::Tag("div class=\"a\"".to_string()).open_close(),
+            ("<div class=\"a\">".to_string(), "</div>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_find_nth_tag_pos_in_tree() {
+        use crate::syntax::{Configuration, Loader};
+        use std::sync::Arc;
+
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "html"
+            scope = "text.html.basic"
+            file-types = ["html"]
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let loader = Arc::new(Loader::new(config));
+
+        // set runtime path so we can find the queries
+        let mut runtime = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        runtime.push("../runtime");
+        std::env::set_var("HELIX_RUNTIME", runtime.to_str().unwrap());
+
+        // the `a>` in the attribute trips up the text scanning
+        let doc = Rope::from("<div title=\"a>\"><p>text</p></div>");
+        let language_config = loader.language_config_for_scope("text.html.basic").unwrap();
+        let highlight_config = language_config.highlight_config(&[]).unwrap();
+        let syntax = Syntax::new(&doc, highlight_config, loader);
+        let slice = doc.slice(..);
+        let tag = Surround::from_char('t', None);
+
+        // cursor on t[e]xt
+        assert_eq!(
+            find_nth_surround_pos(slice, &tag, Some(&syntax), 20, 1),
+            pos((16, 19), (23, 27))
+        );
+        assert_eq!(
+            find_nth_surround_pos(slice, &tag, Some(&syntax), 20, 2),
+            pos((0, 16), (27, 33))
+        );
+        // the text scanning is confused by the attribute
+        assert_ne!(
+            find_nth_surround_pos(slice, &tag, None, 20, 2),
+            pos((0, 16), (27, 33))
+        );
+    }
 }
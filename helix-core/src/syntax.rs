@@ -1,6 +1,8 @@
 use crate::{
+    auto_pairs::AutoPairs,
     chars::char_is_line_ending,
     regex::Regex,
+    surround::SurroundPair,
     transaction::{ChangeSet, Operation},
     Rope, RopeSlice, Tendril,
 };
@@ -12,20 +14,76 @@ use arc_swap::ArcSwap;
 use std::{
     borrow::Cow,
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     fmt,
     path::Path,
     sync::Arc,
 };
 
 use once_cell::sync::{Lazy, OnceCell};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use slotmap::HopSlotMap;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Configuration {
     pub language: Vec<LanguageConfiguration>,
 }
 
+/// Merges the `languages.toml` value `right` over `left`. The `[[language]]` entries are matched
+/// up by their name and merged field by field, the others are appended.
+pub fn merge_toml_values(left: toml::Value, right: toml::Value) -> toml::Value {
+    use toml::Value;
+
+    match (left, right) {
+        (Value::Table(mut left), Value::Table(right)) => {
+            for (key, right_value) in right {
+                let value = match (key.as_str(), left.remove(&key)) {
+                    ("language", Some(Value::Array(mut languages))) => {
+                        for language in right_value.as_array().cloned().unwrap_or_default() {
+                            let name = language.get("name");
+                            match languages
+                                .iter_mut()
+                                .find(|existing| name.is_some() && existing.get("name") == name)
+                            {
+                                Some(existing) => {
+                                    *existing = merge_toml_tables(existing.clone(), language)
+                                }
+                                None => languages.push(language),
+                            }
+                        }
+                        Value::Array(languages)
+                    }
+                    (_, Some(left_value)) => merge_toml_tables(left_value, right_value),
+                    (_, None) => right_value,
+                };
+                left.insert(key, value);
+            }
+            Value::Table(left)
+        }
+        (_, right) => right,
+    }
+}
+
+/// Merges the tables of `right` over the ones of `left` key by key, other values of `right`
+/// replace the ones of `left`.
+fn merge_toml_tables(left: toml::Value, right: toml::Value) -> toml::Value {
+    use toml::Value;
+
+    match (left, right) {
+        (Value::Table(mut left), Value::Table(right)) => {
+            for (key, right_value) in right {
+                let value = match left.remove(&key) {
+                    Some(left_value) => merge_toml_tables(left_value, right_value),
+                    None => right_value,
+                };
+                left.insert(key, value);
+            }
+            Value::Table(left)
+        }
+        (_, right) => right,
+    }
+}
+
 // largely based on tree-sitter/cli/src/loader.rs
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -34,17 +92,48 @@ pub struct LanguageConfiguration {
     pub(crate) language_id: String,
     pub scope: String,           // source.rust
     pub file_types: Vec<String>, // filename ends_with? <Gemfile, rb, etc>
-    pub roots: Vec<String>,      // these indicate project roots <.git, Cargo.toml>
+    /// Interpreters picked from the shebang of files with an unknown name, e.g. `python` for
+    /// `#!/usr/bin/env python3`. Version numbers at the end of the interpreter are ignored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shebangs: Vec<String>,
+    pub roots: Vec<String>, // these indicate project roots <.git, Cargo.toml>
     pub comment_token: Option<String>,
+    /// Either a single `{ start = "/*", end = "*/" }` table or a list of them, the first one is
+    /// used to comment.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_block_comment_tokens"
+    )]
+    pub block_comment_tokens: Option<Vec<BlockCommentToken>>,
     pub config: Option<String>,
 
     #[serde(default)]
     pub auto_format: bool,
+    /// Wrap long lines at the width of the view instead of scrolling horizontally.
+    #[serde(default)]
+    pub soft_wrap: bool,
+
+    /// Characters inserted in pairs, `auto-pairs = false` disables them.
+    #[serde(
+        default = "default_auto_pairs",
+        skip_serializing,
+        deserialize_with = "deserialize_auto_pairs"
+    )]
+    pub auto_pairs: Option<AutoPairs>,
+    /// Multi character surround pairs by the key picking them, e.g.
+    /// `surround-pairs = { s = { open = "Some(", close = ")" } }`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub surround_pairs: Option<HashMap<char, SurroundPair>>,
 
     // content_regex
-    // injection_regex
-    // first_line_regex
-    //
+    /// Matches the names used to inject this language into others, e.g. the language of a
+    /// markdown code block or an `injection.language` capture.
+    #[serde(default, skip_serializing, deserialize_with = "deserialize_regex")]
+    pub injection_regex: Option<Regex>,
+    /// Matches the first line of files with an unknown name, e.g. `^<\?xml`.
+    #[serde(default, skip_serializing, deserialize_with = "deserialize_regex")]
+    pub first_line_regex: Option<Regex>,
     #[serde(skip)]
     pub(crate) highlight_config: OnceCell<Option<Arc<HighlightConfiguration>>>,
     // tags_config OnceCell<> https://github.com/tree-sitter/tree-sitter/pull/583
@@ -55,6 +144,65 @@ pub struct LanguageConfiguration {
 
     #[serde(skip)]
     pub(crate) indent_query: OnceCell<Option<IndentQuery>>,
+    #[serde(skip)]
+    pub(crate) textobject_query: OnceCell<Option<TextObjectQuery>>,
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|buf| Regex::new(&buf).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockCommentToken {
+    pub start: String,
+    pub end: String,
+}
+
+fn deserialize_block_comment_tokens<'de, D>(
+    deserializer: D,
+) -> Result<Option<Vec<BlockCommentToken>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BlockCommentTokens {
+        Single(BlockCommentToken),
+        Multiple(Vec<BlockCommentToken>),
+    }
+
+    Ok(Some(match BlockCommentTokens::deserialize(deserializer)? {
+        BlockCommentTokens::Single(token) => vec![token],
+        BlockCommentTokens::Multiple(tokens) => tokens,
+    })
+    .filter(|tokens| !tokens.is_empty()))
+}
+
+fn default_auto_pairs() -> Option<AutoPairs> {
+    Some(AutoPairs::default())
+}
+
+fn deserialize_auto_pairs<'de, D>(deserializer: D) -> Result<Option<AutoPairs>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AutoPairConfig {
+        Enable(bool),
+        Pairs(HashMap<char, char>),
+    }
+
+    Ok(match AutoPairConfig::deserialize(deserializer)? {
+        AutoPairConfig::Enable(false) => None,
+        AutoPairConfig::Enable(true) => default_auto_pairs(),
+        AutoPairConfig::Pairs(pairs) => Some(AutoPairs::new(pairs)),
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -84,6 +232,37 @@ pub struct IndentQuery {
     pub outdent: HashSet<String>,
 }
 
+#[derive(Debug)]
+pub struct TextObjectQuery {
+    pub query: Query,
+}
+
+impl TextObjectQuery {
+    /// Run the query on the given node and return the nodes captured as `capture_name`
+    /// (`function.inside`, `class.around`, etc).
+    pub fn capture_nodes<'a>(
+        &'a self,
+        capture_name: &str,
+        node: Node<'a>,
+        slice: RopeSlice<'a>,
+        cursor: &'a mut QueryCursor,
+    ) -> Option<impl Iterator<Item = Node<'a>>> {
+        let capture_idx = self
+            .query
+            .capture_names()
+            .iter()
+            .position(|name| name == capture_name)? as u32;
+        let nodes = cursor
+            .captures(&self.query, node, move |n: Node| node_to_bytes(n, slice))
+            .filter_map(move |(mat, idx)| {
+                let capture = mat.captures[idx];
+                (capture.index == capture_idx).then(|| capture.node)
+            });
+
+        Some(nodes)
+    }
+}
+
 fn load_runtime_file(language: &str, filename: &str) -> Result<String, std::io::Error> {
     let path = crate::RUNTIME_DIR
         .join("queries")
@@ -178,6 +357,23 @@ impl LanguageConfiguration {
             .as_ref()
     }
 
+    pub fn textobject_query(&self) -> Option<&TextObjectQuery> {
+        self.textobject_query
+            .get_or_init(|| {
+                let language = self.language_id.to_ascii_lowercase();
+
+                let query_text = read_query(&language, "textobjects.scm");
+                if query_text.is_empty() {
+                    return None;
+                }
+                let grammar = get_language(&crate::RUNTIME_DIR, &self.language_id).ok()?;
+                let query = Query::new(grammar, &query_text).ok()?;
+
+                Some(TextObjectQuery { query })
+            })
+            .as_ref()
+    }
+
     pub fn scope(&self) -> &str {
         &self.scope
     }
@@ -188,6 +384,10 @@ pub struct Loader {
     // highlight_names ?
     language_configs: Vec<Arc<LanguageConfiguration>>,
     language_config_ids_by_file_type: HashMap<String, usize>, // Vec<usize>
+    language_config_ids_by_shebang: HashMap<String, usize>,
+
+    /// The highlight names of the current theme, used to configure injected languages.
+    scopes: ArcSwap<Vec<String>>,
 }
 
 impl Loader {
@@ -195,6 +395,8 @@ impl Loader {
         let mut loader = Self {
             language_configs: Vec::new(),
             language_config_ids_by_file_type: HashMap::new(),
+            language_config_ids_by_shebang: HashMap::new(),
+            scopes: ArcSwap::from_pointee(Vec::new()),
         };
 
         for config in config.language {
@@ -208,6 +410,12 @@ impl Loader {
                     .insert(file_type.clone(), language_id);
             }
 
+            for shebang in &config.shebangs {
+                loader
+                    .language_config_ids_by_shebang
+                    .insert(shebang.clone(), language_id);
+            }
+
             loader.language_configs.push(Arc::new(config));
         }
 
@@ -232,6 +440,77 @@ impl Loader {
         // TODO: content_regex handling conflict resolution
     }
 
+    /// Find the language configuration of a file. A modeline takes precedence over the file
+    /// name, files with an unknown name fall back to their shebang and first line.
+    pub fn language_config_for_file(
+        &self,
+        path: Option<&Path>,
+        text: RopeSlice,
+    ) -> Option<Arc<LanguageConfiguration>> {
+        self.language_config_for_modeline(text)
+            .or_else(|| path.and_then(|path| self.language_config_for_file_name(path)))
+            .or_else(|| self.language_config_for_shebang(text))
+            .or_else(|| self.language_config_for_first_line(text))
+    }
+
+    /// Find the language configuration named by a vim (`vim: set ft=python:`) or emacs
+    /// (`-*- mode: python -*-`) modeline. The name is either the name of the language or fully
+    /// matched by its `injection-regex`.
+    pub fn language_config_for_modeline(
+        &self,
+        text: RopeSlice,
+    ) -> Option<Arc<LanguageConfiguration>> {
+        let name = modeline_language(text)?;
+        self.language_configs
+            .iter()
+            .find(|config| {
+                config.language_id.eq_ignore_ascii_case(&name)
+                    || config
+                        .injection_regex
+                        .as_ref()
+                        .and_then(|regex| regex.find(&name))
+                        .map_or(false, |mat| mat.start() == 0 && mat.end() == name.len())
+            })
+            .cloned()
+    }
+
+    /// Find the language configuration for the interpreter of the shebang on the first line.
+    pub fn language_config_for_shebang(
+        &self,
+        text: RopeSlice,
+    ) -> Option<Arc<LanguageConfiguration>> {
+        let line = Cow::from(text.line(0));
+        let interpreter = shebang_interpreter(&line)?;
+        let configuration_id = self
+            .language_config_ids_by_shebang
+            .get(interpreter)
+            .or_else(|| {
+                // python3.9 is python
+                let interpreter =
+                    interpreter.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.');
+                self.language_config_ids_by_shebang.get(interpreter)
+            });
+
+        configuration_id.and_then(|&id| self.language_configs.get(id).cloned())
+    }
+
+    /// Find the language configuration whose `first-line-regex` matches the first line.
+    pub fn language_config_for_first_line(
+        &self,
+        text: RopeSlice,
+    ) -> Option<Arc<LanguageConfiguration>> {
+        let line = Cow::from(text.line(0));
+        self.language_configs
+            .iter()
+            .find(|config| {
+                config
+                    .first_line_regex
+                    .as_ref()
+                    .map_or(false, |regex| regex.is_match(&line))
+            })
+            .cloned()
+    }
+
     pub fn language_config_for_scope(&self, scope: &str) -> Option<Arc<LanguageConfiguration>> {
         self.language_configs
             .iter()
@@ -239,9 +518,106 @@ impl Loader {
             .cloned()
     }
 
+    /// Find the language configuration for the name of an injected language: the longest
+    /// `injection-regex` match, falling back to the `source.<name>` scope.
+    pub fn language_config_for_injection_string(
+        &self,
+        string: &str,
+    ) -> Option<Arc<LanguageConfiguration>> {
+        self.language_configs
+            .iter()
+            .filter_map(|config| {
+                let mat = config.injection_regex.as_ref()?.find(string)?;
+                Some((mat.end() - mat.start(), config))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, config)| config.clone())
+            .or_else(|| self.language_config_for_scope(&format!("source.{}", string)))
+    }
+
     pub fn language_configs_iter(&self) -> impl Iterator<Item = &Arc<LanguageConfiguration>> {
         self.language_configs.iter()
     }
+
+    /// Set the highlight names of the theme and reconfigure the already loaded languages.
+    pub fn set_scopes(&self, scopes: Vec<String>) {
+        for config in self
+            .language_configs
+            .iter()
+            .filter(|config| config.is_highlight_initialized())
+        {
+            config.reconfigure(&scopes);
+        }
+        self.scopes.store(Arc::new(scopes));
+    }
+
+    pub fn scopes(&self) -> arc_swap::Guard<Arc<Vec<String>>> {
+        self.scopes.load()
+    }
+}
+
+/// The name of the interpreter of a `#!/usr/bin/python3` or `#!/usr/bin/env -S python3 -u`
+/// shebang.
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line
+        .strip_prefix("#!")?
+        .split_whitespace()
+        .map(|word| word.rsplit('/').next().unwrap_or(word));
+    let interpreter = words.next()?;
+    if interpreter == "env" {
+        // skip the options and variables passed to env
+        words.find(|word| !word.starts_with('-') && !word.contains('='))
+    } else {
+        Some(interpreter)
+    }
+}
+
+/// The language named by a modeline: an emacs one on the first line, or on the second one after
+/// a shebang, or a vim one within the first or last five lines.
+fn modeline_language(text: RopeSlice) -> Option<String> {
+    static VIM_MODELINE_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(?:^|\s)(?:vi|vim|ex):(?:.*[\s:])?(?:ft|filetype|syntax)=([\w+#-]+)").unwrap()
+    });
+
+    let emacs_lines = if text.line(0).chars().take(2).eq("#!".chars()) {
+        0..2
+    } else {
+        0..1
+    };
+    let emacs_mode = emacs_lines
+        .filter(|line| *line < text.len_lines())
+        .find_map(|line| emacs_mode(&Cow::from(text.line(line))));
+    if emacs_mode.is_some() {
+        return emacs_mode;
+    }
+
+    let len_lines = text.len_lines();
+    (0..len_lines.min(5))
+        .chain(len_lines.saturating_sub(5).max(5)..len_lines)
+        .find_map(|line| {
+            let line = Cow::from(text.line(line));
+            let captures = VIM_MODELINE_REGEX.captures(&line)?;
+            Some(captures[1].to_string())
+        })
+}
+
+/// The mode of an emacs `-*- mode: python; coding: utf-8 -*-` or `-*- python -*-` modeline.
+fn emacs_mode(line: &str) -> Option<String> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let variables = line[start..end].trim();
+
+    if !variables.contains(':') {
+        return Some(variables.to_lowercase()).filter(|mode| !mode.is_empty());
+    }
+    variables.split(';').find_map(|variable| {
+        let (name, value) = variable.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("mode") {
+            Some(value.trim().to_lowercase())
+        } else {
+            None
+        }
+    })
 }
 
 pub struct TsParser {
@@ -263,11 +639,18 @@ thread_local! {
     })
 }
 
+slotmap::new_key_type! {
+    /// Identifies a [`LanguageLayer`] of a [`Syntax`].
+    pub struct LayerId;
+}
+
+/// The syntax tree of a document: a root layer parsed with the document's language and a layer
+/// for each injected language (JS inside of HTML, Rust inside of macro invocations, etc).
 #[derive(Debug)]
 pub struct Syntax {
-    config: Arc<HighlightConfiguration>,
-
-    root_layer: LanguageLayer,
+    layers: HopSlotMap<LayerId, LanguageLayer>,
+    root: LayerId,
+    loader: Arc<Loader>,
 }
 
 fn byte_range_to_str(range: std::ops::Range<usize>, source: RopeSlice) -> Cow<str> {
@@ -287,142 +670,430 @@ fn node_to_bytes<'a>(node: Node, source: RopeSlice<'a>) -> Cow<'a, [u8]> {
 }
 
 impl Syntax {
-    // buffer, grammar, config, grammars, sync_timeout?
-    pub fn new(
-        /*language: Lang,*/ source: &Rope,
-        config: Arc<HighlightConfiguration>,
-    ) -> Self {
-        let root_layer = LanguageLayer { tree: None };
+    pub fn new(source: &Rope, config: Arc<HighlightConfiguration>, loader: Arc<Loader>) -> Self {
+        let root_layer = LanguageLayer {
+            tree: None,
+            config,
+            language_config: None,
+            depth: 0,
+            parent: None,
+            ranges: vec![Range {
+                start_byte: 0,
+                end_byte: usize::MAX,
+                start_point: Point::new(0, 0),
+                end_point: Point::new(usize::MAX, usize::MAX),
+            }],
+            moved: false,
+            modified: false,
+        };
 
-        // track markers of injections
-        // track scope_descriptor: a Vec of scopes for item in tree
+        let mut layers = HopSlotMap::default();
+        let root = layers.insert(root_layer);
 
         let mut syntax = Self {
-            // grammar,
-            config,
-            root_layer,
+            layers,
+            root,
+            loader,
         };
 
-        // update root layer
-        PARSER.with(|ts_parser| {
-            // TODO: handle the returned `Result` properly.
-            let _ = syntax.root_layer.parse(
-                &mut ts_parser.borrow_mut(),
-                &syntax.config,
-                source,
-                0,
-                vec![Range {
-                    start_byte: 0,
-                    end_byte: usize::MAX,
-                    start_point: Point::new(0, 0),
-                    end_point: Point::new(usize::MAX, usize::MAX),
-                }],
-            );
-        });
+        // TODO: handle the returned `Result` properly.
+        let _ = syntax.update(source, source, &ChangeSet::new(source));
         syntax
     }
 
+    /// Update the syntax tree after `changeset` was applied to `old_source`. Layers touched by
+    /// the changes are reparsed, then the injections of the reparsed layers are recomputed from
+    /// the root down, where their trees changed: the layer of an injection found again is kept,
+    /// and edited when its ranges changed, new injections get a layer and layers whose injection
+    /// went away are dropped.
     pub fn update(
         &mut self,
         old_source: &Rope,
         source: &Rope,
         changeset: &ChangeSet,
     ) -> Result<(), Error> {
+        let edits = LanguageLayer::generate_edits(old_source.slice(..), changeset);
+
+        // Use the edits to update the ranges of all the injected layers.
+        if !edits.is_empty() {
+            for layer in self.layers.values_mut() {
+                // The root layer always covers the whole document.
+                if layer.parent.is_none() {
+                    layer.modified = true;
+                    continue;
+                }
+
+                for range in &mut layer.ranges {
+                    // The edits are in terms of the old text, apply them back to front so the
+                    // earlier positions remain valid.
+                    // Roughly based on tree-sitter's `ts_subtree_edit`.
+                    for edit in edits.iter().rev() {
+                        let is_pure_insertion = edit.old_end_byte == edit.start_byte;
+
+                        // the edit is after the range
+                        if edit.start_byte > range.end_byte {
+                            continue;
+                        }
+
+                        if edit.old_end_byte < range.start_byte {
+                            // the edit is before the range, shift the entire range
+                            range.start_byte =
+                                edit.new_end_byte + (range.start_byte - edit.old_end_byte);
+                            range.start_point = point_add(
+                                edit.new_end_position,
+                                point_sub(range.start_point, edit.old_end_position),
+                            );
+                            range.end_byte = edit
+                                .new_end_byte
+                                .saturating_add(range.end_byte - edit.old_end_byte);
+                            range.end_point = point_add(
+                                edit.new_end_position,
+                                point_sub(range.end_point, edit.old_end_position),
+                            );
+                            layer.moved = true;
+                        } else if edit.start_byte < range.start_byte {
+                            // the edit starts before the range and extends into it
+                            range.start_byte = edit.new_end_byte;
+                            range.start_point = edit.new_end_position;
+                            range.end_byte = range
+                                .end_byte
+                                .saturating_sub(edit.old_end_byte)
+                                .saturating_add(edit.new_end_byte);
+                            range.end_point = point_add(
+                                edit.new_end_position,
+                                point_sub(range.end_point, edit.old_end_position),
+                            );
+                            layer.modified = true;
+                        } else if edit.start_byte == range.start_byte && is_pure_insertion {
+                            // an insertion right at the start of the range, shift the start
+                            range.start_byte = edit.new_end_byte;
+                            range.start_point = edit.new_end_position;
+                            layer.moved = true;
+                        } else {
+                            // the edit is inside of the range
+                            range.end_byte = range
+                                .end_byte
+                                .saturating_sub(edit.old_end_byte)
+                                .saturating_add(edit.new_end_byte);
+                            range.end_point = point_add(
+                                edit.new_end_position,
+                                point_sub(range.end_point, edit.old_end_position),
+                            );
+                            layer.modified = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let layers = &mut self.layers;
+        let loader = &self.loader;
+        let scopes = loader.scopes();
+        let injection_callback = |language: &str| {
+            let language_config = loader.language_config_for_injection_string(language)?;
+            let config = language_config.highlight_config(&scopes)?;
+            Some((language_config, config))
+        };
+
+        let mut queue = VecDeque::new();
+        queue.push_back(self.root);
+
         PARSER.with(|ts_parser| {
-            self.root_layer.update(
-                &mut ts_parser.borrow_mut(),
-                &self.config,
-                old_source,
-                source,
-                changeset,
-            )
+            let ts_parser = &mut ts_parser.borrow_mut();
+            let mut cursor = ts_parser.cursors.pop().unwrap_or_else(QueryCursor::new);
+
+            let source_slice = source.slice(..);
+            let mut touched = HashSet::new();
+
+            while let Some(layer_id) = queue.pop_front() {
+                let layer = &mut layers[layer_id];
+
+                if let Some(tree) = &mut layer.tree {
+                    if layer.moved || layer.modified {
+                        for edit in edits.iter().rev() {
+                            tree.edit(edit);
+                        }
+                    }
+                }
+
+                // the edited tree, to find where the new one changed
+                let old_tree = layer.tree.clone();
+                let reparse = layer.modified || layer.tree.is_none();
+                if reparse {
+                    if let Err(err) = layer.parse(ts_parser, source) {
+                        // an injection that fails to parse is dropped
+                        if layer.parent.is_none() {
+                            ts_parser.cursors.push(cursor);
+                            return Err(err);
+                        }
+                        continue;
+                    }
+                }
+                layer.moved = false;
+                layer.modified = false;
+                touched.insert(layer_id);
+
+                let layer = &layers[layer_id];
+                let children: Vec<_> = layers
+                    .iter()
+                    .filter(|(_, child)| child.parent == Some(layer_id))
+                    .map(|(id, _)| id)
+                    .collect();
+
+                // Injections can only change where the tree changed or the text was edited,
+                // combined injections gather their nodes from the whole layer though.
+                let query_range = match (&old_tree, &layer.config.combined_injections_query) {
+                    _ if !reparse => None,
+                    (Some(old_tree), None) => changed_byte_range(old_tree, layer.tree(), &edits),
+                    _ => Some(0..usize::MAX),
+                };
+                let query_range = match query_range {
+                    Some(query_range) => query_range,
+                    None => {
+                        queue.extend(children);
+                        continue;
+                    }
+                };
+
+                cursor.set_byte_range(query_range.start, query_range.end);
+                let mut injections = Vec::new();
+
+                let matches = cursor.matches(
+                    &layer.config.injections_query,
+                    layer.tree().root_node(),
+                    |n: Node| node_to_bytes(n, source_slice),
+                );
+                for mat in matches {
+                    let (language_name, content_node, include_children) = injection_for_match(
+                        &layer.config,
+                        &layer.config.injections_query,
+                        &mat,
+                        source_slice,
+                    );
+
+                    if let (Some(language_name), Some(content_node)) = (language_name, content_node)
+                    {
+                        if let Some((language_config, config)) = injection_callback(&language_name)
+                        {
+                            let ranges =
+                                intersect_ranges(&layer.ranges, &[content_node], include_children);
+                            if !ranges.is_empty() {
+                                injections.push((language_config, config, ranges));
+                            }
+                        }
+                    }
+                }
+
+                // Process combined injections. (ERB, EJS, etc https://github.com/tree-sitter/tree-sitter/pull/526)
+                if let Some(combined_injections_query) = &layer.config.combined_injections_query {
+                    let mut injections_by_pattern_index =
+                        vec![(None, Vec::new(), false); combined_injections_query.pattern_count()];
+                    let matches = cursor.matches(
+                        combined_injections_query,
+                        layer.tree().root_node(),
+                        |n: Node| node_to_bytes(n, source_slice),
+                    );
+                    for mat in matches {
+                        let entry = &mut injections_by_pattern_index[mat.pattern_index];
+                        let (language_name, content_node, include_children) = injection_for_match(
+                            &layer.config,
+                            combined_injections_query,
+                            &mat,
+                            source_slice,
+                        );
+                        if language_name.is_some() {
+                            entry.0 = language_name;
+                        }
+                        if let Some(content_node) = content_node {
+                            entry.1.push(content_node);
+                        }
+                        entry.2 = include_children;
+                    }
+                    for (lang_name, content_nodes, includes_children) in injections_by_pattern_index
+                    {
+                        if let (Some(lang_name), false) = (lang_name, content_nodes.is_empty()) {
+                            if let Some((language_config, config)) = injection_callback(&lang_name)
+                            {
+                                let ranges = intersect_ranges(
+                                    &layer.ranges,
+                                    &content_nodes,
+                                    includes_children,
+                                );
+                                if !ranges.is_empty() {
+                                    injections.push((language_config, config, ranges));
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let depth = layer.depth + 1;
+                let mut unclaimed = children;
+                for (language_config, config, ranges) in injections {
+                    // Reuse the layer the injection had before the changes...
+                    let existing = unclaimed
+                        .iter()
+                        .position(|id| {
+                            let layer = &layers[*id];
+                            layer.config.language == config.language
+                                && ranges_overlap(&layer.ranges, &ranges)
+                        })
+                        .map(|idx| unclaimed.swap_remove(idx));
+
+                    let id = match existing {
+                        Some(id) => {
+                            let layer = &mut layers[id];
+                            if layer.ranges != ranges {
+                                // its tree gets edited and parsed again for the new ranges
+                                layer.ranges = ranges;
+                                layer.modified = true;
+                            }
+                            id
+                        }
+                        // ...or create a new one.
+                        None => layers.insert(LanguageLayer {
+                            tree: None,
+                            config,
+                            language_config: Some(language_config),
+                            depth,
+                            parent: Some(layer_id),
+                            ranges,
+                            moved: false,
+                            modified: false,
+                        }),
+                    };
+
+                    // other layers of its ranges belonged to an injection that changed
+                    let ranges = &layers[id].ranges;
+                    unclaimed.retain(|other| !ranges_overlap(&layers[*other].ranges, ranges));
+                    queue.push_back(id);
+                }
+
+                // The injections outside of the queried range are kept as they are, the ones in
+                // it that weren't found again are dropped.
+                queue.extend(unclaimed.into_iter().filter(|id| {
+                    layers[*id].ranges.iter().all(|range| {
+                        range.end_byte <= query_range.start || query_range.end <= range.start_byte
+                    })
+                }));
+            }
+
+            // Return the cursor back in the pool.
+            ts_parser.cursors.push(cursor);
+
+            // Remove the layers of injections that no longer exist.
+            layers.retain(|id, _| touched.contains(&id));
+
+            Ok(())
         })
+    }
 
-        // TODO: deal with injections and update them too
+    /// The syntax tree of the root layer.
+    pub fn tree(&self) -> &Tree {
+        self.layers[self.root].tree()
     }
 
-    // fn buffer_changed -> call layer.update(range, new_text) on root layer and then all marker layers
+    /// The language layers containing the given byte range, from the innermost injection out
+    /// to the root layer.
+    pub fn layers_for_byte_range(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = &LanguageLayer> {
+        let innermost = self
+            .layers
+            .iter()
+            .filter(|(_, layer)| {
+                layer
+                    .ranges
+                    .iter()
+                    .any(|range| range.start_byte <= start && end <= range.end_byte)
+            })
+            .max_by_key(|(_, layer)| layer.depth)
+            .map_or(self.root, |(id, _)| id);
 
-    // call this on transaction.apply() -> buffer_changed(changes)
-    //
-    // fn parse(language, old_tree, ranges)
-    //
-    pub fn tree(&self) -> &Tree {
-        self.root_layer.tree()
+        iter::successors(Some(&self.layers[innermost]), move |layer| {
+            layer.parent.map(|id| &self.layers[id])
+        })
     }
-    //
-    // <!--update_for_injection(grammar)-->
 
     // Highlighting
 
     /// Iterate over the highlighted regions for a given slice of source code.
     pub fn highlight_iter<'a>(
-        &self,
+        &'a self,
         source: RopeSlice<'a>,
         range: Option<std::ops::Range<usize>>,
         cancellation_flag: Option<&'a AtomicUsize>,
-        injection_callback: impl FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
     ) -> impl Iterator<Item = Result<HighlightEvent, Error>> + 'a {
-        // The `captures` iterator borrows the `Tree` and the `QueryCursor`, which
-        // prevents them from being moved. But both of these values are really just
-        // pointers, so it's actually ok to move them.
-
-        // reuse a cursor from the pool if possible
-        let mut cursor = PARSER.with(|ts_parser| {
-            let highlighter = &mut ts_parser.borrow_mut();
-            highlighter.cursors.pop().unwrap_or_else(QueryCursor::new)
-        });
-        let tree_ref = unsafe { mem::transmute::<_, &'static Tree>(self.tree()) };
-        let cursor_ref = unsafe { mem::transmute::<_, &'static mut QueryCursor>(&mut cursor) };
-        let query_ref = unsafe { mem::transmute::<_, &'static Query>(&self.config.query) };
-        let config_ref =
-            unsafe { mem::transmute::<_, &'static HighlightConfiguration>(self.config.as_ref()) };
-
-        // TODO: if reusing cursors this might need resetting
-        if let Some(range) = &range {
-            cursor_ref.set_byte_range(range.start, range.end);
-        }
+        let mut layers = Vec::with_capacity(self.layers.len());
+        for layer in self.layers.values() {
+            // skip injections outside of the highlighted range
+            if let Some(range) = &range {
+                let start = layer.ranges.first().map_or(0, |r| r.start_byte);
+                let end = layer.ranges.last().map_or(0, |r| r.end_byte);
+                if end <= range.start || range.end <= start {
+                    continue;
+                }
+            }
 
-        let captures = cursor_ref
-            .captures(query_ref, tree_ref.root_node(), move |n: Node| {
-                // &source[n.byte_range()]
-                node_to_bytes(n, source)
-            })
-            .peekable();
-
-        // manually craft the root layer based on the existing tree
-        let layer = HighlightIterLayer {
-            highlight_end_stack: Vec::new(),
-            scope_stack: vec![LocalScope {
-                inherits: false,
-                range: 0..usize::MAX,
-                local_defs: Vec::new(),
-            }],
-            cursor,
-            depth: 0,
-            _tree: None,
-            captures,
-            config: config_ref,
-            ranges: vec![Range {
-                start_byte: 0,
-                end_byte: usize::MAX,
-                start_point: Point::new(0, 0),
-                end_point: Point::new(usize::MAX, usize::MAX),
-            }],
-        };
+            // reuse a cursor from the pool if possible
+            let mut cursor = PARSER.with(|ts_parser| {
+                let highlighter = &mut ts_parser.borrow_mut();
+                highlighter.cursors.pop().unwrap_or_else(QueryCursor::new)
+            });
+
+            // The `captures` iterator borrows the `QueryCursor`, which prevents it from being
+            // moved. But the cursor is really just a pointer, so it's actually ok to move it.
+            let cursor_ref = unsafe { mem::transmute::<_, &'static mut QueryCursor>(&mut cursor) };
+
+            // reused cursors keep their byte range, so always reset it
+            let (start, end) = range.as_ref().map_or((0, usize::MAX), |r| (r.start, r.end));
+            cursor_ref.set_byte_range(start, end);
+
+            let captures = cursor_ref
+                .captures(
+                    &layer.config.query,
+                    layer.tree().root_node(),
+                    move |n: Node| {
+                        // &source[n.byte_range()]
+                        node_to_bytes(n, source)
+                    },
+                )
+                .peekable();
+
+            let mut layer = HighlightIterLayer {
+                highlight_end_stack: Vec::new(),
+                scope_stack: vec![LocalScope {
+                    inherits: false,
+                    range: 0..usize::MAX,
+                    local_defs: Vec::new(),
+                }],
+                cursor,
+                depth: layer.depth,
+                captures,
+                config: layer.config.as_ref(),
+            };
+
+            match layer.sort_key() {
+                Some(sort_key) => layers.push((sort_key, layer)),
+                // nothing to highlight in this layer
+                None => PARSER.with(|ts_parser| {
+                    ts_parser.borrow_mut().cursors.push(layer.cursor);
+                }),
+            }
+        }
+        layers.sort_by_key(|(sort_key, _)| *sort_key);
 
-        let mut result = HighlightIter {
+        HighlightIter {
             source,
             byte_offset: range.map_or(0, |r| r.start), // TODO: simplify
-            injection_callback,
             cancellation_flag,
             iter_count: 0,
-            layers: vec![layer],
+            layers: layers.into_iter().map(|(_, layer)| layer).collect(),
             next_event: None,
             last_highlight_range: None,
-        };
-        result.sort_layers();
-        result
+        }
     }
     // on_tokenize
     // on_change_highlighting
@@ -445,92 +1116,113 @@ impl Syntax {
     // buffer_range_for_scope_at_pos
 }
 
+/// The byte range spanning the changes from the edited `old_tree` to `new_tree` and the `edits`
+/// of the text, `None` when nothing changed.
+fn changed_byte_range(
+    old_tree: &Tree,
+    new_tree: &Tree,
+    edits: &[tree_sitter::InputEdit],
+) -> Option<std::ops::Range<usize>> {
+    // the edits are in terms of the old text, shift them by the edits before them
+    let mut offset = 0isize;
+    let edits = edits.iter().map(|edit| {
+        let start = (edit.start_byte as isize + offset) as usize;
+        let end = (edit.new_end_byte as isize + offset) as usize;
+        offset += edit.new_end_byte as isize - edit.old_end_byte as isize;
+        start..end
+    });
+
+    old_tree
+        .changed_ranges(new_tree)
+        .map(|range| range.start_byte..range.end_byte)
+        .chain(edits)
+        .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+        // widened so the nodes right before and after a deletion are in the range
+        .map(|range| range.start.saturating_sub(1)..range.end.saturating_add(1))
+}
+
+fn ranges_overlap(a: &[Range], b: &[Range]) -> bool {
+    a.iter().any(|a| {
+        b.iter()
+            .any(|b| a.start_byte < b.end_byte && b.start_byte < a.end_byte)
+    })
+}
+
+fn point_add(a: Point, b: Point) -> Point {
+    if b.row > 0 {
+        Point::new(a.row.saturating_add(b.row), b.column)
+    } else {
+        Point::new(a.row, a.column.saturating_add(b.column))
+    }
+}
+
+fn point_sub(a: Point, b: Point) -> Point {
+    if a.row > b.row {
+        Point::new(a.row.saturating_sub(b.row), a.column)
+    } else {
+        Point::new(0, a.column.saturating_sub(b.column))
+    }
+}
+
 #[derive(Debug)]
 pub struct LanguageLayer {
-    // mode
-    // grammar
-    // depth
+    pub config: Arc<HighlightConfiguration>,
+    /// Configuration of an injected language. `None` for the root layer, the configuration of
+    /// the document's language is owned by the document.
+    language_config: Option<Arc<LanguageConfiguration>>,
     pub(crate) tree: Option<Tree>,
+    /// The ranges of the document that are parsed by this layer.
+    pub ranges: Vec<Range>,
+    /// How many injections deep this layer is, the root layer is at depth 0.
+    pub depth: usize,
+    parent: Option<LayerId>,
+    /// Edits shifted the layer, its tree needs to be edited but not reparsed.
+    moved: bool,
+    /// Edits touched the layer's ranges, it needs to be reparsed.
+    modified: bool,
 }
 
 impl LanguageLayer {
-    // pub fn new() -> Self {
-    //     Self { tree: None }
-    // }
-
     pub fn tree(&self) -> &Tree {
         // TODO: no unwrap
         self.tree.as_ref().unwrap()
     }
 
-    fn parse(
-        &mut self,
-        ts_parser: &mut TsParser,
-        config: &HighlightConfiguration,
-        source: &Rope,
-        _depth: usize,
-        ranges: Vec<Range>,
-    ) -> Result<(), Error> {
-        if ts_parser.parser.set_included_ranges(&ranges).is_ok() {
-            ts_parser
-                .parser
-                .set_language(config.language)
-                .map_err(|_| Error::InvalidLanguage)?;
-
-            // unsafe { syntax.parser.set_cancellation_flag(cancellation_flag) };
-            let tree = ts_parser
-                .parser
-                .parse_with(
-                    &mut |byte, _| {
-                        if byte <= source.len_bytes() {
-                            let (chunk, start_byte, _, _) = source.chunk_at_byte(byte);
-                            chunk[byte - start_byte..].as_bytes()
-                        } else {
-                            // out of range
-                            &[]
-                        }
-                    },
-                    self.tree.as_ref(),
-                )
-                .ok_or(Error::Cancelled)?;
-            // unsafe { syntax.parser.set_cancellation_flag(None) };
-            // let mut cursor = syntax.cursors.pop().unwrap_or_else(QueryCursor::new);
-
-            // Process combined injections. (ERB, EJS, etc https://github.com/tree-sitter/tree-sitter/pull/526)
-            // if let Some(combined_injections_query) = &config.combined_injections_query {
-            //     let mut injections_by_pattern_index =
-            //         vec![(None, Vec::new(), false); combined_injections_query.pattern_count()];
-            //     let matches =
-            //         cursor.matches(combined_injections_query, tree.root_node(), |n: Node| {
-            //             // &source[n.byte_range()]
-            //             node_to_bytes(n, source)
-            //         });
-            //     for mat in matches {
-            //         let entry = &mut injections_by_pattern_index[mat.pattern_index];
-            //         let (language_name, content_node, include_children) =
-            //             injection_for_match(config, combined_injections_query, &mat, source);
-            //         if language_name.is_some() {
-            //             entry.0 = language_name;
-            //         }
-            //         if let Some(content_node) = content_node {
-            //             entry.1.push(content_node);
-            //         }
-            //         entry.2 = include_children;
-            //     }
-            //     for (lang_name, content_nodes, includes_children) in injections_by_pattern_index {
-            //         if let (Some(lang_name), false) = (lang_name, content_nodes.is_empty()) {
-            //             if let Some(next_config) = (injection_callback)(lang_name) {
-            //                 let ranges =
-            //                     Self::intersect_ranges(&ranges, &content_nodes, includes_children);
-            //                 if !ranges.is_empty() {
-            //                     queue.push((next_config, depth + 1, ranges));
-            //                 }
-            //             }
-            //         }
-            //     }
-            // }
-            self.tree = Some(tree)
-        }
+    /// Configuration of the injected language, `None` for the root layer.
+    pub fn language_config(&self) -> Option<&Arc<LanguageConfiguration>> {
+        self.language_config.as_ref()
+    }
+
+    fn parse(&mut self, ts_parser: &mut TsParser, source: &Rope) -> Result<(), Error> {
+        ts_parser
+            .parser
+            .set_included_ranges(&self.ranges)
+            .map_err(|_| Error::InvalidRanges)?;
+
+        ts_parser
+            .parser
+            .set_language(self.config.language)
+            .map_err(|_| Error::InvalidLanguage)?;
+
+        // unsafe { syntax.parser.set_cancellation_flag(cancellation_flag) };
+        let tree = ts_parser
+            .parser
+            .parse_with(
+                &mut |byte, _| {
+                    if byte <= source.len_bytes() {
+                        let (chunk, start_byte, _, _) = source.chunk_at_byte(byte);
+                        chunk[byte - start_byte..].as_bytes()
+                    } else {
+                        // out of range
+                        &[]
+                    }
+                },
+                self.tree.as_ref(),
+            )
+            .ok_or(Error::Cancelled)?;
+        // unsafe { syntax.parser.set_cancellation_flag(None) };
+
+        self.tree = Some(tree);
         Ok(())
     }
 
@@ -640,76 +1332,11 @@ impl LanguageLayer {
         }
         edits
     }
-
-    fn update(
-        &mut self,
-        ts_parser: &mut TsParser,
-        config: &HighlightConfiguration,
-        old_source: &Rope,
-        source: &Rope,
-        changeset: &ChangeSet,
-    ) -> Result<(), Error> {
-        if changeset.is_empty() {
-            return Ok(());
-        }
-
-        let edits = Self::generate_edits(old_source.slice(..), changeset);
-
-        // Notify the tree about all the changes
-        for edit in edits {
-            self.tree.as_mut().unwrap().edit(&edit);
-        }
-
-        self.parse(
-            ts_parser,
-            config,
-            source,
-            0,
-            // TODO: what to do about this range on update
-            vec![Range {
-                start_byte: 0,
-                end_byte: usize::MAX,
-                start_point: Point::new(0, 0),
-                end_point: Point::new(usize::MAX, usize::MAX),
-            }],
-        )
-    }
-
-    // fn highlight_iter() -> same as Mode but for this layer. Mode composits these
-    // fn buffer_changed
-    // fn update(range)
-    // fn update_injections()
 }
 
 // -- refactored from tree-sitter-highlight to be able to retain state
 // TODO: add seek() to iter
 
-// problem: any time a layer is updated it must update it's injections on the parent (potentially
-// removing some from use)
-// can't modify to vec and exist in it at the same time since that would violate borrows
-// maybe we can do with an arena
-// maybe just caching on the top layer and nevermind the injections for now?
-//
-// Grammar {
-//  layers: Vec<Box<Layer>> to prevent memory moves when vec is modified
-// }
-// injections tracked by marker:
-// if marker areas match it's fine and update
-// if not found add new layer
-// if length 0 then area got removed, clean up the layer
-//
-// layer update:
-// if range.len = 0 then remove the layer
-// for change in changes { tree.edit(change) }
-// tree = parser.parse(.., tree, ..)
-// calculate affected range and update injections
-// injection update:
-// look for existing injections
-// if present, range = (first injection start, last injection end)
-//
-// For now cheat and just throw out non-root layers if they exist. This should still improve
-// parsing in majority of cases.
-
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{iter, mem, ops, str, usize};
 use tree_sitter::{
@@ -728,6 +1355,7 @@ pub struct Highlight(pub usize);
 pub enum Error {
     Cancelled,
     InvalidLanguage,
+    InvalidRanges,
     Unknown,
 }
 
@@ -746,8 +1374,8 @@ pub enum HighlightEvent {
 pub struct HighlightConfiguration {
     pub language: Grammar,
     pub query: Query,
+    injections_query: Query,
     combined_injections_query: Option<Query>,
-    locals_pattern_index: usize,
     highlights_pattern_index: usize,
     highlight_indices: ArcSwap<Vec<Option<Highlight>>>,
     non_local_variable_patterns: Vec<bool>,
@@ -774,13 +1402,9 @@ struct LocalScope<'a> {
 }
 
 #[derive(Debug)]
-struct HighlightIter<'a, 'tree: 'a, F>
-where
-    F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
-{
+struct HighlightIter<'a, 'tree: 'a> {
     source: RopeSlice<'a>,
     byte_offset: usize,
-    injection_callback: F,
     cancellation_flag: Option<&'a AtomicUsize>,
     layers: Vec<HighlightIterLayer<'a, 'tree>>,
     iter_count: usize,
@@ -789,13 +1413,11 @@ where
 }
 
 struct HighlightIterLayer<'a, 'tree: 'a> {
-    _tree: Option<Tree>,
     cursor: QueryCursor,
     captures: iter::Peekable<QueryCaptures<'a, 'tree, Cow<'a, [u8]>>>,
     config: &'a HighlightConfiguration,
     highlight_end_stack: Vec<usize>,
     scope_stack: Vec<LocalScope<'a>>,
-    ranges: Vec<Range>,
     depth: usize,
 }
 
@@ -828,38 +1450,32 @@ impl HighlightConfiguration {
     ) -> Result<Self, QueryError> {
         // Concatenate the query strings, keeping track of the start offset of each section.
         let mut query_source = String::new();
-        query_source.push_str(injection_query);
-        let locals_query_offset = query_source.len();
         query_source.push_str(locals_query);
         let highlights_query_offset = query_source.len();
         query_source.push_str(highlights_query);
 
-        // Construct a single query by concatenating the three query strings, but record the
+        // Construct a single query by concatenating the two query strings, but record the
         // range of pattern indices that belong to each individual string.
-        let mut query = Query::new(language, &query_source)?;
-        let mut locals_pattern_index = 0;
+        let query = Query::new(language, &query_source)?;
         let mut highlights_pattern_index = 0;
         for i in 0..(query.pattern_count()) {
             let pattern_offset = query.start_byte_for_pattern(i);
             if pattern_offset < highlights_query_offset {
-                if pattern_offset < highlights_query_offset {
-                    highlights_pattern_index += 1;
-                }
-                if pattern_offset < locals_query_offset {
-                    locals_pattern_index += 1;
-                }
+                highlights_pattern_index += 1;
             }
         }
 
-        // Construct a separate query just for dealing with the 'combined injections'.
-        // Disable the combined injection patterns in the main query.
+        // Injections are processed when the syntax tree is updated, so they get a query of
+        // their own. Construct a separate query just for dealing with the 'combined
+        // injections' and disable the combined injection patterns in the main one.
+        let mut injections_query = Query::new(language, injection_query)?;
         let mut combined_injections_query = Query::new(language, injection_query)?;
         let mut has_combined_queries = false;
-        for pattern_index in 0..locals_pattern_index {
-            let settings = query.property_settings(pattern_index);
+        for pattern_index in 0..injections_query.pattern_count() {
+            let settings = injections_query.property_settings(pattern_index);
             if settings.iter().any(|s| &*s.key == "injection.combined") {
                 has_combined_queries = true;
-                query.disable_pattern(pattern_index);
+                injections_query.disable_pattern(pattern_index);
             } else {
                 combined_injections_query.disable_pattern(pattern_index);
             }
@@ -891,8 +1507,6 @@ impl HighlightConfiguration {
         for (i, name) in query.capture_names().iter().enumerate() {
             let i = Some(i as u32);
             match name.as_str() {
-                "injection.content" => injection_content_capture_index = i,
-                "injection.language" => injection_language_capture_index = i,
                 "local.definition" => local_def_capture_index = i,
                 "local.definition-value" => local_def_value_capture_index = i,
                 "local.reference" => local_ref_capture_index = i,
@@ -900,13 +1514,21 @@ impl HighlightConfiguration {
                 _ => {}
             }
         }
+        for (i, name) in injections_query.capture_names().iter().enumerate() {
+            let i = Some(i as u32);
+            match name.as_str() {
+                "injection.content" => injection_content_capture_index = i,
+                "injection.language" => injection_language_capture_index = i,
+                _ => {}
+            }
+        }
 
         let highlight_indices = ArcSwap::from_pointee(vec![None; query.capture_names().len()]);
         Ok(Self {
             language,
             query,
+            injections_query,
             combined_injections_query,
-            locals_pattern_index,
             highlights_pattern_index,
             highlight_indices,
             non_local_variable_patterns,
@@ -970,245 +1592,101 @@ impl HighlightConfiguration {
     }
 }
 
-impl<'a, 'tree: 'a> HighlightIterLayer<'a, 'tree> {
-    /// Create a new 'layer' of highlighting for this document.
-    ///
-    /// In the even that the new layer contains "combined injections" (injections where multiple
-    /// disjoint ranges are parsed as one syntax tree), these will be eagerly processed and
-    /// added to the returned vector.
-    fn new<F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a>(
-        source: RopeSlice<'a>,
-        cancellation_flag: Option<&'a AtomicUsize>,
-        injection_callback: &mut F,
-        mut config: &'a HighlightConfiguration,
-        mut depth: usize,
-        mut ranges: Vec<Range>,
-    ) -> Result<Vec<Self>, Error> {
-        let mut result = Vec::with_capacity(1);
-        let mut queue = Vec::new();
-        loop {
-            // --> Tree parsing part
-
-            PARSER.with(|ts_parser| {
-                let highlighter = &mut ts_parser.borrow_mut();
-
-                if highlighter.parser.set_included_ranges(&ranges).is_ok() {
-                    highlighter
-                        .parser
-                        .set_language(config.language)
-                        .map_err(|_| Error::InvalidLanguage)?;
-
-                    unsafe { highlighter.parser.set_cancellation_flag(cancellation_flag) };
-                    let tree = highlighter
-                        .parser
-                        .parse_with(
-                            &mut |byte, _| {
-                                if byte <= source.len_bytes() {
-                                    let (chunk, start_byte, _, _) = source.chunk_at_byte(byte);
-                                    chunk[byte - start_byte..].as_bytes()
-                                } else {
-                                    // out of range
-                                    &[]
-                                }
-                            },
-                            None,
-                        )
-                        .ok_or(Error::Cancelled)?;
-                    unsafe { highlighter.parser.set_cancellation_flag(None) };
-                    let mut cursor = highlighter.cursors.pop().unwrap_or_else(QueryCursor::new);
-
-                    // Process combined injections.
-                    if let Some(combined_injections_query) = &config.combined_injections_query {
-                        let mut injections_by_pattern_index = vec![
-                            (None, Vec::new(), false);
-                            combined_injections_query
-                                .pattern_count()
-                        ];
-                        let matches = cursor.matches(
-                            combined_injections_query,
-                            tree.root_node(),
-                            |n: Node| {
-                                // &source[n.byte_range()]
-                                node_to_bytes(n, source)
-                            },
-                        );
-                        for mat in matches {
-                            let entry = &mut injections_by_pattern_index[mat.pattern_index];
-                            let (language_name, content_node, include_children) =
-                                injection_for_match(
-                                    config,
-                                    combined_injections_query,
-                                    &mat,
-                                    source,
-                                );
-                            if language_name.is_some() {
-                                entry.0 = language_name;
-                            }
-                            if let Some(content_node) = content_node {
-                                entry.1.push(content_node);
-                            }
-                            entry.2 = include_children;
-                        }
-                        for (lang_name, content_nodes, includes_children) in
-                            injections_by_pattern_index
-                        {
-                            if let (Some(lang_name), false) = (lang_name, content_nodes.is_empty())
-                            {
-                                if let Some(next_config) = (injection_callback)(&lang_name) {
-                                    let ranges = Self::intersect_ranges(
-                                        &ranges,
-                                        &content_nodes,
-                                        includes_children,
-                                    );
-                                    if !ranges.is_empty() {
-                                        queue.push((next_config, depth + 1, ranges));
-                                    }
-                                }
-                            }
-                        }
-                    }
+// Compute the ranges that should be included when parsing an injection.
+// This takes into account three things:
+// * `parent_ranges` - The ranges must all fall within the *current* layer's ranges.
+// * `nodes` - Every injection takes place within a set of nodes. The injection ranges
+//   are the ranges of those nodes.
+// * `includes_children` - For some injections, the content nodes' children should be
+//   excluded from the nested document, so that only the content nodes' *own* content
+//   is reparsed. For other injections, the content nodes' entire ranges should be
+//   reparsed, including the ranges of their children.
+fn intersect_ranges(
+    parent_ranges: &[Range],
+    nodes: &[Node],
+    includes_children: bool,
+) -> Vec<Range> {
+    let mut cursor = nodes[0].walk();
+    let mut result = Vec::new();
+    let mut parent_range_iter = parent_ranges.iter();
+    let mut parent_range = parent_range_iter
+        .next()
+        .expect("Layers should only be constructed with non-empty ranges vectors");
+    for node in nodes.iter() {
+        let mut preceding_range = Range {
+            start_byte: 0,
+            start_point: Point::new(0, 0),
+            end_byte: node.start_byte(),
+            end_point: node.start_position(),
+        };
+        let following_range = Range {
+            start_byte: node.end_byte(),
+            start_point: node.end_position(),
+            end_byte: usize::MAX,
+            end_point: Point::new(usize::MAX, usize::MAX),
+        };
 
-                    // --> Highlighting query part
-
-                    // The `captures` iterator borrows the `Tree` and the `QueryCursor`, which
-                    // prevents them from being moved. But both of these values are really just
-                    // pointers, so it's actually ok to move them.
-                    let tree_ref = unsafe { mem::transmute::<_, &'static Tree>(&tree) };
-                    let cursor_ref =
-                        unsafe { mem::transmute::<_, &'static mut QueryCursor>(&mut cursor) };
-                    let captures = cursor_ref
-                        .captures(&config.query, tree_ref.root_node(), move |n: Node| {
-                            // &source[n.byte_range()]
-                            node_to_bytes(n, source)
-                        })
-                        .peekable();
-
-                    result.push(HighlightIterLayer {
-                        highlight_end_stack: Vec::new(),
-                        scope_stack: vec![LocalScope {
-                            inherits: false,
-                            range: 0..usize::MAX,
-                            local_defs: Vec::new(),
-                        }],
-                        cursor,
-                        depth,
-                        _tree: Some(tree),
-                        captures,
-                        config,
-                        ranges,
-                    });
+        for excluded_range in node
+            .children(&mut cursor)
+            .filter_map(|child| {
+                if includes_children {
+                    None
+                } else {
+                    Some(child.range())
                 }
+            })
+            .chain([following_range].iter().cloned())
+        {
+            let mut range = Range {
+                start_byte: preceding_range.end_byte,
+                start_point: preceding_range.end_point,
+                end_byte: excluded_range.start_byte,
+                end_point: excluded_range.start_point,
+            };
+            preceding_range = excluded_range;
 
-                Ok(()) // so we can use the try operator
-            })?;
-
-            if queue.is_empty() {
-                break;
+            if range.end_byte < parent_range.start_byte {
+                continue;
             }
 
-            let (next_config, next_depth, next_ranges) = queue.remove(0);
-            config = next_config;
-            depth = next_depth;
-            ranges = next_ranges;
-        }
-
-        Ok(result)
-    }
-
-    // Compute the ranges that should be included when parsing an injection.
-    // This takes into account three things:
-    // * `parent_ranges` - The ranges must all fall within the *current* layer's ranges.
-    // * `nodes` - Every injection takes place within a set of nodes. The injection ranges
-    //   are the ranges of those nodes.
-    // * `includes_children` - For some injections, the content nodes' children should be
-    //   excluded from the nested document, so that only the content nodes' *own* content
-    //   is reparsed. For other injections, the content nodes' entire ranges should be
-    //   reparsed, including the ranges of their children.
-    fn intersect_ranges(
-        parent_ranges: &[Range],
-        nodes: &[Node],
-        includes_children: bool,
-    ) -> Vec<Range> {
-        let mut cursor = nodes[0].walk();
-        let mut result = Vec::new();
-        let mut parent_range_iter = parent_ranges.iter();
-        let mut parent_range = parent_range_iter
-            .next()
-            .expect("Layers should only be constructed with non-empty ranges vectors");
-        for node in nodes.iter() {
-            let mut preceding_range = Range {
-                start_byte: 0,
-                start_point: Point::new(0, 0),
-                end_byte: node.start_byte(),
-                end_point: node.start_position(),
-            };
-            let following_range = Range {
-                start_byte: node.end_byte(),
-                start_point: node.end_position(),
-                end_byte: usize::MAX,
-                end_point: Point::new(usize::MAX, usize::MAX),
-            };
-
-            for excluded_range in node
-                .children(&mut cursor)
-                .filter_map(|child| {
-                    if includes_children {
-                        None
-                    } else {
-                        Some(child.range())
+            while parent_range.start_byte <= range.end_byte {
+                if parent_range.end_byte > range.start_byte {
+                    if range.start_byte < parent_range.start_byte {
+                        range.start_byte = parent_range.start_byte;
+                        range.start_point = parent_range.start_point;
                     }
-                })
-                .chain([following_range].iter().cloned())
-            {
-                let mut range = Range {
-                    start_byte: preceding_range.end_byte,
-                    start_point: preceding_range.end_point,
-                    end_byte: excluded_range.start_byte,
-                    end_point: excluded_range.start_point,
-                };
-                preceding_range = excluded_range;
 
-                if range.end_byte < parent_range.start_byte {
-                    continue;
-                }
-
-                while parent_range.start_byte <= range.end_byte {
-                    if parent_range.end_byte > range.start_byte {
-                        if range.start_byte < parent_range.start_byte {
-                            range.start_byte = parent_range.start_byte;
-                            range.start_point = parent_range.start_point;
+                    if parent_range.end_byte < range.end_byte {
+                        if range.start_byte < parent_range.end_byte {
+                            result.push(Range {
+                                start_byte: range.start_byte,
+                                start_point: range.start_point,
+                                end_byte: parent_range.end_byte,
+                                end_point: parent_range.end_point,
+                            });
                         }
-
-                        if parent_range.end_byte < range.end_byte {
-                            if range.start_byte < parent_range.end_byte {
-                                result.push(Range {
-                                    start_byte: range.start_byte,
-                                    start_point: range.start_point,
-                                    end_byte: parent_range.end_byte,
-                                    end_point: parent_range.end_point,
-                                });
-                            }
-                            range.start_byte = parent_range.end_byte;
-                            range.start_point = parent_range.end_point;
-                        } else {
-                            if range.start_byte < range.end_byte {
-                                result.push(range);
-                            }
-                            break;
+                        range.start_byte = parent_range.end_byte;
+                        range.start_point = parent_range.end_point;
+                    } else {
+                        if range.start_byte < range.end_byte {
+                            result.push(range);
                         }
+                        break;
                     }
+                }
 
-                    if let Some(next_range) = parent_range_iter.next() {
-                        parent_range = next_range;
-                    } else {
-                        return result;
-                    }
+                if let Some(next_range) = parent_range_iter.next() {
+                    parent_range = next_range;
+                } else {
+                    return result;
                 }
             }
         }
-        result
     }
+    result
+}
 
+impl<'a, 'tree: 'a> HighlightIterLayer<'a, 'tree> {
     // First, sort scope boundaries by their byte offset in the document. At a
     // given position, emit scope endings before scope beginnings. Finally, emit
     // scope boundaries from deeper layers first.
@@ -1234,10 +1712,7 @@ impl<'a, 'tree: 'a> HighlightIterLayer<'a, 'tree> {
     }
 }
 
-impl<'a, 'tree: 'a, F> HighlightIter<'a, 'tree, F>
-where
-    F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
-{
+impl<'a, 'tree: 'a> HighlightIter<'a, 'tree> {
     fn emit_event(
         &mut self,
         offset: usize,
@@ -1284,30 +1759,9 @@ where
             }
         }
     }
-
-    fn insert_layer(&mut self, mut layer: HighlightIterLayer<'a, 'tree>) {
-        if let Some(sort_key) = layer.sort_key() {
-            let mut i = 1;
-            while i < self.layers.len() {
-                if let Some(sort_key_i) = self.layers[i].sort_key() {
-                    if sort_key_i > sort_key {
-                        self.layers.insert(i, layer);
-                        return;
-                    }
-                    i += 1;
-                } else {
-                    self.layers.remove(i);
-                }
-            }
-            self.layers.push(layer);
-        }
-    }
 }
 
-impl<'a, 'tree: 'a, F> Iterator for HighlightIter<'a, 'tree, F>
-where
-    F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
-{
+impl<'a, 'tree: 'a> Iterator for HighlightIter<'a, 'tree> {
     type Item = Result<HighlightEvent, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -1374,48 +1828,6 @@ where
             let (mut match_, capture_index) = layer.captures.next().unwrap();
             let mut capture = match_.captures[capture_index];
 
-            // If this capture represents an injection, then process the injection.
-            if match_.pattern_index < layer.config.locals_pattern_index {
-                let (language_name, content_node, include_children) =
-                    injection_for_match(layer.config, &layer.config.query, &match_, self.source);
-
-                // Explicitly remove this match so that none of its other captures will remain
-                // in the stream of captures.
-                match_.remove();
-
-                // If a language is found with the given name, then add a new language layer
-                // to the highlighted document.
-                if let (Some(language_name), Some(content_node)) = (language_name, content_node) {
-                    if let Some(config) = (self.injection_callback)(&language_name) {
-                        let ranges = HighlightIterLayer::intersect_ranges(
-                            &self.layers[0].ranges,
-                            &[content_node],
-                            include_children,
-                        );
-                        if !ranges.is_empty() {
-                            match HighlightIterLayer::new(
-                                self.source,
-                                self.cancellation_flag,
-                                &mut self.injection_callback,
-                                config,
-                                self.layers[0].depth + 1,
-                                ranges,
-                            ) {
-                                Ok(layers) => {
-                                    for layer in layers {
-                                        self.insert_layer(layer);
-                                    }
-                                }
-                                Err(e) => return Some(Err(e)),
-                            }
-                        }
-                    }
-                }
-
-                self.sort_layers();
-                continue 'main;
-            }
-
             // Remove from the local scope stack any local scopes that have already ended.
             while range.start > layer.scope_stack.last().unwrap().range.end {
                 layer.scope_stack.pop();
@@ -1806,7 +2218,8 @@ mod test {
             fn main() {}
         ",
         );
-        let syntax = Syntax::new(&source, Arc::new(config));
+        let loader = Arc::new(Loader::new(Configuration { language: vec![] }));
+        let syntax = Syntax::new(&source, Arc::new(config), loader);
         let tree = syntax.tree();
         let root = tree.root_node();
         assert_eq!(root.kind(), "source_file");
@@ -1880,6 +2293,340 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_injection_layers() {
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "rust"
+            scope = "source.rust"
+            injection-regex = "^rust$"
+            file-types = ["rs"]
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let loader = Arc::new(Loader::new(config));
+        let language_config = loader.language_config_for_scope("source.rust").unwrap();
+        let highlight_config = language_config.highlight_config(&[]).unwrap();
+
+        let mut doc = Rope::from("fn main() {\n    let v = vec![1, 2];\n}\n");
+        let mut syntax = Syntax::new(&doc, highlight_config, loader);
+
+        let depths = |syntax: &Syntax, pos: usize| {
+            syntax
+                .layers_for_byte_range(pos, pos)
+                .map(|layer| layer.depth)
+                .collect::<Vec<_>>()
+        };
+        let injection = |syntax: &Syntax| {
+            let (id, layer) = syntax.layers.iter().find(|(_, layer)| layer.depth == 1)?;
+            Some((id, layer.ranges[0].start_byte, layer.ranges[0].end_byte))
+        };
+
+        // the macro arguments are injected as rust
+        assert_eq!(depths(&syntax, 3), [0]);
+        assert_eq!(depths(&syntax, 29), [1, 0]);
+        let (id, ..) = injection(&syntax).unwrap();
+        let layer = syntax.layers_for_byte_range(29, 29).next().unwrap();
+        assert!(Arc::ptr_eq(
+            layer.language_config().unwrap(),
+            &language_config
+        ));
+
+        // edits before and inside of the injection update its ranges
+        let transaction = Transaction::change(
+            &doc,
+            vec![(4, 4, Some("x".into())), (33, 33, Some(", 3".into()))].into_iter(),
+        );
+        let old_doc = doc.clone();
+        transaction.apply(&mut doc);
+        syntax
+            .update(&old_doc, &doc, transaction.changes())
+            .unwrap();
+        assert_eq!(syntax.layers.len(), 2);
+        assert_eq!(injection(&syntax), Some((id, 29, 38)));
+
+        // the layer is kept when the injection's range changes, an insertion at its end grows
+        // the range until the injections are queried again
+        let transaction =
+            Transaction::change(&doc, vec![(38, 38, Some(".len()".into()))].into_iter());
+        let old_doc = doc.clone();
+        transaction.apply(&mut doc);
+        syntax
+            .update(&old_doc, &doc, transaction.changes())
+            .unwrap();
+        assert_eq!(syntax.layers.len(), 2);
+        assert_eq!(injection(&syntax), Some((id, 29, 38)));
+
+        // removing the macro removes the injection
+        let transaction = Transaction::change(&doc, vec![(25, 44, Some("0".into()))].into_iter());
+        let old_doc = doc.clone();
+        transaction.apply(&mut doc);
+        syntax
+            .update(&old_doc, &doc, transaction.changes())
+            .unwrap();
+        assert_eq!(doc, "fn mxain() {\n    let v = 0;\n}\n");
+        assert_eq!(syntax.layers.len(), 1);
+        assert_eq!(depths(&syntax, 25), [0]);
+    }
+
+    #[test]
+    fn test_auto_pairs() {
+        use crate::{auto_pairs, Selection};
+
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "rust"
+            scope = "source.rust"
+            file-types = ["rs"]
+            roots = []
+            auto-pairs = { '(' = ')', '"' = '"' }
+
+            [[language]]
+            name = "toml"
+            scope = "source.toml"
+            file-types = ["toml"]
+            roots = []
+            auto-pairs = false
+
+            [[language]]
+            name = "json"
+            scope = "source.json"
+            file-types = ["json"]
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let pairs = config.language[0].auto_pairs.as_ref().unwrap();
+        assert!(pairs.contains('(', ')') && pairs.contains('"', '"'));
+        assert!(!pairs.contains('\'', '\''));
+        assert_eq!(config.language[1].auto_pairs, None);
+        assert_eq!(config.language[2].auto_pairs, Some(AutoPairs::default()));
+
+        // pairs aren't closed inside of strings and comments
+        let loader = Arc::new(Loader::new(config));
+        let language_config = loader.language_config_for_scope("source.rust").unwrap();
+        let highlight_config = language_config.highlight_config(&[]).unwrap();
+        let pairs = language_config.auto_pairs.as_ref().unwrap();
+        let doc = Rope::from("let s = \"a \"; // b \nf \n");
+        let syntax = Syntax::new(&doc, highlight_config, loader);
+
+        let insert = |pos: usize| {
+            let transaction =
+                auto_pairs::hook(&doc, &Selection::point(pos), '(', pairs, Some(&syntax)).unwrap();
+            let mut doc = doc.clone();
+            transaction.apply(&mut doc);
+            doc
+        };
+        assert_eq!(insert(11), "let s = \"a (\"; // b \nf \n");
+        assert_eq!(insert(19), "let s = \"a \"; // b (\nf \n");
+        assert_eq!(insert(22), "let s = \"a \"; // b \nf ()\n");
+    }
+
+    #[test]
+    fn test_block_comment_tokens() {
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "c"
+            scope = "source.c"
+            file-types = ["c"]
+            roots = []
+            block-comment-tokens = { start = "/*", end = "*/" }
+
+            [[language]]
+            name = "rust"
+            scope = "source.rust"
+            file-types = ["rs"]
+            roots = []
+            block-comment-tokens = [
+                { start = "/*", end = "*/" },
+                { start = "/**", end = "*/" },
+            ]
+
+            [[language]]
+            name = "toml"
+            scope = "source.toml"
+            file-types = ["toml"]
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let token = |start: &str, end: &str| BlockCommentToken {
+            start: start.into(),
+            end: end.into(),
+        };
+        assert_eq!(
+            config.language[0].block_comment_tokens,
+            Some(vec![token("/*", "*/")])
+        );
+        assert_eq!(
+            config.language[1].block_comment_tokens,
+            Some(vec![token("/*", "*/"), token("/**", "*/")])
+        );
+        assert_eq!(config.language[2].block_comment_tokens, None);
+    }
+
+    #[test]
+    fn test_surround_pairs() {
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "rust"
+            scope = "source.rust"
+            file-types = ["rs"]
+            roots = []
+            surround-pairs = { s = { open = "Some(", close = ")" } }
+            "#,
+        )
+        .unwrap();
+        let pairs = config.language[0].surround_pairs.as_ref().unwrap();
+        assert_eq!(pairs.get(&'s'), Some(&SurroundPair::new("Some(", ")")));
+        assert_eq!(pairs.len(), 1);
+    }
+
+    #[test]
+    fn test_language_detection() {
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "python"
+            scope = "source.python"
+            injection-regex = "python"
+            file-types = ["py"]
+            shebangs = ["python"]
+            roots = []
+
+            [[language]]
+            name = "bash"
+            scope = "source.bash"
+            injection-regex = "^(bash|sh)$"
+            file-types = ["sh"]
+            shebangs = ["sh", "bash"]
+            roots = []
+
+            [[language]]
+            name = "xml"
+            scope = "text.xml"
+            file-types = ["xml"]
+            first-line-regex = "^<\\?xml"
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let loader = Loader::new(config);
+        let detect = |path: Option<&str>, text: &str| {
+            loader
+                .language_config_for_file(path.map(Path::new), Rope::from(text).slice(..))
+                .map(|config| config.language_id.clone())
+        };
+
+        assert_eq!(detect(Some("main.py"), ""), Some("python".to_string()));
+        assert_eq!(detect(Some("run"), "echo hi\n"), None);
+        // shebangs, with the version of the interpreter and the arguments of env
+        assert_eq!(
+            detect(Some("run"), "#!/usr/bin/python3.9\n"),
+            Some("python".to_string())
+        );
+        assert_eq!(
+            detect(None, "#!/usr/bin/env -S PYTHONPATH=. python3 -u\n"),
+            Some("python".to_string())
+        );
+        assert_eq!(
+            detect(Some("run"), "#! /bin/sh\n"),
+            Some("bash".to_string())
+        );
+        // the file name comes before the shebang
+        assert_eq!(
+            detect(Some("script.sh"), "#!/usr/bin/env python\n"),
+            Some("bash".to_string())
+        );
+
+        assert_eq!(
+            detect(Some("build"), "<?xml version=\"1.0\"?>\n"),
+            Some("xml".to_string())
+        );
+
+        // modelines come before the file name
+        assert_eq!(
+            detect(
+                Some("a.py"),
+                "#!/bin/sh\n# -*- mode: sh; coding: utf-8 -*-\n"
+            ),
+            Some("bash".to_string())
+        );
+        assert_eq!(
+            detect(None, "# -*- Python -*-\n"),
+            Some("python".to_string())
+        );
+        assert_eq!(
+            detect(None, "\n\n\n\n\n\n# vim: set ts=4 ft=python:\n"),
+            Some("python".to_string())
+        );
+        assert_eq!(detect(None, "# vim:ft=bash\n"), Some("bash".to_string()));
+        // not a modeline
+        assert_eq!(detect(None, "# -*- coding: utf-8 -*-\n"), None);
+        assert_eq!(detect(None, "# svim: ft=python\n"), None);
+    }
+
+    #[test]
+    fn test_merge_toml_values() {
+        let default: toml::Value = toml::from_str(
+            r#"
+            [[language]]
+            name = "rust"
+            scope = "source.rust"
+            file-types = ["rs"]
+            roots = []
+            language-server = { command = "rust-analyzer" }
+            indent = { tab-width = 4, unit = "    " }
+
+            [[language]]
+            name = "toml"
+            scope = "source.toml"
+            file-types = ["toml"]
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let user: toml::Value = toml::from_str(
+            r#"
+            [[language]]
+            name = "rust"
+            file-types = ["rs", "rs.in"]
+            language-server = { args = ["--verbose"] }
+            indent = { unit = "\t" }
+
+            [[language]]
+            name = "nix"
+            scope = "source.nix"
+            file-types = ["nix"]
+            roots = []
+            "#,
+        )
+        .unwrap();
+
+        let config: Configuration = merge_toml_values(default, user).try_into().unwrap();
+        let names: Vec<_> = config
+            .language
+            .iter()
+            .map(|config| config.language_id.as_str())
+            .collect();
+        assert_eq!(names, ["rust", "toml", "nix"]);
+
+        let rust = &config.language[0];
+        // arrays are replaced, tables merged
+        assert_eq!(rust.file_types, ["rs", "rs.in"]);
+        assert_eq!(rust.scope, "source.rust");
+        let server = rust.language_server.as_ref().unwrap();
+        assert_eq!(server.command, "rust-analyzer");
+        assert_eq!(server.args, ["--verbose"]);
+        let indent = rust.indent.as_ref().unwrap();
+        assert_eq!((indent.tab_width, indent.unit.as_str()), (4, "\t"));
+    }
+
     #[test]
     fn test_load_runtime_file() {
         // Test to make sure we can load some data from the runtime directory.
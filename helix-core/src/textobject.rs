@@ -1,9 +1,13 @@
+use std::fmt::Display;
+
 use ropey::RopeSlice;
+use tree_sitter::{Node, QueryCursor};
 
-use crate::chars::{categorize_char, char_is_whitespace, CharCategory};
+use crate::chars::{categorize_char, char_is_line_ending, char_is_whitespace, CharCategory};
 use crate::graphemes::next_grapheme_boundary;
-use crate::movement::Direction;
+use crate::movement::{is_sub_word_boundary, Direction};
 use crate::surround;
+use crate::syntax::LanguageConfiguration;
 use crate::Range;
 
 fn find_word_boundary(slice: RopeSlice, mut pos: usize, direction: Direction) -> usize {
@@ -51,17 +55,27 @@ pub enum TextObject {
     Inside,
 }
 
-// count doesn't do anything yet
+impl Display for TextObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Around => "around",
+            Self::Inside => "inside",
+        })
+    }
+}
+
+/// Select the word under the cursor, `count` extends the selection over the following words of
+/// the line.
 pub fn textobject_word(
     slice: RopeSlice,
     range: Range,
     textobject: TextObject,
-    _count: usize,
+    count: usize,
 ) -> Range {
     let pos = range.cursor(slice);
 
     let word_start = find_word_boundary(slice, pos, Direction::Backward);
-    let word_end = match slice.get_char(pos).map(categorize_char) {
+    let mut word_end = match slice.get_char(pos).map(categorize_char) {
         None | Some(CharCategory::Whitespace | CharCategory::Eol) => pos,
         _ => find_word_boundary(slice, pos + 1, Direction::Forward),
     };
@@ -71,6 +85,20 @@ pub fn textobject_word(
         return Range::new(word_start, word_end);
     }
 
+    for _ in 1..count {
+        let next_start = word_end
+            + slice
+                .chars_at(word_end)
+                .take_while(|c| char_is_whitespace(*c))
+                .count();
+        match slice.get_char(next_start) {
+            Some(ch) if !char_is_line_ending(ch) => {
+                word_end = find_word_boundary(slice, next_start + 1, Direction::Forward)
+            }
+            _ => break,
+        }
+    }
+
     match textobject {
         TextObject::Inside => Range::new(word_start, word_end),
         TextObject::Around => {
@@ -93,6 +121,61 @@ pub fn textobject_word(
     }
 }
 
+/// Select the part of a camelCase, snake_case or alphanumeric word under the cursor. Around
+/// includes the underscores or whitespace following it, or preceding it if there are none.
+/// `count` extends the selection over the following sub words of the line.
+pub fn textobject_sub_word(
+    slice: RopeSlice,
+    range: Range,
+    textobject: TextObject,
+    count: usize,
+) -> Range {
+    let pos = range.cursor(slice);
+    let is_separator = |ch: char| ch == '_' || char_is_whitespace(ch);
+
+    match slice.get_char(pos) {
+        Some(ch) if !is_separator(ch) && !char_is_line_ending(ch) => (),
+        _ => return Range::new(pos, pos),
+    }
+
+    let mut start = pos;
+    while start > 0 && !is_sub_word_boundary(slice.char(start - 1), slice.char(start)) {
+        start -= 1;
+    }
+    let mut end = pos + 1;
+    for i in 0..count {
+        if i > 0 {
+            let next_start = end + slice.chars_at(end).take_while(|c| is_separator(*c)).count();
+            match slice.get_char(next_start) {
+                Some(ch) if !char_is_line_ending(ch) => end = next_start + 1,
+                _ => break,
+            }
+        }
+        while end < slice.len_chars() && !is_sub_word_boundary(slice.char(end - 1), slice.char(end))
+        {
+            end += 1;
+        }
+    }
+
+    match textobject {
+        TextObject::Inside => Range::new(start, end),
+        TextObject::Around => {
+            let separators_right = slice.chars_at(end).take_while(|c| is_separator(*c)).count();
+
+            if separators_right > 0 {
+                Range::new(start, end + separators_right)
+            } else {
+                let separators_left = {
+                    let mut iter = slice.chars_at(start);
+                    iter.reverse();
+                    iter.take_while(|c| is_separator(*c)).count()
+                };
+                Range::new(start - separators_left, end)
+            }
+        }
+    }
+}
+
 pub fn textobject_surround(
     slice: RopeSlice,
     range: Range,
@@ -100,7 +183,7 @@ pub fn textobject_surround(
     ch: char,
     count: usize,
 ) -> Range {
-    surround::find_nth_pairs_pos(slice, ch, range.head, count)
+    surround::find_nth_pairs_pos(slice, ch, range.cursor(slice), count)
         .map(|(anchor, head)| match textobject {
             TextObject::Inside => Range::new(next_grapheme_boundary(slice, anchor), head),
             TextObject::Around => Range::new(anchor, next_grapheme_boundary(slice, head)),
@@ -108,6 +191,42 @@ pub fn textobject_surround(
         .unwrap_or(range)
 }
 
+/// Select a textobject defined by the language's `textobjects.scm` query, e.g. a function
+/// (`object_name` is "function") is captured as `@function.inside` and `@function.around`.
+/// `count` selects the n-th innermost object surrounding the range.
+pub fn textobject_treesitter(
+    slice: RopeSlice,
+    range: Range,
+    textobject: TextObject,
+    object_name: &str,
+    slice_tree: Node,
+    lang_config: &LanguageConfiguration,
+    count: usize,
+) -> Range {
+    let get_range = move || -> Option<Range> {
+        let from = slice.char_to_byte(range.from());
+        let to = slice.char_to_byte(range.to());
+
+        let capture_name = format!("{}.{}", object_name, textobject);
+        let mut cursor = QueryCursor::new();
+        let mut nodes: Vec<_> = lang_config
+            .textobject_query()?
+            .capture_nodes(&capture_name, slice_tree, slice, &mut cursor)?
+            .filter(|node| node.start_byte() <= from && node.end_byte() >= to)
+            .collect();
+
+        // innermost first, syntax nodes nest so equal lengths mean the same node
+        nodes.sort_by_key(|node| node.end_byte() - node.start_byte());
+        nodes.dedup_by_key(|node| (node.start_byte(), node.end_byte()));
+        let node = nodes.into_iter().take(count).last()?;
+
+        let start = slice.byte_to_char(node.start_byte());
+        let end = slice.byte_to_char(node.end_byte());
+        Some(Range::new(start, end))
+    };
+    get_range().unwrap_or(range)
+}
+
 #[cfg(test)]
 mod test {
     use super::TextObject::*;
@@ -229,6 +348,84 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_textobject_word_count() {
+        let doc = Rope::from("one two three\nfour");
+        let slice = doc.slice(..);
+
+        // (cursor position, textobject, count, final range)
+        let tests = &[
+            (0, Inside, 2, (0, 7)),
+            (5, Inside, 2, (4, 13)),
+            (0, Around, 2, (0, 8)),
+            // the count stops at the end of the line
+            (0, Inside, 5, (0, 13)),
+        ];
+        for &(pos, objtype, count, expected_range) in tests {
+            let result = textobject_word(slice, Range::point(pos), objtype, count);
+            assert_eq!(result, expected_range.into(), "\nCase failed: {}", pos);
+        }
+
+        let doc = Rope::from("fooBar_baz42 qux");
+        let slice = doc.slice(..);
+        let tests = &[
+            (1, Inside, 2, (0, 6)),
+            (1, Inside, 3, (0, 10)),
+            (1, Around, 2, (0, 7)),
+            (8, Inside, 3, (7, 16)),
+        ];
+        for &(pos, objtype, count, expected_range) in tests {
+            let result = textobject_sub_word(slice, Range::point(pos), objtype, count);
+            assert_eq!(result, expected_range.into(), "\nCase failed: {}", pos);
+        }
+    }
+
+    #[test]
+    fn test_textobject_sub_word() {
+        // (text, [(cursor position, textobject, final range), ...])
+        let tests = &[
+            (
+                "fooBar_baz42 qux",
+                vec![
+                    (1, Inside, (0, 3)),
+                    (1, Around, (0, 3)),
+                    (4, Inside, (3, 6)),
+                    (4, Around, (3, 7)),
+                    (9, Inside, (7, 10)),
+                    (9, Around, (6, 10)),
+                    (11, Inside, (10, 12)),
+                    (11, Around, (10, 13)),
+                    (6, Inside, (6, 6)),
+                    (12, Around, (12, 12)),
+                ],
+            ),
+            (
+                "snake_case_word\n",
+                vec![
+                    (12, Inside, (11, 15)),
+                    (12, Around, (10, 15)),
+                    (15, Inside, (15, 15)),
+                ],
+            ),
+        ];
+
+        for (sample, scenario) in tests {
+            let doc = Rope::from(*sample);
+            let slice = doc.slice(..);
+            for &case in scenario {
+                let (pos, objtype, expected_range) = case;
+                let result = textobject_sub_word(slice, Range::point(pos), objtype, 1);
+                assert_eq!(
+                    result,
+                    expected_range.into(),
+                    "\nCase failed: {:?} - {:?}",
+                    sample,
+                    case
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_textobject_surround() {
         // (text, [(cursor position, textobject, final range, count), ...])
@@ -265,13 +462,13 @@ mod test {
                     (0, Inside, (1, 35), '(', 1),
                     (6, Inside, (1, 35), ')', 1),
                     (8, Inside, (9, 25), '(', 1),
-                    (8, Inside, (9, 35), ')', 2),
+                    (8, Inside, (1, 35), ')', 2),
                     (20, Inside, (9, 25), '(', 2),
                     (20, Inside, (1, 35), ')', 3),
                     (0, Around, (0, 36), '(', 1),
                     (6, Around, (0, 36), ')', 1),
                     (8, Around, (8, 26), '(', 1),
-                    (8, Around, (8, 36), ')', 2),
+                    (8, Around, (0, 36), ')', 2),
                     (20, Around, (8, 26), '(', 2),
                     (20, Around, (0, 36), ')', 3),
                 ],
@@ -318,4 +515,121 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_textobject_treesitter() {
+        use crate::movement::goto_treesitter_object;
+        use crate::syntax::{Configuration, LanguageConfiguration, Loader, Syntax};
+        use once_cell::sync::OnceCell;
+        use std::sync::Arc;
+
+        let doc = Rope::from(
+            "fn main() {
+    let add = |a: i32, b: i32| a + b;
+    println!(\"{}\", add(1, 2));
+}
+
+// a comment
+struct Point {
+    x: i32,
+}
+
+#[test]
+fn it_works() {
+    assert!(true);
+}
+",
+        );
+
+        let loader = Arc::new(Loader::new(Configuration {
+            language: vec![LanguageConfiguration {
+                scope: "source.rust".to_string(),
+                file_types: vec!["rs".to_string()],
+                language_id: "Rust".to_string(),
+                highlight_config: OnceCell::new(),
+                config: None,
+                roots: vec![],
+                shebangs: Vec::new(),
+                comment_token: None,
+                block_comment_tokens: None,
+                auto_format: false,
+                soft_wrap: false,
+                auto_pairs: None,
+                surround_pairs: None,
+                injection_regex: None,
+                first_line_regex: None,
+                language_server: None,
+                indent: None,
+                indent_query: OnceCell::new(),
+                textobject_query: OnceCell::new(),
+            }],
+        }));
+
+        // set runtime path so we can find the queries
+        let mut runtime = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        runtime.push("../runtime");
+        std::env::set_var("HELIX_RUNTIME", runtime.to_str().unwrap());
+
+        let language_config = loader.language_config_for_scope("source.rust").unwrap();
+        let highlight_config = language_config.highlight_config(&[]).unwrap();
+        let syntax = Syntax::new(&doc, highlight_config, loader);
+        let root = syntax.tree().root_node();
+        let slice = doc.slice(..);
+
+        // (cursor position, textobject, object name, count, final range)
+        let tests = &[
+            (43, Inside, "function", 1, (43, 48)),
+            (43, Around, "function", 1, (26, 48)),
+            (43, Inside, "function", 2, (10, 82)),
+            (43, Around, "function", 2, (0, 82)),
+            (43, Around, "function", 3, (0, 82)),
+            (37, Inside, "parameter", 1, (35, 41)),
+            (27, Inside, "parameter", 1, (27, 33)),
+            (88, Around, "comment", 1, (84, 96)),
+            (116, Inside, "class", 1, (110, 125)),
+            (116, Around, "class", 1, (97, 125)),
+            (155, Inside, "test", 1, (149, 171)),
+            (155, Around, "test", 1, (135, 171)),
+            (20, Around, "test", 1, (20, 20)),
+            (20, Around, "class", 1, (20, 20)),
+        ];
+
+        for &case in tests {
+            let (pos, objtype, object_name, count, expected_range) = case;
+            let result = textobject_treesitter(
+                slice,
+                Range::point(pos),
+                objtype,
+                object_name,
+                root,
+                &language_config,
+                count,
+            );
+            assert_eq!(result, expected_range.into(), "\nCase failed: {:?}", case);
+        }
+
+        // (cursor position, object name, direction, count, final range)
+        let tests = &[
+            (0, "function", Direction::Forward, 1, (26, 48)),
+            (0, "function", Direction::Forward, 2, (135, 171)),
+            (155, "function", Direction::Forward, 1, (155, 155)),
+            (155, "function", Direction::Backward, 1, (171, 135)),
+            (155, "function", Direction::Backward, 2, (48, 26)),
+            (155, "class", Direction::Backward, 1, (125, 97)),
+        ];
+
+        for &case in tests {
+            let (pos, object_name, direction, count, expected_range) = case;
+            let result = goto_treesitter_object(
+                slice,
+                Range::point(pos),
+                object_name,
+                direction,
+                root,
+                &language_config,
+                count,
+            );
+            assert_eq!(result, expected_range.into(), "\nCase failed: {:?}", case);
+        }
+    }
 }
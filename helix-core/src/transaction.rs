@@ -1,18 +1,32 @@
 use crate::{Range, Rope, Selection, Tendril};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 
 /// (from, to, replacement)
 pub type Change = (usize, usize, Option<Tendril>);
 
 // TODO: pub(crate)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Operation {
     /// Move cursor by n characters.
     Retain(usize),
     /// Delete n characters.
     Delete(usize),
     /// Insert text at position.
-    Insert(Tendril),
+    Insert(#[serde(with = "tendril_serde")] Tendril),
+}
+
+mod tendril_serde {
+    use crate::Tendril;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(tendril: &Tendril, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(tendril)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Tendril, D::Error> {
+        String::deserialize(deserializer).map(Tendril::from)
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -22,7 +36,7 @@ pub enum Assoc {
 }
 
 // ChangeSpec = Change | ChangeSet | Vec<Change>
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChangeSet {
     pub(crate) changes: Vec<Operation>,
     /// The required document length. Will refuse to apply changes unless it matches.
@@ -257,13 +271,111 @@ impl ChangeSet {
     /// `before` is `true`, order changes as if `this` comes before
     /// `other`, otherwise (the default) treat `other` as coming first.
     ///
-    /// Given two changes `A` and `B`, `A.compose(B.map(A))` and
-    /// `B.compose(A.map(B, true))` will produce the same document. This
+    /// Given two changes `A` and `B`, `A.compose(B.map(&A, false))` and
+    /// `B.compose(A.map(&B, true))` will produce the same document. This
     /// provides a basic form of [operational
     /// transformation](https://en.wikipedia.org/wiki/Operational_transformation),
     /// and can be used for collaborative editing.
-    pub fn map(self, _other: Self) -> Self {
-        unimplemented!()
+    pub fn map(self, other: &Self, before: bool) -> Self {
+        debug_assert!(self.len == other.len);
+
+        // mapping over (or mapping) an empty set is a no-op, but the operations are missing the
+        // retain that would otherwise cover the whole document
+        if other.changes.is_empty() {
+            return self;
+        }
+        if self.changes.is_empty() {
+            let mut changes = Self::with_capacity(1);
+            changes.retain(other.len_after);
+            return changes;
+        }
+
+        let mut changes_a = self.changes.into_iter();
+        let mut changes_b = other.changes.iter().cloned();
+
+        let mut head_a = changes_a.next();
+        let mut head_b = changes_b.next();
+
+        let mut changes = Self::with_capacity(other.changes.len()); // rough estimate
+
+        loop {
+            use std::cmp::Ordering;
+            use Operation::*;
+            match (head_a, head_b) {
+                // we are done
+                (None, None) => {
+                    break;
+                }
+                // concurrent insertions at the same point, order them according to `before`
+                (Some(Insert(s)), Some(Insert(t))) => {
+                    if before {
+                        changes.insert(s);
+                        head_a = changes_a.next();
+                        head_b = Some(Insert(t));
+                    } else {
+                        changes.retain(t.chars().count());
+                        head_a = Some(Insert(s));
+                        head_b = changes_b.next();
+                    }
+                }
+                // insertion in A is kept as-is
+                (Some(Insert(s)), b) => {
+                    changes.insert(s);
+                    head_a = changes_a.next();
+                    head_b = b;
+                }
+                // insertion in B has to be skipped over
+                (a, Some(Insert(t))) => {
+                    changes.retain(t.chars().count());
+                    head_a = a;
+                    head_b = changes_b.next();
+                }
+                (None, val) | (val, None) => unreachable!("({:?})", val),
+                // both sides now consume the same span of the original document
+                (Some(a), Some(b)) => {
+                    let (i, j) = match (&a, &b) {
+                        (Retain(i) | Delete(i), Retain(j) | Delete(j)) => (*i, *j),
+                        _ => unreachable!(),
+                    };
+                    let len = std::cmp::min(i, j);
+
+                    match (&a, &b) {
+                        // text is still present in B, keep A's operation
+                        (Retain(_), Retain(_)) => changes.retain(len),
+                        (Delete(_), Retain(_)) => changes.delete(len),
+                        // text was already deleted by B, nothing left to do
+                        (_, Delete(_)) => (),
+                        _ => unreachable!(),
+                    }
+
+                    let shorten = |op: Operation, n: usize| match op {
+                        Retain(_) => Retain(n),
+                        Delete(_) => Delete(n),
+                        Insert(_) => unreachable!(),
+                    };
+
+                    match i.cmp(&j) {
+                        Ordering::Less => {
+                            head_a = changes_a.next();
+                            head_b = Some(shorten(b, j - i));
+                        }
+                        Ordering::Equal => {
+                            head_a = changes_a.next();
+                            head_b = changes_b.next();
+                        }
+                        Ordering::Greater => {
+                            head_a = Some(shorten(a, i - j));
+                            head_b = changes_b.next();
+                        }
+                    }
+                }
+            };
+        }
+
+        // the mapped set applies to the document produced by `other`
+        debug_assert!(changes.len == other.len_after);
+
+        changes
     }
 
     /// Returns a new changeset that reverts this one. Useful for `undo` implementation.
@@ -416,7 +528,7 @@ impl ChangeSet {
 
 /// Transaction represents a single undoable unit of changes. Several changes can be grouped into
 /// a single transaction.
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     changes: ChangeSet,
     selection: Option<Selection>,
@@ -615,6 +727,109 @@ mod test {
         assert_eq!(text, "世orld! abc");
     }
 
+    #[test]
+    fn map() {
+        let doc = Rope::from("hello world");
+
+        let a = Transaction::change(&doc, vec![(0, 5, Some("goodbye".into()))].into_iter());
+        let b = Transaction::change(
+            &doc,
+            vec![(5, 5, Some(",".into())), (11, 11, Some("!".into()))].into_iter(),
+        );
+
+        // a then b
+        let mut text = doc.clone();
+        let changes = a
+            .changes
+            .clone()
+            .compose(b.changes.clone().map(&a.changes, false));
+        assert!(changes.apply(&mut text));
+        assert_eq!(text, "goodbye, world!");
+
+        // b then a
+        let mut text = doc;
+        let changes = b.changes.clone().compose(a.changes.map(&b.changes, true));
+        assert!(changes.apply(&mut text));
+        assert_eq!(text, "goodbye, world!");
+
+        // concurrent inserts at the same position are ordered by `before`
+        let doc = Rope::from("ab");
+        let a = Transaction::change(&doc, vec![(1, 1, Some("x".into()))].into_iter());
+        let b = Transaction::change(&doc, vec![(1, 1, Some("y".into()))].into_iter());
+
+        let mut text = doc.clone();
+        let mapped = b.changes.clone().map(&a.changes, false);
+        assert!(a.changes.clone().compose(mapped).apply(&mut text));
+        assert_eq!(text, "axyb");
+
+        let mut text = doc;
+        let mapped = b.changes.map(&a.changes, true);
+        assert!(a.changes.compose(mapped).apply(&mut text));
+        assert_eq!(text, "ayxb");
+
+        // overlapping deletes only delete the text once
+        let doc = Rope::from("0123456789");
+        let a = Transaction::change(&doc, vec![(2, 6, None)].into_iter());
+        let b = Transaction::change(&doc, vec![(4, 8, Some("-".into()))].into_iter());
+
+        let mut text = doc.clone();
+        let mapped = b.changes.clone().map(&a.changes, false);
+        assert!(a.changes.clone().compose(mapped).apply(&mut text));
+        assert_eq!(text, "01-89");
+
+        let mut text = doc;
+        let mapped = a.changes.map(&b.changes, true);
+        assert!(b.changes.compose(mapped).apply(&mut text));
+        assert_eq!(text, "01-89");
+    }
+
+    /// Builds a changeset over `doc` out of arbitrary (unsorted, possibly overlapping) spans.
+    fn arbitrary_changeset(doc: &Rope, spans: Vec<(usize, usize, Option<String>)>) -> ChangeSet {
+        let len = doc.len_chars();
+        let mut spans: Vec<_> = spans
+            .into_iter()
+            .map(|(from, to, text)| {
+                let (from, to) = (from % (len + 1), to % (len + 1));
+                (from.min(to), from.max(to), text)
+            })
+            .collect();
+        spans.sort_by_key(|(from, to, _)| (*from, *to));
+
+        let mut last = 0;
+        let changes = spans.into_iter().filter_map(|(from, to, text)| {
+            if from < last {
+                return None;
+            }
+            last = to;
+            Some((from, to, text.map(Tendril::from)))
+        });
+        // collecting so the iterator has an exact size hint
+        let changes: Vec<_> = changes.collect();
+        Transaction::change(doc, changes.into_iter()).changes
+    }
+
+    quickcheck::quickcheck! {
+        fn test_map_converges(
+            text: String,
+            a: Vec<(usize, usize, Option<String>)>,
+            b: Vec<(usize, usize, Option<String>)>
+        ) -> bool {
+            let doc = Rope::from(text);
+            let a = arbitrary_changeset(&doc, a);
+            let b = arbitrary_changeset(&doc, b);
+
+            let mut doc_a = doc.clone();
+            let ab = a.clone().compose(b.clone().map(&a, false));
+            assert!(ab.apply(&mut doc_a));
+
+            let mut doc_b = doc;
+            let ba = b.clone().compose(a.map(&b, true));
+            assert!(ba.apply(&mut doc_b));
+
+            doc_a == doc_b
+        }
+    }
+
     #[test]
     fn invert() {
         use Operation::*;
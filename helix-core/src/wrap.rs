@@ -0,0 +1,199 @@
+use crate::{
+    graphemes::{grapheme_width, RopeGraphemes},
+    line_ending::get_line_ending,
+    Position, RopeSlice,
+};
+use std::borrow::Cow;
+
+/// Marker drawn in front of the continuation rows of a wrapped line.
+pub const WRAP_INDICATOR: &str = "↪ ";
+const WRAP_INDICATOR_WIDTH: usize = 2;
+
+/// A line soft wrapped into rows of at most `width` columns. Lines are broken after whitespace
+/// when possible, and continuation rows are indented like the line itself, followed by the
+/// [`WRAP_INDICATOR`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrappedLine<'a> {
+    /// The line without its line ending.
+    line: RopeSlice<'a>,
+    tab_width: usize,
+    /// The char offsets within the line where each row starts, the first one is always 0.
+    starts: Vec<usize>,
+    /// The column continuation rows start at.
+    indent: usize,
+}
+
+impl<'a> WrappedLine<'a> {
+    pub fn new(line: RopeSlice<'a>, width: usize, tab_width: usize) -> Self {
+        let line_ending = get_line_ending(&line).map_or(0, |ending| ending.len_chars());
+        let line = line.slice(..line.len_chars() - line_ending);
+        let width = width.max(1);
+
+        let leading: usize = RopeGraphemes::new(line)
+            .take_while(|grapheme| *grapheme == " " || *grapheme == "\t")
+            .map(|grapheme| if grapheme == "\t" { tab_width } else { 1 })
+            .sum();
+        // deeply indented lines still keep half of the width for their text
+        let mut indent = leading.min(width / 2) + WRAP_INDICATOR_WIDTH;
+        if indent >= width {
+            indent = 0;
+        }
+
+        let mut starts = vec![0];
+        let mut row_start = 0;
+        let mut col = 0;
+        let mut pos = 0;
+        // the position and column right after the last whitespace of the row
+        let mut last_break: Option<(usize, usize)> = None;
+
+        for grapheme in RopeGraphemes::new(line) {
+            let grapheme_width = visual_width(grapheme, tab_width);
+            while col + grapheme_width > width && pos > row_start {
+                let (break_pos, break_col) = match last_break {
+                    Some((break_pos, break_col)) if break_pos > row_start => (break_pos, break_col),
+                    // a single word longer than the row gets broken anywhere
+                    _ => (pos, col),
+                };
+                starts.push(break_pos);
+                row_start = break_pos;
+                col = indent + col - break_col;
+                last_break = None;
+            }
+
+            col += grapheme_width;
+            pos += grapheme.len_chars();
+            if grapheme == " " || grapheme == "\t" {
+                last_break = Some((pos, col));
+            }
+        }
+
+        Self {
+            line,
+            tab_width,
+            starts,
+            indent,
+        }
+    }
+
+    /// The number of rows the line takes.
+    pub fn rows(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// The char offsets within the line where each row starts.
+    pub fn row_starts(&self) -> &[usize] {
+        &self.starts
+    }
+
+    /// The column the text of continuation rows starts at, the [`WRAP_INDICATOR`] comes right
+    /// before it. `0` if the rows are too narrow for an indent.
+    pub fn indent(&self) -> usize {
+        self.indent
+    }
+
+    /// The row and visual column of the char `offset` of the line.
+    pub fn visual_coords(&self, offset: usize) -> Position {
+        let offset = offset.min(self.line.len_chars());
+        let row = self
+            .starts
+            .iter()
+            .rposition(|start| *start <= offset)
+            .unwrap_or(0);
+        let start = self.starts[row];
+        let col = RopeGraphemes::new(self.line.slice(start..offset))
+            .map(|grapheme| visual_width(grapheme, self.tab_width))
+            .sum::<usize>();
+
+        Position::new(row, self.row_indent(row) + col)
+    }
+
+    /// The char offset of the line displayed at `row` and visual column `col`. Columns past the
+    /// end of the row are clamped to its last character.
+    pub fn offset_at(&self, row: usize, col: usize) -> usize {
+        let row = row.min(self.rows() - 1);
+        let start = self.starts[row];
+        let end = match self.starts.get(row + 1) {
+            // stay on the row instead of moving onto the first char of the next one
+            Some(next) => crate::graphemes::prev_grapheme_boundary(self.line, *next),
+            None => self.line.len_chars(),
+        };
+
+        let mut pos = start;
+        let mut current = self.row_indent(row);
+        for grapheme in RopeGraphemes::new(self.line.slice(start..end)) {
+            if current >= col {
+                break;
+            }
+            current += visual_width(grapheme, self.tab_width);
+            pos += grapheme.len_chars();
+        }
+        pos.min(end)
+    }
+
+    fn row_indent(&self, row: usize) -> usize {
+        if row == 0 {
+            0
+        } else {
+            self.indent
+        }
+    }
+}
+
+fn visual_width(grapheme: RopeSlice, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        tab_width
+    } else {
+        grapheme_width(&Cow::from(grapheme))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Rope;
+
+    #[test]
+    fn test_wrap_at_words() {
+        let text = Rope::from("  one two three four\n");
+        let wrapped = WrappedLine::new(text.slice(..), 14, 4);
+        // "  one two " | "three four"
+        assert_eq!(wrapped.row_starts(), &[0, 10]);
+        assert_eq!(wrapped.indent(), 4);
+        assert_eq!(wrapped.rows(), 2);
+
+        assert_eq!(wrapped.visual_coords(2), Position::new(0, 2));
+        assert_eq!(wrapped.visual_coords(10), Position::new(1, 4));
+        assert_eq!(wrapped.visual_coords(20), Position::new(1, 14));
+
+        assert_eq!(wrapped.offset_at(0, 6), 6);
+        // past the end of the first row
+        assert_eq!(wrapped.offset_at(0, 40), 9);
+        // within the indent of a continuation row
+        assert_eq!(wrapped.offset_at(1, 0), 10);
+        assert_eq!(wrapped.offset_at(1, 6), 12);
+        assert_eq!(wrapped.offset_at(1, 40), 20);
+    }
+
+    #[test]
+    fn test_wrap_long_words() {
+        let text = Rope::from("abcdefghij");
+        let wrapped = WrappedLine::new(text.slice(..), 4, 4);
+        assert_eq!(wrapped.indent(), 2);
+        assert_eq!(wrapped.row_starts(), &[0, 4, 6, 8]);
+
+        let text = Rope::from("\tx今日は");
+        let wrapped = WrappedLine::new(text.slice(..), 8, 4);
+        assert_eq!(wrapped.indent(), 6);
+        assert_eq!(wrapped.row_starts(), &[0, 1, 3, 4]);
+        assert_eq!(wrapped.visual_coords(3), Position::new(2, 6));
+
+        // no room for an indent
+        let wrapped = WrappedLine::new(text.slice(..), 3, 4);
+        assert_eq!(wrapped.indent(), 0);
+
+        let text = Rope::from("\n");
+        let wrapped = WrappedLine::new(text.slice(..), 6, 4);
+        assert_eq!(wrapped.row_starts(), &[0]);
+        assert_eq!(wrapped.offset_at(0, 3), 0);
+    }
+}
@@ -59,12 +59,6 @@ impl Application {
         let theme_loader =
             std::sync::Arc::new(theme::Loader::new(&conf_dir, &helix_core::runtime_dir()));
 
-        // load $HOME/.config/helix/languages.toml, fallback to default config
-        let lang_conf = std::fs::read(conf_dir.join("languages.toml"));
-        let lang_conf = lang_conf
-            .as_deref()
-            .unwrap_or(include_bytes!("../../languages.toml"));
-
         let theme = if let Some(theme) = &config.theme {
             match theme_loader.load(theme) {
                 Ok(theme) => theme,
@@ -77,7 +71,8 @@ impl Application {
             theme_loader.default()
         };
 
-        let syn_loader_conf = toml::from_slice(lang_conf).expect("Could not parse languages.toml");
+        // the default languages.toml with the user's and the project's merged over it
+        let syn_loader_conf = crate::config::lang_config()?;
         let syn_loader = std::sync::Arc::new(syntax::Loader::new(syn_loader_conf));
 
         let mut editor = Editor::new(
@@ -235,7 +230,16 @@ impl Application {
                 self.compositor
                     .handle_event(Event::Resize(width, height), &mut cx)
             }
-            Some(Ok(event)) => self.compositor.handle_event(event, &mut cx),
+            Some(Ok(event)) => {
+                // record the typed keys, not the ones of replayed macros
+                if let (Event::Key(key), Some((_, keys))) = (event, &mut cx.editor.macro_recording)
+                {
+                    let mut key = key.into();
+                    ui::editor::canonicalize_key(&mut key);
+                    keys.push(key);
+                }
+                self.compositor.handle_event(event, &mut cx)
+            }
             Some(Err(x)) => panic!("{}", x),
             None => panic!(),
         };
@@ -527,6 +531,9 @@ impl Application {
 
         self.event_loop().await;
 
+        for doc in self.editor.documents() {
+            doc.persist_history();
+        }
         self.editor.close_language_servers(None).await?;
 
         self.restore_term()?;
@@ -1,5 +1,7 @@
 use helix_core::{
-    comment, coords_at_pos, find_first_non_whitespace_char, find_root, graphemes, indent,
+    chars::char_is_word,
+    comment, coords_at_pos, find_first_non_whitespace_char, find_root, graphemes, increment,
+    indent,
     indent::IndentStyle,
     line_ending::{get_line_ending_of_str, line_end_char_index, str_is_line_ending},
     match_brackets,
@@ -7,13 +9,16 @@ use helix_core::{
     object, pos_at_coords,
     regex::{self, Regex},
     register::Register,
-    search, selection, surround, textobject, LineEnding, Position, Range, Rope, RopeGraphemes,
-    RopeSlice, Selection, SmallVec, Tendril, Transaction,
+    search, selection,
+    substitute::Substitution,
+    surround::{self, Surround, SurroundPair},
+    textobject, visual_coords_at_pos, LineEnding, Position, Range, Rope, RopeGraphemes, RopeSlice,
+    Selection, SmallVec, Syntax, Tendril, Transaction,
 };
 
 use helix_view::{
-    document::Mode, editor::Action, input::KeyEvent, keyboard::KeyCode, view::View, Document,
-    DocumentId, Editor, ViewId,
+    document::Mode, editor::Action, input::KeyEvent, keyboard::KeyCode, marks, view::View,
+    Document, DocumentId, Editor, ViewId,
 };
 
 use anyhow::{anyhow, bail, Context as _};
@@ -37,6 +42,7 @@ use std::{fmt, future::Future};
 
 use std::{
     borrow::Cow,
+    collections::HashMap,
     path::{Path, PathBuf},
 };
 
@@ -46,6 +52,8 @@ use serde::de::{self, Deserialize, Deserializer};
 pub struct Context<'a> {
     pub selected_register: helix_view::RegisterSelection,
     pub count: Option<NonZeroUsize>,
+    /// The number of keys of the binding that invoked the command.
+    pub binding_len: usize,
     pub editor: &'a mut Editor,
 
     pub callback: Option<crate::compositor::Callback>,
@@ -56,9 +64,9 @@ pub struct Context<'a> {
 impl<'a> Context<'a> {
     /// Push a new component onto the compositor.
     pub fn push_layer(&mut self, component: Box<dyn Component>) {
-        self.callback = Some(Box::new(|compositor: &mut Compositor| {
-            compositor.push(component)
-        }));
+        self.callback = Some(Box::new(
+            |compositor: &mut Compositor, _cx: &mut compositor::Context| compositor.push(component),
+        ));
     }
 
     #[inline]
@@ -117,6 +125,7 @@ fn align_view(doc: &Document, view: &mut View, align: Align) {
     };
 
     view.first_line = line.saturating_sub(relative);
+    view.first_row = 0;
 }
 
 /// A command is composed of a static name, and a function that takes the current state plus a count,
@@ -164,10 +173,14 @@ impl Command {
         move_char_right, "Move right",
         move_line_up, "Move up",
         move_line_down, "Move down",
+        move_visual_line_up, "Move up by display line",
+        move_visual_line_down, "Move down by display line",
         extend_char_left, "Extend left",
         extend_char_right, "Extend right",
         extend_line_up, "Extend up",
         extend_line_down, "Extend down",
+        extend_visual_line_up, "Extend up by display line",
+        extend_visual_line_down, "Extend down by display line",
         copy_selection_on_next_line, "Copy selection on next line",
         copy_selection_on_prev_line, "Copy selection on previous line",
         move_next_word_start, "Move to beginning of next word",
@@ -179,6 +192,12 @@ impl Command {
         extend_next_word_start, "Extend to beginning of next word",
         extend_prev_word_start, "Extend to beginning of previous word",
         extend_next_word_end, "Extend to end of next word",
+        move_next_sub_word_start, "Move to beginning of next sub word",
+        move_prev_sub_word_start, "Move to beginning of previous sub word",
+        move_next_sub_word_end, "Move to end of next sub word",
+        extend_next_sub_word_start, "Extend to beginning of next sub word",
+        extend_prev_sub_word_start, "Extend to beginning of previous sub word",
+        extend_next_sub_word_end, "Extend to end of next sub word",
         find_till_char, "Move till next occurance of char",
         find_next_char, "Move to next occurance of char",
         extend_till_char, "Extend till next occurance of char",
@@ -203,6 +222,10 @@ impl Command {
         search_next, "Select next search match",
         extend_search_next, "Add next search match to selection",
         search_selection, "Use current selection as search pattern",
+        add_next_match, "Add next match of primary selection as a new selection",
+        skip_to_next_match, "Move primary selection to the next match",
+        remove_last_match, "Remove the last match added as a new selection",
+        select_all_matches, "Select all matches of primary selection",
         extend_line, "Select current line, if already selected, extend to next line",
         extend_to_line_bounds, "Extend selection to line bounds (line-wise selection)",
         delete_selection, "Delete selection",
@@ -217,6 +240,9 @@ impl Command {
         buffer_picker, "Open buffer picker",
         symbol_picker, "Open symbol picker",
         last_picker, "Open last picker",
+        set_mark, "Set mark to the current selection",
+        goto_mark, "Restore selection from mark",
+        mark_picker, "Open mark picker",
         prepend_to_line, "Insert at start of line",
         append_to_line, "Insert at end of line",
         open_below, "Open new line below selection",
@@ -240,19 +266,31 @@ impl Command {
         goto_last_diag, "Goto last diagnostic",
         goto_next_diag, "Goto next diagnostic",
         goto_prev_diag, "Goto previous diagnostic",
+        goto_next_function, "Goto next function",
+        goto_prev_function, "Goto previous function",
+        goto_next_class, "Goto next class",
+        goto_prev_class, "Goto previous class",
+        goto_next_parameter, "Goto next parameter",
+        goto_prev_parameter, "Goto previous parameter",
+        goto_next_comment, "Goto next comment",
+        goto_prev_comment, "Goto previous comment",
+        goto_next_test, "Goto next test",
+        goto_prev_test, "Goto previous test",
         goto_line_start, "Goto line start",
         goto_line_end, "Goto line end",
         // TODO: different description ?
         goto_line_end_newline, "Goto line end",
         goto_first_nonwhitespace, "Goto first non-blank in line",
         signature_help, "Show signature help",
-        insert_tab, "Insert tab char",
+        insert_tab, "Insert tab char, or go to the next snippet tabstop",
+        goto_prev_tabstop, "Go to the previous snippet tabstop",
         insert_newline, "Insert newline char",
         delete_char_backward, "Delete previous char",
         delete_char_forward, "Delete next char",
         delete_word_backward, "Delete previous word",
         undo, "Undo change",
         redo, "Redo change",
+        undo_tree, "Open undo tree",
         yank, "Yank selection",
         yank_joined_to_clipboard, "Join and yank selections to clipboard",
         yank_main_selection_to_clipboard, "Yank main selection to clipboard",
@@ -265,17 +303,29 @@ impl Command {
         indent, "Indent selection",
         unindent, "Unindent selection",
         format_selections, "Format selection",
+        reindent_selections, "Reindent the lines of the selections",
         join_selections, "Join lines inside selection",
         keep_selections, "Keep selections matching regex",
         keep_primary_selection, "Keep primary selection",
         completion, "Invoke completion popup",
         hover, "Show docs for item under cursor",
         toggle_comments, "Comment/uncomment selections",
+        toggle_block_comments, "Block comment/uncomment selections",
+        align_selections, "Align selections in column",
+        align_selection_ends, "Align the ends of selections in column",
+        increment, "Increment the number, date or boolean under the cursor",
+        decrement, "Decrement the number, date or boolean under the cursor",
+        increment_sequence, "Increment numbers by the count times the selection's position",
+        decrement_sequence, "Decrement numbers by the count times the selection's position",
         rotate_selections_forward, "Rotate selections forward",
         rotate_selections_backward, "Rotate selections backward",
         rotate_selection_contents_forward, "Rotate selection contents forward",
         rotate_selection_contents_backward, "Rotate selections contents backward",
         expand_selection, "Expand selection to parent syntax node",
+        shrink_selection, "Shrink selection to previously expanded syntax node",
+        select_first_child, "Select first child syntax node",
+        select_next_sibling, "Select next sibling syntax node",
+        select_prev_sibling, "Select previous sibling syntax node",
         jump_forward, "Jump forward on jumplist",
         jump_backward, "Jump backward on jumplist",
         rotate_view, "Goto next window",
@@ -283,6 +333,8 @@ impl Command {
         vsplit, "Vertical right split",
         wclose, "Close window",
         select_register, "Select register",
+        record_macro, "Record macro",
+        replay_macro, "Replay macro",
         align_view_middle, "Align view middle",
         align_view_top, "Align view top",
         align_view_center, "Align view center",
@@ -385,6 +437,42 @@ fn move_line_down(cx: &mut Context) {
     doc.set_selection(view.id, selection);
 }
 
+/// Moves by the rows of soft wrapped lines, or by lines if the document isn't soft wrapped.
+fn move_visual_line(cx: &mut Context, dir: Direction, behaviour: Movement) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let wrap_width = view.wrap_width(doc);
+    let tab_width = doc.tab_width();
+
+    let selection = doc
+        .selection(view.id)
+        .clone()
+        .transform(|range| match wrap_width {
+            Some(width) => movement::move_vertically_visual(
+                text, range, dir, count, behaviour, width, tab_width,
+            ),
+            None => movement::move_vertically(text, range, dir, count, behaviour),
+        });
+    doc.set_selection(view.id, selection);
+}
+
+fn move_visual_line_up(cx: &mut Context) {
+    move_visual_line(cx, Direction::Backward, Movement::Move)
+}
+
+fn move_visual_line_down(cx: &mut Context) {
+    move_visual_line(cx, Direction::Forward, Movement::Move)
+}
+
+fn extend_visual_line_up(cx: &mut Context) {
+    move_visual_line(cx, Direction::Backward, Movement::Extend)
+}
+
+fn extend_visual_line_down(cx: &mut Context) {
+    move_visual_line(cx, Direction::Forward, Movement::Extend)
+}
+
 fn goto_line_end(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
     let text = doc.text().slice(..);
@@ -552,6 +640,42 @@ fn move_next_long_word_end(cx: &mut Context) {
     doc.set_selection(view.id, selection);
 }
 
+fn move_next_sub_word_start(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+
+    let selection = doc
+        .selection(view.id)
+        .clone()
+        .transform(|range| movement::move_next_sub_word_start(text, range, count));
+    doc.set_selection(view.id, selection);
+}
+
+fn move_prev_sub_word_start(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+
+    let selection = doc
+        .selection(view.id)
+        .clone()
+        .transform(|range| movement::move_prev_sub_word_start(text, range, count));
+    doc.set_selection(view.id, selection);
+}
+
+fn move_next_sub_word_end(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+
+    let selection = doc
+        .selection(view.id)
+        .clone()
+        .transform(|range| movement::move_next_sub_word_end(text, range, count));
+    doc.set_selection(view.id, selection);
+}
+
 fn goto_file_start(cx: &mut Context) {
     if cx.count.is_some() {
         goto_line(cx);
@@ -607,6 +731,45 @@ fn extend_next_word_end(cx: &mut Context) {
     doc.set_selection(view.id, selection);
 }
 
+fn extend_next_sub_word_start(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+
+    let selection = doc.selection(view.id).clone().transform(|range| {
+        let word = movement::move_next_sub_word_start(text, range, count);
+        let pos = word.cursor(text);
+        range.put_cursor(text, pos, true)
+    });
+    doc.set_selection(view.id, selection);
+}
+
+fn extend_prev_sub_word_start(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+
+    let selection = doc.selection(view.id).clone().transform(|range| {
+        let word = movement::move_prev_sub_word_start(text, range, count);
+        let pos = word.cursor(text);
+        range.put_cursor(text, pos, true)
+    });
+    doc.set_selection(view.id, selection);
+}
+
+fn extend_next_sub_word_end(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+
+    let selection = doc.selection(view.id).clone().transform(|range| {
+        let word = movement::move_next_sub_word_end(text, range, count);
+        let pos = word.cursor(text);
+        range.put_cursor(text, pos, true)
+    });
+    doc.set_selection(view.id, selection);
+}
+
 #[inline]
 fn find_char_impl<F>(cx: &mut Context, search_fn: F, inclusive: bool, extend: bool)
 where
@@ -901,6 +1064,7 @@ fn scroll(cx: &mut Context, offset: usize, direction: Direction) {
         Backward => view.first_line.saturating_sub(offset),
     }
     .min(doc_last_line);
+    view.first_row = 0;
 
     // recalculate last line
     let last_line = view.last_line(doc);
@@ -1096,7 +1260,7 @@ fn split_selection_on_newline(cx: &mut Context) {
     doc.set_selection(view.id, selection);
 }
 
-fn search_impl(doc: &mut Document, view: &mut View, contents: &str, regex: &Regex, extend: bool) {
+fn search_impl(doc: &mut Document, view: &mut View, regex: &Regex, extend: bool) {
     let text = doc.text().slice(..);
     let selection = doc.selection(view.id);
 
@@ -1106,11 +1270,9 @@ fn search_impl(doc: &mut Document, view: &mut View, contents: &str, regex: &Rege
         selection.primary().cursor(text),
     ));
 
-    // use find_at to find the next match after the cursor, loop around the end
-    // Careful, `Regex` uses `bytes` as offsets, not character indices!
-    let mat = regex
-        .find_at(contents, start)
-        .or_else(|| regex.find(contents));
+    // find the next match after the cursor, loop around the end
+    // Careful, matches use `bytes` as offsets, not character indices!
+    let mat = search::find_next(regex, text, start);
     // TODO: message on wraparound
     if let Some(mat) = mat {
         let start = text.byte_to_char(mat.start());
@@ -1134,20 +1296,14 @@ fn search_impl(doc: &mut Document, view: &mut View, contents: &str, regex: &Rege
 
 // TODO: use one function for search vs extend
 fn search(cx: &mut Context) {
-    let (_, doc) = current!(cx.editor);
-
     // TODO: could probably share with select_on_matches?
-
-    // HAXX: sadly we can't avoid allocating a single string for the whole buffer since we can't
-    // feed chunks into the regex yet
-    let contents = doc.text().slice(..).to_string();
-
     let prompt = ui::regex_prompt(
         cx,
         "search:".to_string(),
         move |view, doc, registers, regex| {
-            search_impl(doc, view, &contents, &regex, false);
+            search_impl(doc, view, &regex, false);
             // TODO: only store on enter (accept), not update
+            // the pattern as typed, the regex is built from the input as is
             registers.write('\\', vec![regex.as_str().to_string()]);
         },
     );
@@ -1160,9 +1316,8 @@ fn search_next_impl(cx: &mut Context, extend: bool) {
     let registers = &mut cx.editor.registers;
     if let Some(query) = registers.read('\\') {
         let query = query.first().unwrap();
-        let contents = doc.text().slice(..).to_string();
         let regex = Regex::new(query).unwrap();
-        search_impl(doc, view, &contents, &regex, extend);
+        search_impl(doc, view, &regex, extend);
     }
 }
 
@@ -1183,6 +1338,114 @@ fn search_selection(cx: &mut Context) {
     search_next(cx);
 }
 
+/// Builds the regex matching the text of the primary selection and stores it in the search
+/// register. A cursor on a word gets extended to the word first, which is then matched as a whole
+/// word, and keeps being matched as one while the search register holds it.
+fn primary_selection_regex(cx: &mut Context) -> Regex {
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+    let primary = selection.primary();
+
+    let is_cursor = graphemes::next_grapheme_boundary(text, primary.from()) >= primary.to();
+    let query = if is_cursor && text.get_char(primary.from()).map_or(false, char_is_word) {
+        let word = textobject::textobject_word(text, primary, textobject::TextObject::Inside, 1);
+        let query = format!(r"\b{}\b", regex::escape(&word.fragment(text)));
+        let selection = replace_primary(selection, word);
+        doc.set_selection(view.id, selection);
+        query
+    } else {
+        let query = regex::escape(&primary.fragment(text));
+        let word_query = format!(r"\b{}\b", query);
+        let registers = &cx.editor.registers;
+        let last_query = registers.read('\\').and_then(|values| values.first());
+        if last_query == Some(&word_query) {
+            word_query
+        } else {
+            query
+        }
+    };
+
+    cx.editor.registers.write('\\', vec![query.clone()]);
+    Regex::new(&query).unwrap()
+}
+
+fn replace_primary(selection: &Selection, range: Range) -> Selection {
+    if selection.len() == 1 {
+        Selection::single(range.anchor, range.head)
+    } else {
+        selection
+            .clone()
+            .remove(selection.primary_index())
+            .push(range)
+    }
+}
+
+fn add_next_match(cx: &mut Context) {
+    let regex = primary_selection_regex(cx);
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+
+    match selection::find_next_match(text, selection, &regex) {
+        Some(range) => {
+            // save the current selection so removing the match can restore it
+            view.match_selections.push(selection.clone());
+            let selection = selection.clone().push(range);
+            doc.set_selection(view.id, selection);
+        }
+        None => cx.editor.set_status("No more matches".to_string()),
+    }
+}
+
+fn skip_to_next_match(cx: &mut Context) {
+    let regex = primary_selection_regex(cx);
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+
+    match selection::find_next_match(text, selection, &regex) {
+        Some(range) => {
+            let selection = replace_primary(selection, range);
+            doc.set_selection(view.id, selection);
+        }
+        None => cx.editor.set_status("No more matches".to_string()),
+    }
+}
+
+fn remove_last_match(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let current_selection = doc.selection(view.id);
+
+    if let Some(prev_selection) = view.match_selections.pop() {
+        // the selection changed since the match was added, the history doesn't apply anymore
+        if prev_selection.len() + 1 == current_selection.len()
+            && current_selection.contains(&prev_selection)
+        {
+            doc.set_selection(view.id, prev_selection);
+            return;
+        }
+        view.match_selections.clear();
+    }
+    cx.editor.set_error("No added match to remove".to_string());
+}
+
+fn select_all_matches(cx: &mut Context) {
+    let regex = primary_selection_regex(cx);
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let primary = doc.selection(view.id).primary();
+
+    let whole_text = Selection::single(0, text.len_chars());
+    if let Some(mut selection) = selection::select_on_matches(text, &whole_text, &regex) {
+        // the match of the primary selection stays the primary one
+        if let Some(index) = selection.iter().position(|range| range.overlaps(&primary)) {
+            selection.set_primary_index(index);
+        }
+        doc.set_selection(view.id, selection);
+    }
+}
+
 fn extend_line(cx: &mut Context) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
@@ -1339,6 +1602,23 @@ mod cmd {
         // params, flags, helper, completer
         pub fun: fn(&mut compositor::Context, &[&str], PromptEvent) -> anyhow::Result<()>,
         pub completer: Option<Completer>,
+        pub flags: TypableFlags,
+    }
+
+    #[derive(Clone, Copy)]
+    pub struct TypableFlags {
+        /// Pass the text following the command name as a single argument, the way it was typed.
+        pub raw_args: bool,
+        /// Also run the command with `PromptEvent::Update` as the input changes, so it can preview
+        /// its result with [`Document::preview`].
+        pub preview: bool,
+    }
+
+    impl TypableFlags {
+        pub const NONE: Self = Self {
+            raw_args: false,
+            preview: false,
+        };
     }
 
     fn quit(
@@ -1823,6 +2103,35 @@ mod cmd {
         doc.reload(view.id)
     }
 
+    /// Replace the matches of a regex within the selections, or the whole document when there are
+    /// only cursors. The result gets previewed while typing.
+    fn substitute(
+        cx: &mut compositor::Context,
+        args: &[&str],
+        event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let input = args.first().copied().unwrap_or_default();
+        let substitution = Substitution::parse(input).map_err(|err| anyhow!(err))?;
+
+        let (view, doc) = current!(cx.editor);
+        let changes = substitution.changes(doc.text().slice(..), doc.selection(view.id));
+        let count = changes.len();
+        let transaction = Transaction::change(doc.text(), changes.into_iter());
+
+        if event == PromptEvent::Update {
+            doc.preview(&transaction, view.id);
+            return Ok(());
+        }
+
+        if count == 0 {
+            bail!("no matches");
+        }
+        doc.apply(&transaction, view.id);
+        doc.append_changes_to_history(view.id);
+        cx.editor.set_status(format!("{} substitutions", count));
+        Ok(())
+    }
+
     pub const TYPABLE_COMMAND_LIST: &[TypableCommand] = &[
         TypableCommand {
             name: "quit",
@@ -1830,6 +2139,7 @@ mod cmd {
             doc: "Close the current view.",
             fun: quit,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "quit!",
@@ -1837,6 +2147,7 @@ mod cmd {
             doc: "Close the current view.",
             fun: force_quit,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "open",
@@ -1844,6 +2155,7 @@ mod cmd {
             doc: "Open a file from disk into the current view.",
             fun: open,
             completer: Some(completers::filename),
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "write",
@@ -1851,6 +2163,7 @@ mod cmd {
             doc: "Write changes to disk. Accepts an optional path (:write some/path.txt)",
             fun: write,
             completer: Some(completers::filename),
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "new",
@@ -1858,6 +2171,7 @@ mod cmd {
             doc: "Create a new scratch buffer.",
             fun: new_file,
             completer: Some(completers::filename),
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "format",
@@ -1865,6 +2179,7 @@ mod cmd {
             doc: "Format the file using a formatter.",
             fun: format,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "indent-style",
@@ -1872,6 +2187,7 @@ mod cmd {
             doc: "Set the indentation style for editing. ('t' for tabs or 1-8 for number of spaces.)",
             fun: set_indent_style,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "line-ending",
@@ -1879,6 +2195,7 @@ mod cmd {
             doc: "Set the document's default line ending. Options: crlf, lf, cr, ff, nel.",
             fun: set_line_ending,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "earlier",
@@ -1886,6 +2203,7 @@ mod cmd {
             doc: "Jump back to an earlier point in edit history. Accepts a number of steps or a time span.",
             fun: earlier,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "later",
@@ -1893,6 +2211,7 @@ mod cmd {
             doc: "Jump to a later point in edit history. Accepts a number of steps or a time span.",
             fun: later,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "write-quit",
@@ -1900,6 +2219,7 @@ mod cmd {
             doc: "Writes changes to disk and closes the current view. Accepts an optional path (:wq some/path.txt)",
             fun: write_quit,
             completer: Some(completers::filename),
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "write-quit!",
@@ -1907,6 +2227,7 @@ mod cmd {
             doc: "Writes changes to disk and closes the current view forcefully. Accepts an optional path (:wq! some/path.txt)",
             fun: force_write_quit,
             completer: Some(completers::filename),
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "write-all",
@@ -1914,6 +2235,7 @@ mod cmd {
             doc: "Writes changes from all views to disk.",
             fun: write_all,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "write-quit-all",
@@ -1921,6 +2243,7 @@ mod cmd {
             doc: "Writes changes from all views to disk and close all views.",
             fun: write_all_quit,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "write-quit-all!",
@@ -1928,6 +2251,7 @@ mod cmd {
             doc: "Writes changes from all views to disk and close all views forcefully (ignoring unsaved changes).",
             fun: force_write_all_quit,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "quit-all",
@@ -1935,6 +2259,7 @@ mod cmd {
             doc: "Close all views.",
             fun: quit_all,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "quit-all!",
@@ -1942,6 +2267,7 @@ mod cmd {
             doc: "Close all views forcefully (ignoring unsaved changes).",
             fun: force_quit_all,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "theme",
@@ -1949,6 +2275,7 @@ mod cmd {
             doc: "Change the theme of current view. Requires theme name as argument (:theme <name>)",
             fun: theme,
             completer: Some(completers::theme),
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "clipboard-yank",
@@ -1956,6 +2283,7 @@ mod cmd {
             doc: "Yank main selection into system clipboard.",
             fun: yank_main_selection_to_clipboard,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "clipboard-yank-join",
@@ -1963,6 +2291,7 @@ mod cmd {
             doc: "Yank joined selections into system clipboard. A separator can be provided as first argument. Default value is newline.", // FIXME: current UI can't display long doc.
             fun: yank_joined_to_clipboard,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "clipboard-paste-after",
@@ -1970,6 +2299,7 @@ mod cmd {
             doc: "Paste system clipboard after selections.",
             fun: paste_clipboard_after,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "clipboard-paste-before",
@@ -1977,6 +2307,7 @@ mod cmd {
             doc: "Paste system clipboard before selections.",
             fun: paste_clipboard_before,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "clipboard-paste-replace",
@@ -1984,6 +2315,7 @@ mod cmd {
             doc: "Replace selections with content of system clipboard.",
             fun: replace_selections_with_clipboard,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "show-clipboard-provider",
@@ -1991,6 +2323,7 @@ mod cmd {
             doc: "Show clipboard provider name in status bar.",
             fun: show_clipboard_provider,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "change-current-directory",
@@ -1998,6 +2331,7 @@ mod cmd {
             doc: "Change the current working directory (:cd <dir>).",
             fun: change_current_directory,
             completer: Some(completers::directory),
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "show-directory",
@@ -2005,13 +2339,15 @@ mod cmd {
             doc: "Show the current working directory.",
             fun: show_current_directory,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "encoding",
             alias: None,
-            doc: "Set encoding based on `https://encoding.spec.whatwg.org`",
+            doc: "Set encoding based on `https://encoding.spec.whatwg.org`, or UTF-32",
             fun: set_encoding,
             completer: None,
+            flags: TypableFlags::NONE,
         },
         TypableCommand {
             name: "reload",
@@ -2019,9 +2355,32 @@ mod cmd {
             doc: "Discard changes and reload from the source file.",
             fun: reload,
             completer: None,
+            flags: TypableFlags::NONE,
+        },
+        TypableCommand {
+            name: "substitute",
+            alias: Some("s"),
+            doc: "Replace regex matches in the selections, or the whole buffer: `/pattern/replacement/flags`. Flags are `g` for all matches of a line and `i` to ignore case.",
+            fun: substitute,
+            completer: None,
+            flags: TypableFlags {
+                raw_args: true,
+                preview: true,
+            },
         }
     ];
 
+    /// Splits the input of the command prompt into the command name and the text following it.
+    /// The name ends at the first character that can't be part of one, so `s/a/b/` is `s`
+    /// followed by `/a/b/`.
+    pub fn split_command(input: &str) -> (&str, &str) {
+        let input = input.trim_start();
+        let end = input
+            .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '!'))
+            .unwrap_or_else(|| input.len());
+        input.split_at(end)
+    }
+
     pub static COMMANDS: Lazy<HashMap<&'static str, &'static TypableCommand>> = Lazy::new(|| {
         let mut map = HashMap::new();
 
@@ -2076,29 +2435,48 @@ fn command_mode(cx: &mut Context) {
             }
         }, // completion
         move |cx: &mut compositor::Context, input: &str, event: PromptEvent| {
-            if event != PromptEvent::Validate {
+            // the input may not be the previewed command anymore, revert what it did
+            let (view, doc) = current!(cx.editor);
+            doc.revert_preview(view.id);
+
+            let (name, rest) = cmd::split_command(input);
+            if name.is_empty() || event == PromptEvent::Abort {
                 return;
             }
 
-            let parts = input.split_whitespace().collect::<Vec<&str>>();
-            if parts.is_empty() {
+            let cmd = match cmd::COMMANDS.get(name) {
+                Some(cmd) => cmd,
+                None => {
+                    if event == PromptEvent::Validate {
+                        cx.editor.set_error(format!("no such command: '{}'", name));
+                    }
+                    return;
+                }
+            };
+            if event == PromptEvent::Update && !cmd.flags.preview {
                 return;
             }
 
-            if let Some(cmd) = cmd::COMMANDS.get(parts[0]) {
-                if let Err(e) = (cmd.fun)(cx, &parts[1..], event) {
-                    cx.editor.set_error(format!("{}", e));
-                }
+            let rest = rest.trim_start();
+            let args: Vec<&str> = if !cmd.flags.raw_args {
+                rest.split_whitespace().collect()
+            } else if rest.is_empty() {
+                Vec::new()
             } else {
-                cx.editor
-                    .set_error(format!("no such command: '{}'", parts[0]));
+                vec![rest]
             };
+
+            match (cmd.fun)(cx, &args, event) {
+                // the input is usually incomplete while previewing
+                Err(e) if event == PromptEvent::Validate => cx.editor.set_error(format!("{}", e)),
+                _ => (),
+            }
         },
     );
     prompt.doc_fn = Box::new(|input: &str| {
-        let part = input.split(' ').next().unwrap_or_default();
+        let (name, _) = cmd::split_command(input);
 
-        if let Some(cmd::TypableCommand { doc, .. }) = cmd::COMMANDS.get(part) {
+        if let Some(cmd::TypableCommand { doc, .. }) = cmd::COMMANDS.get(name) {
             return Some(doc);
         }
 
@@ -2143,6 +2521,110 @@ fn buffer_picker(cx: &mut Context) {
     cx.push_layer(Box::new(picker));
 }
 
+fn set_mark(cx: &mut Context) {
+    cx.on_next_key(move |cx, event| {
+        let name = match event {
+            KeyEvent {
+                code: KeyCode::Char(ch),
+                ..
+            } if marks::is_valid_mark(ch) => ch,
+            _ => {
+                cx.editor
+                    .set_error("Marks are named a-z, or A-Z for global marks".to_string());
+                return;
+            }
+        };
+
+        let (view, doc) = current!(cx.editor);
+        let selection = doc.selection(view.id).clone();
+        let doc_id = doc.id();
+        cx.editor.set_mark(name, doc_id, selection);
+    })
+}
+
+/// Restores the selection of the mark `name` in the current view, opening the document it's in
+/// first.
+fn restore_mark(editor: &mut Editor, name: char, action: Action) {
+    if let Err(err) = editor.open_global_mark(name) {
+        editor.set_error(format!("Failed to open mark '{}': {}", name, err));
+        return;
+    }
+
+    let current = view!(editor).doc;
+    let (doc_id, selection) = match editor.mark(name, current) {
+        Some((doc_id, selection)) => (doc_id, selection.clone()),
+        None => {
+            editor.set_error(format!("Mark '{}' is not set", name));
+            return;
+        }
+    };
+
+    push_jump(editor);
+    if doc_id != view!(editor).doc {
+        editor.switch(doc_id, action);
+    }
+
+    let (view, doc) = current!(editor);
+    doc.set_selection(view.id, selection);
+    align_view(doc, view, Align::Center);
+}
+
+fn goto_mark(cx: &mut Context) {
+    cx.on_next_key(move |cx, event| {
+        let name = match event {
+            KeyEvent {
+                code: KeyCode::Char(ch),
+                ..
+            } => ch,
+            _ => return,
+        };
+
+        restore_mark(cx.editor, name, Action::Replace);
+    })
+}
+
+fn mark_picker(cx: &mut Context) {
+    let current = view!(cx.editor).doc;
+
+    // the local marks of the current document, then all the global ones, those of closed files
+    // included
+    let mut marks = Vec::new();
+    for (id, doc) in &cx.editor.documents {
+        for (name, selection) in doc.marks().iter() {
+            if id == current || marks::is_global_mark(name) {
+                let text = doc.text().slice(..);
+                let cursor = selection.primary().cursor(text);
+                let line = text.char_to_line(cursor);
+                let label = format!(
+                    "{} {}:{} {}",
+                    name,
+                    doc.relative_path()
+                        .map(|path| path.to_string_lossy().to_string())
+                        .unwrap_or_else(|| "[scratch]".to_string()),
+                    line + 1,
+                    text.line(line).to_string().trim()
+                );
+                marks.push((name, label));
+            }
+        }
+    }
+    let cwdir = std::env::current_dir().expect("couldn't determine current directory");
+    for (name, (path, _)) in &cx.editor.global_marks {
+        let path = path.strip_prefix(&cwdir).unwrap_or(path);
+        marks.push((*name, format!("{} {}", name, path.to_string_lossy())));
+    }
+    marks.sort_by_key(|(name, _)| (marks::is_global_mark(*name), *name));
+
+    let picker = Picker::new(
+        marks,
+        |(_, label): &(char, String)| label.into(),
+        |editor: &mut Editor, (name, _): &(char, String), action| {
+            restore_mark(editor, *name, action);
+        },
+    );
+    cx.push_layer(Box::new(picker));
+}
+
 fn symbol_picker(cx: &mut Context) {
     fn nested_to_flat(
         list: &mut Vec<lsp::SymbolInformation>,
@@ -2321,13 +2803,15 @@ fn apply_workspace_edit(
 
 fn last_picker(cx: &mut Context) {
     // TODO: last picker does not seem to work well with buffer_picker
-    cx.callback = Some(Box::new(|compositor: &mut Compositor| {
-        if let Some(picker) = compositor.last_picker.take() {
-            compositor.push(picker);
-        }
-        // XXX: figure out how to show error when no last picker lifetime
-        // cx.editor.set_error("no last picker".to_owned())
-    }));
+    cx.callback = Some(Box::new(
+        |compositor: &mut Compositor, _cx: &mut compositor::Context| {
+            if let Some(picker) = compositor.last_picker.take() {
+                compositor.push(picker);
+            }
+            // XXX: figure out how to show error when no last picker lifetime
+            // cx.editor.set_error("no last picker".to_owned())
+        },
+    ));
 }
 
 // I inserts at the first nonwhitespace character of each line with a selection
@@ -2473,6 +2957,7 @@ fn normal_mode(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
 
     doc.mode = Mode::Normal;
+    doc.snippet = None;
 
     doc.append_changes_to_history(view.id);
 
@@ -2880,21 +3365,97 @@ fn goto_prev_diag(cx: &mut Context) {
     goto_pos(editor, diag);
 }
 
-fn signature_help(cx: &mut Context) {
+fn goto_ts_object_impl(cx: &mut Context, object: &str, direction: Direction) {
+    let count = cx.count();
     let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
 
-    let language_server = match doc.language_server() {
-        Some(language_server) => language_server,
-        None => return,
+    let selection = match doc.language_config().zip(doc.syntax()) {
+        Some((lang_config, syntax)) => {
+            let root = syntax.tree().root_node();
+            doc.selection(view.id).clone().transform(|range| {
+                let new_range = movement::goto_treesitter_object(
+                    text,
+                    range,
+                    object,
+                    direction,
+                    root,
+                    lang_config,
+                    count,
+                );
+
+                if doc.mode == Mode::Select {
+                    range.extend(new_range.from(), new_range.to())
+                } else {
+                    new_range
+                }
+            })
+        }
+        None => {
+            cx.editor
+                .set_error("Syntax tree is not available in current buffer".to_string());
+            return;
+        }
     };
 
-    let pos = pos_to_lsp_pos(
-        doc.text(),
-        doc.selection(view.id)
-            .primary()
-            .cursor(doc.text().slice(..)),
-        language_server.offset_encoding(),
-    );
+    doc.set_selection(view.id, selection);
+}
+
+fn goto_next_function(cx: &mut Context) {
+    goto_ts_object_impl(cx, "function", Direction::Forward)
+}
+
+fn goto_prev_function(cx: &mut Context) {
+    goto_ts_object_impl(cx, "function", Direction::Backward)
+}
+
+fn goto_next_class(cx: &mut Context) {
+    goto_ts_object_impl(cx, "class", Direction::Forward)
+}
+
+fn goto_prev_class(cx: &mut Context) {
+    goto_ts_object_impl(cx, "class", Direction::Backward)
+}
+
+fn goto_next_parameter(cx: &mut Context) {
+    goto_ts_object_impl(cx, "parameter", Direction::Forward)
+}
+
+fn goto_prev_parameter(cx: &mut Context) {
+    goto_ts_object_impl(cx, "parameter", Direction::Backward)
+}
+
+fn goto_next_comment(cx: &mut Context) {
+    goto_ts_object_impl(cx, "comment", Direction::Forward)
+}
+
+fn goto_prev_comment(cx: &mut Context) {
+    goto_ts_object_impl(cx, "comment", Direction::Backward)
+}
+
+fn goto_next_test(cx: &mut Context) {
+    goto_ts_object_impl(cx, "test", Direction::Forward)
+}
+
+fn goto_prev_test(cx: &mut Context) {
+    goto_ts_object_impl(cx, "test", Direction::Backward)
+}
+
+fn signature_help(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+
+    let language_server = match doc.language_server() {
+        Some(language_server) => language_server,
+        None => return,
+    };
+
+    let pos = pos_to_lsp_pos(
+        doc.text(),
+        doc.selection(view.id)
+            .primary()
+            .cursor(doc.text().slice(..)),
+        language_server.offset_encoding(),
+    );
 
     let future = language_server.text_document_signature_help(doc.identifier(), pos, None);
 
@@ -3007,20 +3568,46 @@ pub mod insert {
     }
 
     use helix_core::auto_pairs;
-    const HOOKS: &[Hook] = &[auto_pairs::hook, insert];
+    const HOOKS: &[Hook] = &[insert];
     const POST_HOOKS: &[PostHook] = &[completion, signature_help];
 
     pub fn insert_char(cx: &mut Context, c: char) {
         let (view, doc) = current!(cx.editor);
 
+        // typing over the placeholder of a snippet tabstop replaces it
+        if let Some(snippet) = &doc.snippet {
+            let selection = doc.selection(view.id);
+            if snippet.is_placeholder_selected(selection) {
+                let transaction =
+                    Transaction::change_by_selection(doc.text(), selection, |range| {
+                        (range.from(), range.to(), None)
+                    });
+                doc.apply(&transaction, view.id);
+            }
+        }
+
         let text = doc.text();
-        let selection = doc.selection(view.id).clone().cursors(text.slice(..));
+        let selection = doc.selection(view.id);
 
-        // run through insert hooks, stopping on the first one that returns Some(t)
-        for hook in HOOKS {
-            if let Some(transaction) = hook(text, &selection, c) {
+        // auto pairs work on the selection itself so they can surround it
+        let cursor = selection.primary().cursor(text.slice(..));
+        let transaction = doc
+            .auto_pairs_at(cursor)
+            .and_then(|pairs| auto_pairs::hook(text, selection, c, pairs, doc.syntax()));
+
+        match transaction {
+            Some(transaction) => {
                 doc.apply(&transaction, view.id);
-                break;
+            }
+            None => {
+                let selection = selection.clone().cursors(text.slice(..));
+                // run through insert hooks, stopping on the first one that returns Some(t)
+                for hook in HOOKS {
+                    if let Some(transaction) = hook(text, &selection, c) {
+                        doc.apply(&transaction, view.id);
+                        break;
+                    }
+                }
             }
         }
 
@@ -3034,6 +3621,18 @@ pub mod insert {
 
     pub fn insert_tab(cx: &mut Context) {
         let (view, doc) = current!(cx.editor);
+
+        // inside a snippet, jump to the next tabstop instead
+        if let Some(snippet) = &mut doc.snippet {
+            snippet.next_tabstop();
+            let selection = snippet.selection();
+            if snippet.is_final() {
+                doc.snippet = None;
+            }
+            doc.set_selection(view.id, selection);
+            return;
+        }
+
         // TODO: round out to nearest indentation level (for example a line with 3 spaces should
         // indent by one to reach 4 spaces).
 
@@ -3057,6 +3656,8 @@ pub mod insert {
         // TODO: this is annoying, but we need to do it to properly calculate pos after edits
         let mut offs = 0;
 
+        let pairs = doc.auto_pairs_at(selection.primary().head);
+
         let mut transaction = Transaction::change_by_selection(contents, &selection, |range| {
             let pos = range.head;
 
@@ -3095,7 +3696,7 @@ pub mod insert {
             ));
 
             // if between a bracket pair
-            if helix_core::auto_pairs::PAIRS.contains(&(prev, curr)) {
+            if pairs.map_or(false, |pairs| pairs.contains(prev, curr)) {
                 // another newline, indent the end bracket one level less
                 let indent = doc.indent_unit().repeat(indent_level.saturating_sub(1));
                 text.push_str(doc.line_ending.as_str());
@@ -3113,6 +3714,16 @@ pub mod insert {
         doc.apply(&transaction, view.id);
     }
 
+    pub fn goto_prev_tabstop(cx: &mut Context) {
+        let (view, doc) = current!(cx.editor);
+        if let Some(snippet) = &mut doc.snippet {
+            if snippet.prev_tabstop() {
+                let selection = snippet.selection();
+                doc.set_selection(view.id, selection);
+            }
+        }
+    }
+
     // TODO: handle indent-aware delete
     pub fn delete_char_backward(cx: &mut Context) {
         let count = cx.count();
@@ -3121,9 +3732,19 @@ pub mod insert {
         let transaction =
             Transaction::change_by_selection(doc.text(), doc.selection(view.id), |range| {
                 let pos = range.cursor(text);
+                let mut end = pos;
+                // deleting the opening half of an empty pair deletes both
+                if count == 1 && pos > 0 {
+                    if let (Some(pairs), Some(next)) = (doc.auto_pairs_at(pos), text.get_char(pos))
+                    {
+                        if pairs.contains(text.char(pos - 1), next) {
+                            end += 1;
+                        }
+                    }
+                }
                 (
                     graphemes::nth_prev_grapheme_boundary(text, pos, count),
-                    pos,
+                    end,
                     None,
                 )
             });
@@ -3177,6 +3798,12 @@ fn redo(cx: &mut Context) {
     doc.redo(view_id);
 }
 
+fn undo_tree(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let undo_tree = ui::UndoTree::new(doc, view.id);
+    cx.push_layer(Box::new(undo_tree));
+}
+
 // Yank / Paste
 
 fn yank(cx: &mut Context) {
@@ -3477,6 +4104,28 @@ fn unindent(cx: &mut Context) {
     doc.append_changes_to_history(view.id);
 }
 
+fn reindent_selections(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+
+    let language_config = doc.language_config();
+    if doc.syntax().is_none() || language_config.and_then(|lc| lc.indent_query()).is_none() {
+        cx.editor
+            .set_error("Reindenting requires the indent queries of the language".to_string());
+        return;
+    }
+
+    let transaction = indent::reindent(
+        doc.text(),
+        doc.selection(view.id),
+        language_config,
+        doc.syntax(),
+        doc.indent_unit(),
+    );
+
+    doc.apply(&transaction, view.id);
+    doc.append_changes_to_history(view.id);
+}
+
 fn format_selections(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
 
@@ -3734,16 +4383,180 @@ fn hover(cx: &mut Context) {
 // comments
 fn toggle_comments(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
-    let token = doc
-        .language_config()
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+    let cursor = selection.primary().cursor(text);
+    let config = doc.language_config_at(cursor);
+    let token = config
         .and_then(|lc| lc.comment_token.as_ref())
         .map(|tc| tc.as_ref());
-    let transaction = comment::toggle_line_comments(doc.text(), doc.selection(view.id), token);
+    let block_tokens = config.and_then(|lc| lc.block_comment_tokens.as_deref());
+
+    let transaction = match (token, block_tokens) {
+        // without a line comment token, comment each line with a block comment
+        (None, Some(tokens)) => {
+            let lines = comment::split_lines_of_selection(text, selection);
+            let transaction = comment::toggle_block_comments(doc.text(), &lines, tokens);
+            // keep the selection rather than the lines, it gets mapped through the changes
+            Transaction::from(transaction.changes().clone())
+        }
+        _ => comment::toggle_line_comments(doc.text(), selection, token),
+    };
+
+    doc.apply(&transaction, view.id);
+    doc.append_changes_to_history(view.id);
+}
+
+fn toggle_block_comments(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let selection = doc.selection(view.id);
+    let cursor = selection.primary().cursor(doc.text().slice(..));
+    let tokens = match doc
+        .language_config_at(cursor)
+        .and_then(|lc| lc.block_comment_tokens.as_deref())
+    {
+        Some(tokens) => tokens,
+        None => {
+            cx.editor
+                .set_error("Block comments are not supported for this language".to_string());
+            return;
+        }
+    };
+    let transaction = comment::toggle_block_comments(doc.text(), selection, tokens);
+
+    doc.apply(&transaction, view.id);
+    doc.append_changes_to_history(view.id);
+}
+
+fn align_selections_impl(cx: &mut Context, align_end: bool) {
+    // a count aligns to that (1-based) column instead of the furthest selection
+    let column = cx.count.map(|count| count.get() - 1);
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+    let tab_width = doc.tab_width();
+
+    let mut last_line = None;
+    let mut columns = Vec::with_capacity(selection.len());
+    for range in selection {
+        let line = text.char_to_line(range.from());
+        if last_line == Some(line) {
+            cx.editor
+                .set_error("Can't align multiple selections on the same line".to_string());
+            return;
+        }
+        last_line = Some(line);
+
+        let pos = if align_end {
+            // stop at the end of the first line of multiline selections
+            range.to().min(line_end_char_index(&text, line))
+        } else {
+            range.from()
+        };
+        columns.push(visual_coords_at_pos(text, pos, tab_width).col);
+    }
+
+    let max = columns.iter().copied().max().unwrap_or(0);
+    let target = column.unwrap_or(max);
+
+    let changes = selection
+        .iter()
+        .zip(columns)
+        .filter(|(_, col)| *col < target)
+        .map(|(range, col)| {
+            let padding = Tendril::from(" ".repeat(target - col));
+            (range.from(), range.from(), Some(padding))
+        });
+    let transaction = Transaction::change(doc.text(), changes);
+
+    doc.apply(&transaction, view.id);
+    doc.append_changes_to_history(view.id);
+}
+
+fn align_selections(cx: &mut Context) {
+    align_selections_impl(cx, false)
+}
+
+fn align_selection_ends(cx: &mut Context) {
+    align_selections_impl(cx, true)
+}
+
+fn increment_impl(cx: &mut Context, amount: i64, sequence: bool) {
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+
+    // (selection index, from, to, replacement)
+    let mut edits: Vec<_> = selection
+        .iter()
+        .enumerate()
+        .filter_map(|(i, range)| {
+            // the n-th selection increments n times as much
+            let amount = if sequence {
+                amount.checked_mul(i as i64 + 1)?
+            } else {
+                amount
+            };
+            let (from, to, replacement) = increment::increment(text, *range, amount)?;
+            Some((i, from, to, replacement))
+        })
+        .collect();
+    if edits.is_empty() {
+        return;
+    }
+
+    // selections on the same number only change it once
+    edits.sort_by_key(|(_, from, _, _)| *from);
+    let mut last_end = 0;
+    edits.retain(|(_, from, to, _)| {
+        let keep = *from >= last_end;
+        if keep {
+            last_end = *to;
+        }
+        keep
+    });
+
+    let transaction = Transaction::change(
+        doc.text(),
+        edits
+            .iter()
+            .map(|(_, from, to, replacement)| (*from, *to, Some(replacement.clone()))),
+    );
+
+    // select the changed text, the other selections are mapped through the changes
+    let mut ranges: SmallVec<[Range; 1]> = selection
+        .iter()
+        .map(|range| range.map(transaction.changes()))
+        .collect();
+    let mut offs = 0isize;
+    for (i, from, to, replacement) in &edits {
+        let len = replacement.chars().count();
+        let start = (*from as isize + offs) as usize;
+        ranges[*i] = Range::new(start, start + len);
+        offs += len as isize - (to - from) as isize;
+    }
+    let transaction = transaction.with_selection(Selection::new(ranges, selection.primary_index()));
 
     doc.apply(&transaction, view.id);
     doc.append_changes_to_history(view.id);
 }
 
+fn increment(cx: &mut Context) {
+    increment_impl(cx, cx.count() as i64, false)
+}
+
+fn decrement(cx: &mut Context) {
+    increment_impl(cx, -(cx.count() as i64), false)
+}
+
+fn increment_sequence(cx: &mut Context) {
+    increment_impl(cx, cx.count() as i64, true)
+}
+
+fn decrement_sequence(cx: &mut Context) {
+    increment_impl(cx, -(cx.count() as i64), true)
+}
+
 fn rotate_selections(cx: &mut Context, direction: Direction) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
@@ -3813,26 +4626,76 @@ fn expand_selection(cx: &mut Context) {
 
     if let Some(syntax) = doc.syntax() {
         let text = doc.text().slice(..);
-        let selection = object::expand_selection(syntax, text, doc.selection(view.id));
+        let current_selection = doc.selection(view.id);
+        let selection = object::expand_selection(syntax, text, current_selection);
+
+        if *current_selection != selection {
+            // save the current selection so shrinking can restore it
+            view.object_selections.push(current_selection.clone());
+            doc.set_selection(view.id, selection);
+        }
+    }
+}
+
+fn shrink_selection(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let current_selection = doc.selection(view.id);
+
+    if let Some(prev_selection) = view.object_selections.pop() {
+        // the selection changed since it was expanded, the history doesn't apply anymore
+        if current_selection.contains(&prev_selection) {
+            doc.set_selection(view.id, prev_selection);
+            return;
+        }
+        view.object_selections.clear();
+    }
+
+    // nothing to restore, shrink to the first child instead
+    if let Some(syntax) = doc.syntax() {
+        let text = doc.text().slice(..);
+        let selection = object::select_first_child(syntax, text, current_selection);
         doc.set_selection(view.id, selection);
     }
 }
 
-fn match_brackets(cx: &mut Context) {
+fn select_node_impl<F>(cx: &mut Context, select_fn: F)
+where
+    F: Fn(&Syntax, RopeSlice, &Selection) -> Selection,
+{
     let (view, doc) = current!(cx.editor);
 
     if let Some(syntax) = doc.syntax() {
-        let pos = doc
-            .selection(view.id)
-            .primary()
-            .cursor(doc.text().slice(..));
-        if let Some(pos) = match_brackets::find(syntax, doc.text(), pos) {
-            let selection = Selection::point(pos);
-            doc.set_selection(view.id, selection);
-        };
+        let text = doc.text().slice(..);
+        let selection = select_fn(syntax, text, doc.selection(view.id));
+        doc.set_selection(view.id, selection);
     }
 }
 
+fn select_first_child(cx: &mut Context) {
+    select_node_impl(cx, object::select_first_child)
+}
+
+fn select_next_sibling(cx: &mut Context) {
+    select_node_impl(cx, object::select_next_sibling)
+}
+
+fn select_prev_sibling(cx: &mut Context) {
+    select_node_impl(cx, object::select_prev_sibling)
+}
+
+fn match_brackets(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+
+    let pos = doc
+        .selection(view.id)
+        .primary()
+        .cursor(doc.text().slice(..));
+    if let Some(pos) = match_brackets::find_matching_bracket(doc.syntax(), doc.text(), pos) {
+        let selection = Selection::point(pos);
+        doc.set_selection(view.id, selection);
+    };
+}
+
 //
 
 fn jump_forward(cx: &mut Context) {
@@ -3876,13 +4739,14 @@ fn split(cx: &mut Context, action: Action) {
     let (view, doc) = current!(cx.editor);
     let id = doc.id();
     let selection = doc.selection(view.id).clone();
-    let first_line = view.first_line;
+    let (first_line, first_row) = (view.first_line, view.first_row);
 
     cx.editor.switch(id, action);
 
     // match the selection in the previous view
     let (view, doc) = current!(cx.editor);
     view.first_line = first_line;
+    view.first_row = first_row;
     doc.set_selection(view.id, selection);
 }
 
@@ -3908,6 +4772,86 @@ fn select_register(cx: &mut Context) {
     })
 }
 
+/// The register selected for a macro, `@` unless another one was selected.
+fn macro_register(cx: &Context) -> char {
+    if cx.selected_register.is_default() {
+        '@'
+    } else {
+        cx.selected_register.name()
+    }
+}
+
+fn record_macro(cx: &mut Context) {
+    if let Some((reg, mut keys)) = cx.editor.macro_recording.take() {
+        // remove the keys of the binding that ended the recording
+        keys.truncate(keys.len().saturating_sub(cx.binding_len));
+        let keys = helix_view::input::serialize_macro(&keys);
+        cx.editor.registers.write(reg, vec![keys]);
+        cx.editor
+            .set_status(format!("Recorded macro to register [{}]", reg));
+    } else {
+        let reg = macro_register(cx);
+        cx.editor.macro_recording = Some((reg, Vec::new()));
+        cx.editor
+            .set_status(format!("Recording macro to register [{}]", reg));
+    }
+}
+
+fn replay_macro(cx: &mut Context) {
+    let reg = macro_register(cx);
+    if cx.editor.macro_replaying.contains(&reg) {
+        cx.editor.set_error(format!(
+            "Can't replay the macro of register [{}] from within itself",
+            reg
+        ));
+        return;
+    }
+
+    let keys = match cx
+        .editor
+        .registers
+        .read(reg)
+        .and_then(|values| values.first())
+    {
+        Some(keys) => keys,
+        None => {
+            cx.editor
+                .set_error(format!("Register [{}] holds no macro", reg));
+            return;
+        }
+    };
+    let keys = match helix_view::input::parse_macro(keys) {
+        Ok(keys) => keys,
+        Err(err) => {
+            cx.editor
+                .set_error(format!("Invalid macro in register [{}]: {}", reg, err));
+            return;
+        }
+    };
+    let count = cx.count();
+
+    // the keys go through the compositor like typed ones, so they reach prompts and pickers too
+    cx.callback = Some(Box::new(
+        move |compositor: &mut Compositor, cx: &mut compositor::Context| {
+            cx.editor.macro_replaying.push(reg);
+            // keep the edits of the replay apart from the ones around it in the history
+            commit_changes_to_history(cx.editor);
+            for _ in 0..count {
+                for &key in &keys {
+                    compositor.handle_event(crossterm::event::Event::Key(key.into()), cx);
+                }
+            }
+            commit_changes_to_history(cx.editor);
+            cx.editor.macro_replaying.pop();
+        },
+    ));
+}
+
+fn commit_changes_to_history(editor: &mut Editor) {
+    let (view, doc) = current!(editor);
+    doc.append_changes_to_history(view.id);
+}
+
 fn align_view_top(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
     align_view(doc, view, Align::Top);
@@ -3959,9 +4903,31 @@ fn select_textobject(cx: &mut Context, objtype: textobject::TextObject) {
             let (view, doc) = current!(cx.editor);
             let text = doc.text().slice(..);
 
+            let textobject_treesitter = |obj_name: &str, range: Range| -> Range {
+                let (lang_config, syntax) = match doc.language_config().zip(doc.syntax()) {
+                    Some(t) => t,
+                    None => return range,
+                };
+                textobject::textobject_treesitter(
+                    text,
+                    range,
+                    objtype,
+                    obj_name,
+                    syntax.tree().root_node(),
+                    lang_config,
+                    count,
+                )
+            };
+
             let selection = doc.selection(view.id).clone().transform(|range| {
                 match ch {
                     'w' => textobject::textobject_word(text, range, objtype, count),
+                    'S' => textobject::textobject_sub_word(text, range, objtype, count),
+                    'f' => textobject_treesitter("function", range),
+                    't' => textobject_treesitter("class", range),
+                    'a' => textobject_treesitter("parameter", range),
+                    'c' => textobject_treesitter("comment", range),
+                    'T' => textobject_treesitter("test", range),
                     // TODO: cancel new ranges if inconsistent surround matches across lines
                     ch if !ch.is_ascii_alphanumeric() => {
                         textobject::textobject_surround(text, range, objtype, ch, count)
@@ -3974,61 +4940,116 @@ fn select_textobject(cx: &mut Context, objtype: textobject::TextObject) {
     })
 }
 
+/// The multi character surround pairs of the document's language.
+fn surround_pairs(doc: &Document) -> Option<&HashMap<char, SurroundPair>> {
+    doc.language_config()
+        .and_then(|config| config.surround_pairs.as_ref())
+}
+
+/// Prompts for the name of the tag to surround with, attributes can follow the name.
+fn surround_tag_prompt(
+    cx: &mut Context,
+    mut callback: impl FnMut(&mut Editor, &Surround) + 'static,
+) {
+    let prompt = Prompt::new(
+        "tag:".to_owned(),
+        None,
+        |_input: &str| Vec::new(),
+        move |cx: &mut compositor::Context, input: &str, event: PromptEvent| {
+            let tag = input.trim();
+            if event != PromptEvent::Validate || tag.is_empty() {
+                return;
+            }
+            callback(cx.editor, &Surround::Tag(tag.to_string()));
+        },
+    );
+    cx.push_layer(Box::new(prompt));
+}
+
 fn surround_add(cx: &mut Context) {
+    let count = cx.count();
     cx.on_next_key(move |cx, event| {
         if let Some(ch) = event.char() {
-            let (view, doc) = current!(cx.editor);
-            let selection = doc.selection(view.id);
-            let (open, close) = surround::get_pair(ch);
-
-            let mut changes = Vec::new();
-            for range in selection.iter() {
-                changes.push((range.from(), range.from(), Some(Tendril::from_char(open))));
-                changes.push((range.to(), range.to(), Some(Tendril::from_char(close))));
+            let surround = Surround::from_char(ch, surround_pairs(doc_mut!(cx.editor)));
+            match surround {
+                Surround::Tag(_) => surround_tag_prompt(cx, move |editor, surround| {
+                    surround_add_impl(editor, surround, count)
+                }),
+                surround => surround_add_impl(cx.editor, &surround, count),
             }
-
-            let transaction = Transaction::change(doc.text(), changes.into_iter());
-            doc.apply(&transaction, view.id);
-            doc.append_changes_to_history(view.id);
         }
     })
 }
 
+fn surround_add_impl(editor: &mut Editor, surround: &Surround, count: usize) {
+    let (view, doc) = current!(editor);
+    let selection = doc.selection(view.id);
+    // a count surrounds several times
+    let (open, close) = surround.open_close();
+    let open = Tendril::from(open.repeat(count));
+    let close = Tendril::from(close.repeat(count));
+
+    let mut changes = Vec::with_capacity(selection.len() * 2);
+    for range in selection.iter() {
+        changes.push((range.from(), range.from(), Some(open.clone())));
+        changes.push((range.to(), range.to(), Some(close.clone())));
+    }
+
+    let transaction = Transaction::change(doc.text(), changes.into_iter());
+    doc.apply(&transaction, view.id);
+    doc.append_changes_to_history(view.id);
+}
+
 fn surround_replace(cx: &mut Context) {
     let count = cx.count();
     cx.on_next_key(move |cx, event| {
         if let Some(from) = event.char() {
             cx.on_next_key(move |cx, event| {
                 if let Some(to) = event.char() {
-                    let (view, doc) = current!(cx.editor);
-                    let text = doc.text().slice(..);
-                    let selection = doc.selection(view.id);
-
-                    let change_pos = match surround::get_surround_pos(text, selection, from, count)
-                    {
-                        Some(c) => c,
-                        None => return,
-                    };
-
-                    let (open, close) = surround::get_pair(to);
-                    let transaction = Transaction::change(
-                        doc.text(),
-                        change_pos.iter().enumerate().map(|(i, &pos)| {
-                            if i % 2 == 0 {
-                                (pos, pos + 1, Some(Tendril::from_char(open)))
-                            } else {
-                                (pos.saturating_sub(1), pos, Some(Tendril::from_char(close)))
-                            }
+                    let pairs = surround_pairs(doc_mut!(cx.editor));
+                    let from = Surround::from_char(from, pairs);
+                    let to = Surround::from_char(to, pairs);
+                    match to {
+                        Surround::Tag(_) => surround_tag_prompt(cx, move |editor, to| {
+                            surround_replace_impl(editor, &from, to, count)
                         }),
-                    );
-                    doc.apply(&transaction, view.id);
-                    doc.append_changes_to_history(view.id);
+                        to => surround_replace_impl(cx.editor, &from, &to, count),
+                    }
                 }
             });
         }
     })
 }
 
+fn surround_replace_impl(editor: &mut Editor, from: &Surround, to: &Surround, count: usize) {
+    let (view, doc) = current!(editor);
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+
+    let change_pos = match surround::get_surround_pos(text, selection, from, doc.syntax(), count) {
+        Some(c) => c,
+        None => return,
+    };
+
+    let (open, close) = to.open_close();
+    let (open, close) = (Tendril::from(open), Tendril::from(close));
+    let mut changes: Vec<_> = change_pos
+        .iter()
+        .flat_map(|pos| {
+            vec![
+                (pos.open.0, pos.open.1, Some(open.clone())),
+                (pos.close.0, pos.close.1, Some(close.clone())),
+            ]
+        })
+        .collect();
+    // the pairs of different selections can be nested
+    changes.sort_by_key(|(from, _, _)| *from);
+
+    let transaction = Transaction::change(doc.text(), changes.into_iter());
+    doc.apply(&transaction, view.id);
+    doc.append_changes_to_history(view.id);
+}
+
 fn surround_delete(cx: &mut Context) {
     let count = cx.count();
     cx.on_next_key(move |cx, event| {
@@ -4036,14 +5057,26 @@ fn surround_delete(cx: &mut Context) {
             let (view, doc) = current!(cx.editor);
             let text = doc.text().slice(..);
             let selection = doc.selection(view.id);
+            let surround = Surround::from_char(ch, surround_pairs(doc));
 
-            let change_pos = match surround::get_surround_pos(text, selection, ch, count) {
-                Some(c) => c,
-                None => return,
-            };
+            let change_pos =
+                match surround::get_surround_pos(text, selection, &surround, doc.syntax(), count) {
+                    Some(c) => c,
+                    None => return,
+                };
 
-            let transaction =
-                Transaction::change(doc.text(), change_pos.into_iter().map(|p| (p, p + 1, None)));
+            let mut changes: Vec<_> = change_pos
+                .iter()
+                .flat_map(|pos| {
+                    vec![
+                        (pos.open.0, pos.open.1, None),
+                        (pos.close.0, pos.close.1, None),
+                    ]
+                })
+                .collect();
+            changes.sort_by_key(|(from, _, _)| *from);
+
+            let transaction = Transaction::change(doc.text(), changes.into_iter());
             doc.apply(&transaction, view.id);
             doc.append_changes_to_history(view.id);
         }
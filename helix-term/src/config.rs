@@ -1,4 +1,7 @@
+use anyhow::{anyhow, Context};
+use helix_core::syntax;
 use serde::Deserialize;
+use std::path::Path;
 
 use crate::keymap::Keymaps;
 
@@ -19,6 +22,60 @@ pub struct LspConfig {
     pub display_messages: bool,
 }
 
+/// Loads the default `languages.toml` with the ones of the config directory and of the
+/// project's `.helix` directory merged over it, in that order.
+pub fn lang_config() -> anyhow::Result<syntax::Configuration> {
+    let default: toml::Value = toml::from_str(include_str!("../../languages.toml"))
+        .expect("Could not parse the default languages.toml");
+
+    let mut paths = vec![helix_core::config_dir().join("languages.toml")];
+    if let Some(root) = helix_core::find_root(None) {
+        paths.push(root.join(".helix").join("languages.toml"));
+    }
+
+    let mut config = default;
+    for path in paths.iter().filter(|path| path.exists()) {
+        let value = std::fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(toml::from_str(&contents)?))
+            .with_context(|| format!("failed to load {}", path.display()))?;
+        config = syntax::merge_toml_values(config, value);
+        // errors are checked after each merge to know which file they're from
+        parse_lang_config(config.clone(), path)?;
+    }
+
+    parse_lang_config(config, Path::new("languages.toml"))
+}
+
+/// Deserializes the languages one by one so errors name the language they're in.
+fn parse_lang_config(config: toml::Value, path: &Path) -> anyhow::Result<syntax::Configuration> {
+    let languages = match config.get("language") {
+        Some(toml::Value::Array(languages)) => languages.clone(),
+        _ => {
+            return Err(anyhow!(
+                "{}: expected a `[[language]]` list",
+                path.display()
+            ))
+        }
+    };
+
+    let language = languages
+        .into_iter()
+        .map(|language| {
+            let name = language
+                .get("name")
+                .and_then(toml::Value::as_str)
+                .unwrap_or("<unnamed>")
+                .to_string();
+            language
+                .try_into()
+                .map_err(|err| anyhow!("{}: invalid language `{}`: {}", path.display(), name, err))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(syntax::Configuration { language })
+}
+
 #[test]
 fn parsing_keymaps_config_file() {
     use crate::keymap;
@@ -51,3 +108,24 @@ fn parsing_keymaps_config_file() {
         }
     );
 }
+
+#[test]
+fn parsing_lang_config_errors() {
+    let config: toml::Value = toml::from_str(
+        r#"
+            [[language]]
+            name = "rust"
+            scope = "source.rust"
+            file-types = ["rs"]
+            roots = []
+            indent = { tab-width = "four", unit = "    " }
+        "#,
+    )
+    .unwrap();
+
+    let err = parse_lang_config(config, Path::new("/project/.helix/languages.toml"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("/project/.helix/languages.toml: invalid language `rust`: "));
+    assert!(err.contains("indent.tab-width"), "{}", err);
+}
@@ -355,6 +355,10 @@ impl Default for Keymaps {
             "B" => move_prev_long_word_start,
             "E" => move_next_long_word_end,
 
+            "A-w" => move_next_sub_word_start,
+            "A-b" => move_prev_sub_word_start,
+            "A-e" => move_next_sub_word_end,
+
             "v" => select_mode,
             "G" => goto_line,
             "g" => { "Goto"
@@ -399,6 +403,11 @@ impl Default for Keymaps {
             "%" => select_all,
             "x" => extend_line,
             "X" => extend_to_line_bounds,
+            "A-o" => expand_selection,
+            "A-i" => shrink_selection,
+            "A-I" => select_first_child,
+            "A-n" => select_next_sibling,
+            "A-p" => select_prev_sibling,
             // crop_to_whole_line
 
             "m" => { "Match"
@@ -412,10 +421,20 @@ impl Default for Keymaps {
             "[" => { "Left bracket"
                 "d" => goto_prev_diag,
                 "D" => goto_first_diag,
+                "f" => goto_prev_function,
+                "t" => goto_prev_class,
+                "a" => goto_prev_parameter,
+                "c" => goto_prev_comment,
+                "T" => goto_prev_test,
             },
             "]" => { "Right bracket"
                 "d" => goto_next_diag,
                 "D" => goto_last_diag,
+                "f" => goto_next_function,
+                "t" => goto_next_class,
+                "a" => goto_next_parameter,
+                "c" => goto_next_comment,
+                "T" => goto_next_test,
             },
 
             "/" => search,
@@ -424,6 +443,13 @@ impl Default for Keymaps {
             "N" => extend_search_next,
             // N for search_prev
             "*" => search_selection,
+            "A-*" => select_all_matches,
+            "C-n" => add_next_match,
+            "C-k" => skip_to_next_match,
+            "C-p" => remove_last_match,
+
+            "M" => set_mark,
+            "'" => goto_mark,
 
             "u" => undo,
             "U" => redo,
@@ -437,6 +463,7 @@ impl Default for Keymaps {
             ">" => indent,
             "<" => unindent,
             "=" => format_selections,
+            "A-=" => reindent_selections,
             "J" => join_selections,
             // TODO: conflicts hover/doc
             "K" => keep_selections,
@@ -445,10 +472,11 @@ impl Default for Keymaps {
             // TODO: clashes with space mode
             "space" => keep_primary_selection,
 
-            // "q" => record_macro,
-            // "Q" => replay_macro,
+            "q" => record_macro,
+            "Q" => replay_macro,
 
-            // & align selections
+            "&" => align_selections,
+            "A-&" => align_selection_ends,
             // _ trim selections
 
             "(" => rotate_selections_backward,
@@ -473,6 +501,9 @@ impl Default for Keymaps {
             "C-c" => toggle_comments,
             "K" => hover,
 
+            "C-a" => increment,
+            "C-x" => decrement,
+
             // z family for save/restore/combine from/to sels from register
 
             "tab" => jump_forward, // tab == <C-i>
@@ -485,6 +516,8 @@ impl Default for Keymaps {
                 "s" => symbol_picker,
                 "a" => code_action,
                 "'" => last_picker,
+                "m" => mark_picker,
+                "C" => toggle_block_comments,
                 "w" => { "Window"
                     "C-w" | "w" => rotate_view,
                     "C-h" | "h" => hsplit,
@@ -496,6 +529,7 @@ impl Default for Keymaps {
                 "p" => paste_clipboard_after,
                 "P" => paste_clipboard_before,
                 "R" => replace_selections_with_clipboard,
+                "u" => undo_tree,
                 "space" => keep_primary_selection,
             },
             "z" => { "View"
@@ -521,6 +555,10 @@ impl Default for Keymaps {
             "b" => extend_prev_word_start,
             "e" => extend_next_word_end,
 
+            "A-w" => extend_next_sub_word_start,
+            "A-b" => extend_prev_sub_word_start,
+            "A-e" => extend_next_sub_word_end,
+
             "t" => extend_till_char,
             "f" => extend_next_char,
             "T" => extend_till_prev_char,
@@ -537,6 +575,7 @@ impl Default for Keymaps {
             "del" => delete_char_forward,
             "ret" => insert_newline,
             "tab" => insert_tab,
+            "backtab" => goto_prev_tabstop,
             "C-w" => delete_word_backward,
 
             "left" => move_char_left,
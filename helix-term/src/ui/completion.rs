@@ -4,8 +4,11 @@ use tui::buffer::Buffer as Surface;
 
 use std::borrow::Cow;
 
-use helix_core::Transaction;
-use helix_view::{graphics::Rect, Editor};
+use helix_core::{
+    snippets::{self, ActiveSnippet, RenderedSnippet},
+    Selection, Transaction,
+};
+use helix_view::{graphics::Rect, Document, Editor};
 
 use crate::commands;
 use crate::ui::{menu, Markdown, Menu, Popup, PromptEvent};
@@ -98,32 +101,61 @@ impl Completion {
                         doc.apply(&remove, view.id);
                     }
 
-                    let transaction = if let Some(edit) = &item.text_edit {
-                        let edit = match edit {
-                            lsp::CompletionTextEdit::Edit(edit) => edit.clone(),
-                            lsp::CompletionTextEdit::InsertAndReplace(item) => {
-                                unimplemented!("completion: insert_and_replace {:?}", item)
+                    let (start, end, text) = match &item.text_edit {
+                        Some(edit) => {
+                            let edit = match edit {
+                                lsp::CompletionTextEdit::Edit(edit) => edit.clone(),
+                                lsp::CompletionTextEdit::InsertAndReplace(item) => {
+                                    unimplemented!("completion: insert_and_replace {:?}", item)
+                                }
+                            };
+                            // TODO: should probably transcode in Client
+                            match util::lsp_range_to_range(doc.text(), edit.range, offset_encoding)
+                            {
+                                Some(range) => (range.from(), range.to(), edit.new_text),
+                                None => return,
                             }
-                        };
-                        util::generate_transaction_from_edits(
-                            doc.text(),
-                            vec![edit],
-                            offset_encoding, // TODO: should probably transcode in Client
-                        )
-                    } else {
-                        let text = item.insert_text.as_ref().unwrap_or(&item.label);
-                        let cursor = doc
-                            .selection(view.id)
-                            .primary()
-                            .cursor(doc.text().slice(..));
-                        Transaction::change(
-                            doc.text(),
-                            vec![(cursor, cursor, Some(text.as_str().into()))].into_iter(),
-                        )
+                        }
+                        None => {
+                            let cursor = doc
+                                .selection(view.id)
+                                .primary()
+                                .cursor(doc.text().slice(..));
+                            let text = item.insert_text.as_ref().unwrap_or(&item.label);
+                            (cursor, cursor, text.clone())
+                        }
                     };
 
+                    let snippet = match item.insert_text_format {
+                        Some(lsp::InsertTextFormat::Snippet) => {
+                            Some(render_snippet(doc, start, &text))
+                        }
+                        _ => None,
+                    };
+                    let text = snippet
+                        .as_ref()
+                        .map_or(text, |snippet| snippet.text.clone());
+
+                    let transaction = Transaction::change(
+                        doc.text(),
+                        vec![(start, end, Some(text.into()))].into_iter(),
+                    );
                     doc.apply(&transaction, view.id);
 
+                    if let Some(snippet) = snippet {
+                        // select the first tabstop, or go to the end position right away if
+                        // there's nothing to fill in
+                        doc.snippet = ActiveSnippet::new(&snippet, start);
+                        let selection = match &doc.snippet {
+                            Some(active) => active.selection(),
+                            None => {
+                                let (pos, _) = snippet.tabstops[0][0];
+                                Selection::point(start + pos)
+                            }
+                        };
+                        doc.set_selection(view.id, selection);
+                    }
+
                     if let Some(additional_edits) = &item.additional_text_edits {
                         // gopls uses this to add extra imports
                         if !additional_edits.is_empty() {
@@ -178,6 +210,43 @@ impl Completion {
     }
 }
 
+/// Expands a snippet that is to be inserted at `pos`: new lines keep the indentation of the line
+/// and variables are filled in from the document.
+fn render_snippet(doc: &Document, pos: usize, snippet: &str) -> RenderedSnippet {
+    let text = doc.text().slice(..);
+    let line = text.char_to_line(pos);
+    let indent: String = text
+        .line(line)
+        .chars()
+        .take_while(|ch| *ch == ' ' || *ch == '\t')
+        .collect();
+    let newline = format!("{}{}", doc.line_ending.as_str(), indent);
+
+    snippets::parse(snippet).render(&newline, |name| snippet_variable(doc, line, name))
+}
+
+/// Values of the snippet variables, see
+/// <https://code.visualstudio.com/docs/editor/userdefinedsnippets#_variables>.
+fn snippet_variable(doc: &Document, line: usize, name: &str) -> Option<String> {
+    let path = doc.path();
+    let value = match name {
+        "TM_CURRENT_LINE" => doc
+            .text()
+            .line(line)
+            .to_string()
+            .trim_end_matches(|ch| ch == '\n' || ch == '\r')
+            .to_string(),
+        "TM_LINE_INDEX" => line.to_string(),
+        "TM_LINE_NUMBER" => (line + 1).to_string(),
+        "TM_FILENAME" => path?.file_name()?.to_string_lossy().into_owned(),
+        "TM_FILENAME_BASE" => path?.file_stem()?.to_string_lossy().into_owned(),
+        "TM_DIRECTORY" => path?.parent()?.to_string_lossy().into_owned(),
+        "TM_FILEPATH" => path?.to_string_lossy().into_owned(),
+        _ => return None,
+    };
+    Some(value)
+}
+
 // need to:
 // - trigger on the right trigger char
 //   - detect previous open instance and recycle
@@ -9,9 +9,12 @@ use crate::{
 use helix_core::{
     coords_at_pos,
     graphemes::{ensure_grapheme_boundary_next, next_grapheme_boundary, prev_grapheme_boundary},
+    match_brackets,
     syntax::{self, HighlightEvent},
     unicode::segmentation::UnicodeSegmentation,
     unicode::width::UnicodeWidthStr,
+    visual_coords_at_pos,
+    wrap::{WrappedLine, WRAP_INDICATOR},
     LineEnding, Position, Range, Selection,
 };
 use helix_view::{
@@ -20,9 +23,9 @@ use helix_view::{
     info::Info,
     input::KeyEvent,
     keyboard::{KeyCode, KeyModifiers},
-    Document, Editor, Theme, View,
+    Document, DocumentId, Editor, Theme, View,
 };
-use std::borrow::Cow;
+use std::{borrow::Cow, num::NonZeroUsize};
 
 use crossterm::event::{Event, MouseButton, MouseEvent, MouseEventKind};
 use tui::buffer::Buffer as Surface;
@@ -30,7 +33,19 @@ use tui::buffer::Buffer as Surface;
 pub struct EditorView {
     keymaps: Keymaps,
     on_next_key: Option<Box<dyn FnOnce(&mut commands::Context, KeyEvent)>>,
-    last_insert: (commands::Command, Vec<KeyEvent>),
+    /// The keys of the last change, replayed by `.`: the keys of a command changing the document,
+    /// followed by the ones typed in insert mode if the command entered it.
+    last_change: Vec<KeyEvent>,
+    /// The count of the last change, which a count given to `.` replaces.
+    last_change_count: Option<NonZeroUsize>,
+    /// Whether the keys typed in insert mode are part of `last_change`.
+    recording_insert: bool,
+    /// The keys of the command being typed, with its register but without its count.
+    command_keys: Vec<KeyEvent>,
+    /// The count of the command being typed.
+    command_count: Option<NonZeroUsize>,
+    /// The document and the length of its history before the command being typed.
+    command_start: (DocumentId, usize),
     completion: Option<Completion>,
     spinners: ProgressSpinners,
     pub autoinfo: Option<Info>,
@@ -49,7 +64,12 @@ impl EditorView {
         Self {
             keymaps,
             on_next_key: None,
-            last_insert: (commands::Command::normal_mode, Vec::new()),
+            last_change: Vec::new(),
+            last_change_count: None,
+            recording_insert: false,
+            command_keys: Vec::new(),
+            command_count: None,
+            command_start: (DocumentId::default(), 0),
             completion: None,
             spinners: ProgressSpinners::default(),
             autoinfo: None,
@@ -69,7 +89,6 @@ impl EditorView {
         surface: &mut Surface,
         theme: &Theme,
         is_focused: bool,
-        loader: &syntax::Loader,
     ) {
         let area = Rect::new(
             view.area.x + GUTTER_OFFSET,
@@ -78,7 +97,7 @@ impl EditorView {
             view.area.height.saturating_sub(1),
         ); // - 1 for statusline
 
-        self.render_buffer(doc, view, area, surface, theme, is_focused, loader);
+        self.render_buffer(doc, view, area, surface, theme, is_focused);
 
         // if we're not at the edge of the screen, draw a right border
         if viewport.right() != view.area.right() {
@@ -113,7 +132,6 @@ impl EditorView {
         surface: &mut Surface,
         theme: &Theme,
         is_focused: bool,
-        loader: &syntax::Loader,
     ) {
         let text = doc.text().slice(..);
 
@@ -130,26 +148,8 @@ impl EditorView {
         // TODO: range doesn't actually restrict source, just highlight range
         let highlights: Vec<_> = match doc.syntax() {
             Some(syntax) => {
-                let scopes = theme.scopes();
                 syntax
-                    .highlight_iter(text.slice(..), Some(range), None, |language| {
-                        loader
-                                .language_config_for_scope(&format!("source.{}", language))
-                                .and_then(|language_config| {
-                                    let config = language_config.highlight_config(scopes)?;
-                                    let config_ref = config.as_ref();
-                                    // SAFETY: the referenced `HighlightConfiguration` behind
-                                    // the `Arc` is guaranteed to remain valid throughout the
-                                    // duration of the highlight.
-                                    let config_ref = unsafe {
-                                        std::mem::transmute::<
-                                            _,
-                                            &'static syntax::HighlightConfiguration,
-                                        >(config_ref)
-                                    };
-                                    Some(config_ref)
-                                })
-                    })
+                    .highlight_iter(text.slice(..), Some(range), None)
                     .collect() // TODO: we collect here to avoid holding the lock, fix later
             }
             None => vec![Ok(HighlightEvent::Source {
@@ -163,6 +163,26 @@ impl EditorView {
         let tab_width = doc.tab_width();
         let tab = " ".repeat(tab_width);
 
+        // soft wrapped lines are never scrolled horizontally
+        let wrap_width = view.wrap_width(doc);
+        let first_col = if wrap_width.is_some() {
+            0
+        } else {
+            view.first_col as u16
+        };
+        let wrap = |line: usize| {
+            wrap_width.map(|width| WrappedLine::new(text.line(line), width, tab_width))
+        };
+        let wrap_indicator_style = theme.get("ui.linenr");
+        // the document line being rendered, where it starts and the next row it wraps at
+        let mut doc_line = view.first_line;
+        let mut line_start = text.line_to_char(doc_line);
+        let mut wrapped = wrap(doc_line);
+        let mut next_row = 1;
+        // the rows of the first line scrolled past, they're not drawn
+        let first_row = view.skipped_rows(doc);
+        let mut skipped_rows = first_row;
+
         let highlights = highlights.into_iter().map(|event| match event.unwrap() {
             // convert byte offsets to char offset
             HighlightEvent::Source { start, end } => {
@@ -262,7 +282,8 @@ impl EditorView {
                     // `unwrap_or_else` part is for off-the-end indices of
                     // the rope, to allow cursor highlighting at the end
                     // of the rope.
-                    let text = text.get_slice(start..end).unwrap_or_else(|| " ".into());
+                    let source = text.get_slice(start..end).unwrap_or_else(|| " ".into());
+                    let mut pos = start;
 
                     use helix_core::graphemes::{grapheme_width, RopeGraphemes};
 
@@ -271,15 +292,45 @@ impl EditorView {
                         acc.patch(style)
                     });
 
-                    for grapheme in RopeGraphemes::new(text) {
-                        let out_of_bounds = visual_x < view.first_col as u16
-                            || visual_x >= viewport.width + view.first_col as u16;
+                    for grapheme in RopeGraphemes::new(source) {
+                        let grapheme_len = grapheme.len_chars();
+
+                        // continue soft wrapped lines on the next row
+                        if let Some(wrapped) = &wrapped {
+                            if wrapped.row_starts().get(next_row) == Some(&(pos - line_start)) {
+                                next_row += 1;
+                                if skipped_rows > 0 {
+                                    skipped_rows -= 1;
+                                } else {
+                                    line += 1;
+                                }
+                                if line >= viewport.height {
+                                    break 'outer;
+                                }
+
+                                let indent = wrapped.indent() as u16;
+                                if indent > 0 && skipped_rows == 0 {
+                                    surface.set_string(
+                                        viewport.x + indent - WRAP_INDICATOR.width() as u16,
+                                        viewport.y + line,
+                                        WRAP_INDICATOR,
+                                        wrap_indicator_style,
+                                    );
+                                }
+                                visual_x = indent;
+                            }
+                        }
+                        pos += grapheme_len;
+
+                        let out_of_bounds = skipped_rows > 0
+                            || visual_x < first_col
+                            || visual_x >= viewport.width + first_col;
 
                         if LineEnding::from_rope_slice(&grapheme).is_some() {
                             if !out_of_bounds {
                                 // we still want to render an empty cell with the style
                                 surface.set_string(
-                                    viewport.x + visual_x - view.first_col as u16,
+                                    viewport.x + visual_x - first_col,
                                     viewport.y + line,
                                     " ",
                                     style,
@@ -293,6 +344,13 @@ impl EditorView {
                             if line >= viewport.height {
                                 break 'outer;
                             }
+
+                            if wrap_width.is_some() && doc_line + 1 < text.len_lines() {
+                                doc_line += 1;
+                                line_start = text.line_to_char(doc_line);
+                                wrapped = wrap(doc_line);
+                                next_row = 1;
+                            }
                         } else {
                             let grapheme = Cow::from(grapheme);
 
@@ -309,7 +367,7 @@ impl EditorView {
                             if !out_of_bounds {
                                 // if we're offscreen just keep going until we hit a new line
                                 surface.set_string(
-                                    viewport.x + visual_x - view.first_col as u16,
+                                    viewport.x + visual_x - first_col,
                                     viewport.y + line,
                                     grapheme,
                                     style,
@@ -335,12 +393,17 @@ impl EditorView {
         // document or not.  We only draw it if it's not an empty line.
         let draw_last = text.line_to_byte(last_line) < text.len_bytes();
 
-        for (i, line) in (view.first_line..(last_line + 1)).enumerate() {
+        let mut row = 0;
+        for line in view.first_line..(last_line + 1) {
+            if row >= viewport.height {
+                break;
+            }
+
             use helix_core::diagnostic::Severity;
             if let Some(diagnostic) = doc.diagnostics().iter().find(|d| d.line == line) {
                 surface.set_stringn(
                     viewport.x - GUTTER_OFFSET,
-                    viewport.y + i as u16,
+                    viewport.y + row,
                     "●",
                     1,
                     match diagnostic.severity {
@@ -361,11 +424,16 @@ impl EditorView {
             };
             surface.set_stringn(
                 viewport.x + 1 - GUTTER_OFFSET,
-                viewport.y + i as u16,
+                viewport.y + row,
                 line_number_text,
                 5,
                 linenr,
             );
+
+            row += view.line_height(doc, line) as u16;
+            if line == view.first_line {
+                row -= first_row as u16;
+            }
         }
 
         // render selections and selected linenr(s)
@@ -383,18 +451,15 @@ impl EditorView {
             let selection = doc.selection(view.id);
 
             for selection in selection.iter().filter(|range| range.overlaps(&screen)) {
-                let head = view.screen_coords_at_pos(
-                    doc,
-                    text,
-                    if selection.head > selection.anchor {
-                        selection.head - 1
-                    } else {
-                        selection.head
-                    },
-                );
+                let line_number = text.char_to_line(if selection.head > selection.anchor {
+                    selection.head - 1
+                } else {
+                    selection.head
+                });
+                // the first row of the line, soft wrapped lines take several
+                let head = view.screen_coords_at_pos(doc, text, text.line_to_char(line_number));
                 if let Some(head) = head {
                     // Draw line number for selected lines.
-                    let line_number = view.first_line + head.row;
                     let line_number_text = if line_number == last_line && !draw_last {
                         "    ~".into()
                     } else {
@@ -409,35 +474,31 @@ impl EditorView {
                     );
 
                     // TODO: set cursor position for IME
-                    if let Some(syntax) = doc.syntax() {
-                        use helix_core::match_brackets;
-                        let pos = doc
-                            .selection(view.id)
-                            .primary()
-                            .cursor(doc.text().slice(..));
-                        let pos = match_brackets::find(syntax, doc.text(), pos)
-                            .and_then(|pos| view.screen_coords_at_pos(doc, text, pos));
-
-                        if let Some(pos) = pos {
-                            // ensure col is on screen
-                            if (pos.col as u16) < viewport.width + view.first_col as u16
-                                && pos.col >= view.first_col
-                            {
-                                let style = theme.try_get("ui.cursor.match").unwrap_or_else(|| {
-                                    Style::default()
-                                        .add_modifier(Modifier::REVERSED)
-                                        .add_modifier(Modifier::DIM)
-                                });
-
-                                surface
-                                    .get_mut(
-                                        viewport.x + pos.col as u16,
-                                        viewport.y + pos.row as u16,
-                                    )
-                                    .set_style(style);
-                            }
-                        }
-                    }
+                }
+            }
+
+            // highlight the bracket matching the one under the primary cursor
+            let pos = selection.primary().cursor(text);
+            let pos = match_brackets::find_matching_bracket(doc.syntax(), doc.text(), pos)
+                .filter(|pos| {
+                    // screen coords clamp brackets scrolled out to the left onto the first column
+                    view.wrap_width(doc).is_some()
+                        || visual_coords_at_pos(text, *pos, doc.tab_width()).col >= view.first_col
+                })
+                .and_then(|pos| view.screen_coords_at_pos(doc, text, pos));
+
+            if let Some(pos) = pos {
+                // ensure col is on screen
+                if (pos.col as u16) < viewport.width {
+                    let style = theme.try_get("ui.cursor.match").unwrap_or_else(|| {
+                        Style::default()
+                            .add_modifier(Modifier::REVERSED)
+                            .add_modifier(Modifier::DIM)
+                    });
+
+                    surface
+                        .get_mut(viewport.x + pos.col as u16, viewport.y + pos.row as u16)
+                        .set_style(style);
                 }
             }
         }
@@ -583,9 +644,18 @@ impl EditorView {
             format!("{}:{}", pos.row + 1, pos.col + 1) // convert to 1-indexing
         };
 
+        // plain UTF-8 is left out
+        let encoding = doc.encoding();
+        let encoding_info = match (encoding.name(), doc.has_bom()) {
+            ("UTF-8", false) => String::new(),
+            (name, false) => format!("{}    ", name),
+            (name, true) => format!("{} BOM    ", name),
+        };
+
         // Render them to the status line together.
         let right_side_text = format!(
-            "{}    {} ",
+            "{}{}    {} ",
+            encoding_info,
             &diag_count[..diag_count.len().min(4)],
             // indent_info,
             position_info
@@ -610,8 +680,13 @@ impl EditorView {
         event: KeyEvent,
     ) -> Option<KeymapResult> {
         self.autoinfo = None;
-        match self.keymaps.get_mut(&mode).unwrap().get(event) {
-            KeymapResult::Matched(command) => command.execute(cxt),
+        let keymap = self.keymaps.get_mut(&mode).unwrap();
+        let pending = keymap.pending().len();
+        match keymap.get(event) {
+            KeymapResult::Matched(command) => {
+                cxt.binding_len = pending + 1;
+                command.execute(cxt)
+            }
             KeymapResult::Pending(node) => self.autoinfo = Some(node.into()),
             k @ KeymapResult::NotFound | k @ KeymapResult::Cancelled(_) => return Some(k),
         }
@@ -645,23 +720,38 @@ impl EditorView {
         }
     }
 
+    /// Records a key of the command being typed, `revisions` are the document and the length of
+    /// its history before the key, `count` the count typed before it.
+    fn record_command_key(
+        &mut self,
+        key: KeyEvent,
+        revisions: (DocumentId, usize),
+        count: Option<NonZeroUsize>,
+    ) {
+        if self.command_keys.is_empty() {
+            self.command_start = revisions;
+            self.command_count = None;
+        }
+        self.command_keys.push(key);
+        self.command_count = count.or(self.command_count);
+    }
+
+    /// Makes the command that was just typed the last change.
+    fn set_last_change(&mut self) {
+        self.last_change = std::mem::take(&mut self.command_keys);
+        self.last_change_count = self.command_count.take();
+    }
+
     fn command_mode(&mut self, mode: Mode, cxt: &mut commands::Context, event: KeyEvent) {
         match event {
             // count handling
             key!(i @ '0'..='9') => {
                 let i = i.to_digit(10).unwrap() as usize;
                 cxt.editor.count =
-                    std::num::NonZeroUsize::new(cxt.editor.count.map_or(i, |c| c.get() * 10 + i));
+                    NonZeroUsize::new(cxt.editor.count.map_or(i, |c| c.get() * 10 + i));
             }
             // special handling for repeat operator
-            key!('.') => {
-                // first execute whatever put us into insert mode
-                self.last_insert.0.execute(cxt);
-                // then replay the inputs
-                for &key in &self.last_insert.1.clone() {
-                    self.insert_mode(cxt, key)
-                }
-            }
+            key!('.') => self.repeat_last_change(cxt),
             _ => {
                 // set the count
                 cxt.count = cxt.editor.count;
@@ -680,6 +770,44 @@ impl EditorView {
         }
     }
 
+    /// Replays the last change, with the count typed before `.` instead of its own one if there
+    /// is one. The replay is a single undo step.
+    fn repeat_last_change(&mut self, cxt: &mut commands::Context) {
+        let count = cxt.editor.count.take().or(self.last_change_count);
+        self.command_keys.clear();
+
+        let keys = self.last_change.clone();
+        let (view, doc) = current!(cxt.editor);
+        // the replay may switch to another document, the held one is released
+        let held = (doc.id(), view.id);
+        doc.hold_history();
+
+        cxt.editor.count = count;
+        self.replay_keys(cxt, &keys);
+        cxt.editor.count = None;
+
+        let (doc_id, view_id) = held;
+        if let Some(doc) = cxt.editor.document_mut(doc_id) {
+            doc.release_history(view_id);
+        }
+    }
+
+    /// Handles `keys` like typed ones, except they aren't recorded for `.` and don't go through
+    /// the completion menu.
+    fn replay_keys(&mut self, cxt: &mut commands::Context, keys: &[KeyEvent]) {
+        for &key in keys {
+            if let Some(on_next_key) = self.on_next_key.take() {
+                on_next_key(cxt, key);
+            } else {
+                match doc_mut!(cxt.editor).mode() {
+                    Mode::Insert => self.insert_mode(cxt, key),
+                    mode => self.command_mode(mode, cxt, key),
+                }
+            }
+            self.on_next_key = cxt.on_next_key_callback.take();
+        }
+    }
+
     pub fn set_completion(
         &mut self,
         items: Vec<helix_lsp::lsp::CompletionItem>,
@@ -711,24 +839,31 @@ impl Component for EditorView {
 
                 let (_, doc) = current!(cx.editor);
                 let mode = doc.mode();
+                let revisions = (doc.id(), history_len(doc));
 
                 let mut cxt = commands::Context {
                     selected_register: helix_view::RegisterSelection::default(),
                     editor: &mut cx.editor,
                     count: None,
+                    binding_len: 1,
                     callback: None,
                     on_next_key_callback: None,
                     jobs: cx.jobs,
                 };
 
                 if let Some(on_next_key) = self.on_next_key.take() {
+                    if mode != Mode::Insert {
+                        self.record_command_key(key, revisions, None);
+                    }
                     // if there's a command waiting input, do that first
                     on_next_key(&mut cxt, key);
                 } else {
                     match mode {
                         Mode::Insert => {
-                            // record last_insert key
-                            self.last_insert.1.push(key);
+                            // record the key as part of the last change
+                            if self.recording_insert {
+                                self.last_change.push(key);
+                            }
 
                             // let completion swallow the event if necessary
                             let mut consumed = false;
@@ -764,7 +899,14 @@ impl Component for EditorView {
                                 }
                             }
                         }
-                        mode => self.command_mode(mode, &mut cxt, key),
+                        mode => {
+                            // the count is kept apart from the keys, `.` has its own
+                            if !matches!(key, key!('0'..='9') | key!('.')) {
+                                let count = cxt.editor.count;
+                                self.record_command_key(key, revisions, count);
+                            }
+                            self.command_mode(mode, &mut cxt, key)
+                        }
                     }
                 }
 
@@ -781,25 +923,35 @@ impl Component for EditorView {
                 let (view, doc) = current!(cx.editor);
                 view.ensure_cursor_in_view(doc, cx.editor.config.scrolloff);
 
+                // a command is complete once it doesn't wait for more keys
+                let command_complete = self.on_next_key.is_none()
+                    && self.keymaps.pending().is_empty()
+                    && cx.editor.count.is_none()
+                    && cx.editor.selected_register.is_default();
+
                 // mode transitions
                 match (mode, doc.mode()) {
-                    (Mode::Normal, Mode::Insert) => {
-                        // HAXX: if we just entered insert mode from normal, clear key buf
-                        // and record the command that got us into this mode.
-
-                        // how we entered insert mode is important, and we should track that so
-                        // we can repeat the side effect.
-
-                        self.last_insert.0 = match self.keymaps.get_mut(&mode).unwrap().get(key) {
-                            KeymapResult::Matched(command) => command,
-                            // FIXME: insert mode can only be entered through single KeyCodes
-                            _ => unimplemented!(),
-                        };
-                        self.last_insert.1.clear();
+                    (Mode::Insert, Mode::Insert) => (),
+                    (_, Mode::Insert) => {
+                        // the command that entered insert mode starts the last change, and the
+                        // keys typed until leaving it follow
+                        self.set_last_change();
+                        self.recording_insert = true;
                     }
-                    (Mode::Insert, Mode::Normal) => {
+                    (Mode::Insert, _) => {
                         // if exiting insert mode, remove completion
                         self.completion = None;
+                        self.recording_insert = false;
+                    }
+                    _ if command_complete => {
+                        // motions and undos don't add to the history
+                        let (id, len) = self.command_start;
+                        if !self.command_keys.is_empty() && doc.id() == id && history_len(doc) > len
+                        {
+                            self.set_last_change();
+                        } else {
+                            self.command_keys.clear();
+                        }
                     }
                     _ => (),
                 }
@@ -871,16 +1023,7 @@ impl Component for EditorView {
 
         for (view, is_focused) in cx.editor.tree.views() {
             let doc = cx.editor.document(view.doc).unwrap();
-            let loader = &cx.editor.syn_loader;
-            self.render_view(
-                doc,
-                view,
-                area,
-                surface,
-                &cx.editor.theme,
-                is_focused,
-                loader,
-            );
+            self.render_view(doc, view, area, surface, &cx.editor.theme, is_focused);
         }
 
         if let Some(ref info) = self.autoinfo {
@@ -944,7 +1087,11 @@ impl Component for EditorView {
     }
 }
 
-fn canonicalize_key(key: &mut KeyEvent) {
+fn history_len(doc: &Document) -> usize {
+    doc.with_history(|history| history.len())
+}
+
+pub(crate) fn canonicalize_key(key: &mut KeyEvent) {
     if let KeyEvent {
         code: KeyCode::Char(_),
         modifiers: _,
@@ -953,3 +1100,105 @@ fn canonicalize_key(key: &mut KeyEvent) {
         key.modifiers.remove(KeyModifiers::SHIFT)
     }
 }
+
+#[test]
+fn repeat_last_change() {
+    use crate::job::Jobs;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use helix_view::{editor::Action, theme};
+    use std::sync::Arc;
+
+    let mut editor = Editor::new(
+        Rect::new(0, 0, 80, 25),
+        Arc::new(theme::Loader::new("", "")),
+        Arc::new(syntax::Loader::new(syntax::Configuration {
+            language: Vec::new(),
+        })),
+        helix_view::editor::Config::default(),
+    );
+    editor.new_file(Action::VerticalSplit);
+    let mut jobs = Jobs::new();
+    let mut view = EditorView::default();
+
+    let mut type_keys = |editor: &mut Editor, keys: &str| {
+        let mut cx = Context {
+            editor,
+            scroll: None,
+            jobs: &mut jobs,
+        };
+        for ch in keys.chars() {
+            let key = match ch {
+                '⎋' => KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE),
+                'ª' => KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL),
+                ch => KeyEvent::new(KeyCode::Char(ch), KeyModifiers::NONE),
+            };
+            view.handle_event(Event::Key(key), &mut cx);
+        }
+        doc_mut!(cx.editor).text().to_string()
+    };
+
+    // a change entering insert mode, repeated on the next word
+    type_keys(&mut editor, "ione two three⎋gg");
+    assert_eq!(type_keys(&mut editor, "miwcfoo⎋"), "foo two three\n");
+    assert_eq!(type_keys(&mut editor, "ftmiw."), "foo foo three\n");
+    // the repeat is a single undo step
+    assert_eq!(type_keys(&mut editor, "u"), "foo two three\n");
+
+    // the count of `.` replaces the one of the change
+    assert_eq!(type_keys(&mut editor, "ggmiwc1⎋"), "1 two three\n");
+    assert_eq!(type_keys(&mut editor, "gg5ª"), "6 two three\n");
+    assert_eq!(type_keys(&mut editor, "2."), "8 two three\n");
+    assert_eq!(type_keys(&mut editor, "."), "13 two three\n");
+}
+
+#[test]
+fn record_macro_bound_to_key_sequence() {
+    use crate::{job::Jobs, keymap, keymap::Keymap};
+    use helix_core::hashmap;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use helix_view::{editor::Action, theme};
+    use std::sync::Arc;
+
+    let mut editor = Editor::new(
+        Rect::new(0, 0, 80, 25),
+        Arc::new(theme::Loader::new("", "")),
+        Arc::new(syntax::Loader::new(syntax::Configuration {
+            language: Vec::new(),
+        })),
+        helix_view::editor::Config::default(),
+    );
+    editor.new_file(Action::VerticalSplit);
+    let mut jobs = Jobs::new();
+    let mut keymaps = Keymaps::default();
+    let space_q = keymap!({ "Normal mode"
+        "space" => { "Space"
+            "q" => record_macro,
+        },
+    });
+    keymaps.0.get_mut(&Mode::Normal).unwrap().merge(Keymap::new(space_q));
+    let mut view = EditorView::new(keymaps);
+
+    let mut cx = Context {
+        editor: &mut editor,
+        scroll: None,
+        jobs: &mut jobs,
+    };
+    for ch in "␣qiab⎋␣q".chars() {
+        let key = match ch {
+            '⎋' => KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE),
+            '␣' => KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE),
+            ch => KeyEvent::new(KeyCode::Char(ch), KeyModifiers::NONE),
+        };
+        // recorded before the key is handled, as the application does
+        if let Some((_, keys)) = &mut cx.editor.macro_recording {
+            let mut key = key.into();
+            canonicalize_key(&mut key);
+            keys.push(key);
+        }
+        view.handle_event(Event::Key(key), &mut cx);
+    }
+    assert_eq!(
+        editor.registers.read('@'),
+        Some(&["iab<esc>".to_string()][..])
+    );
+}
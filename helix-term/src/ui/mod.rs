@@ -8,6 +8,7 @@ mod popup;
 mod prompt;
 mod spinner;
 mod text;
+mod undo_tree;
 
 pub use completion::Completion;
 pub use editor::EditorView;
@@ -18,6 +19,7 @@ pub use popup::Popup;
 pub use prompt::{Prompt, PromptEvent};
 pub use spinner::{ProgressSpinners, Spinner};
 pub use text::Text;
+pub use undo_tree::UndoTree;
 
 use helix_core::regex::Regex;
 use helix_core::register::Registers;
@@ -158,10 +158,12 @@ impl<T: 'static> Component for Picker<T> {
             _ => return EventResult::Ignored,
         };
 
-        let close_fn = EventResult::Consumed(Some(Box::new(|compositor: &mut Compositor| {
-            // remove the layer
-            compositor.last_picker = compositor.pop();
-        })));
+        let close_fn = EventResult::Consumed(Some(Box::new(
+            |compositor: &mut Compositor, _cx: &mut Context| {
+                // remove the layer
+                compositor.last_picker = compositor.pop();
+            },
+        )));
 
         match key_event {
             KeyEvent {
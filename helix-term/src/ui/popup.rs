@@ -59,10 +59,12 @@ impl<T: Component> Component for Popup<T> {
             _ => return EventResult::Ignored,
         };
 
-        let close_fn = EventResult::Consumed(Some(Box::new(|compositor: &mut Compositor| {
-            // remove the layer
-            compositor.pop();
-        })));
+        let close_fn = EventResult::Consumed(Some(Box::new(
+            |compositor: &mut Compositor, _cx: &mut Context| {
+                // remove the layer
+                compositor.pop();
+            },
+        )));
 
         match key {
             // esc or ctrl-c aborts the completion and closes the menu
@@ -0,0 +1,355 @@
+use crate::compositor::{Component, Compositor, Context, EventResult};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use tui::{
+    buffer::Buffer as Surface,
+    widgets::{Block, Borders, Widget},
+};
+
+use helix_core::{history::History, ChangeSet, Operation};
+use helix_view::{graphics::Rect, Document, DocumentId, ViewId};
+
+use std::time::{Duration, Instant};
+
+/// A single line of the tree, one per revision.
+struct Row {
+    revision: usize,
+    graph: String,
+    label: String,
+}
+
+/// Browser for the undo tree of a document. Moving through the revisions previews them in the
+/// view, confirming keeps the selected revision and aborting goes back to where we started.
+pub struct UndoTree {
+    doc_id: DocumentId,
+    view_id: ViewId,
+    /// Revision that was current when the browser was opened.
+    original: usize,
+    /// Revisions ordered from newest to oldest.
+    rows: Vec<Row>,
+    cursor: usize,
+}
+
+impl UndoTree {
+    pub fn new(doc: &Document, view_id: ViewId) -> Self {
+        let (rows, original) =
+            doc.with_history(|history| (build_rows(history), history.current_revision()));
+        let cursor = rows
+            .iter()
+            .position(|row| row.revision == original)
+            .unwrap_or_default();
+
+        Self {
+            doc_id: doc.id(),
+            view_id,
+            original,
+            rows,
+            cursor,
+        }
+    }
+
+    pub fn move_up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_down(&mut self) {
+        if self.cursor < self.rows.len() - 1 {
+            self.cursor += 1;
+        }
+    }
+
+    fn jump_to(&self, cx: &mut Context, revision: usize) {
+        if let Some(doc) = cx.editor.document_mut(self.doc_id) {
+            doc.jump_to_revision(self.view_id, revision);
+            cx.editor.ensure_cursor_in_view(self.view_id);
+        }
+    }
+
+    fn area(&self, viewport: Rect) -> Rect {
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.graph.chars().count() + row.label.len() + 1)
+            .max()
+            .unwrap_or_default() as u16
+            + 2; // borders
+        let width = width.min(viewport.width / 2);
+        // leave the statusline and the command line visible
+        let height = viewport.height.saturating_sub(2);
+
+        viewport.intersection(Rect::new(
+            viewport.x + viewport.width - width,
+            viewport.y,
+            width,
+            height,
+        ))
+    }
+}
+
+/// Lays out the revisions as a graph. Each branch gets its own lane, which is continued by the
+/// first child of a revision, while later children fork off into a new lane.
+fn build_rows(history: &History) -> Vec<Row> {
+    let len = history.len();
+
+    let mut lanes = vec![0; len];
+    let mut continued = vec![false; len];
+    let mut lane_count = 1;
+    for revision in 1..len {
+        let parent = history.parent(revision);
+        if continued[parent] {
+            lanes[revision] = lane_count;
+            lane_count += 1;
+        } else {
+            lanes[revision] = lanes[parent];
+            continued[parent] = true;
+        }
+    }
+
+    let mut grid = vec![vec![' '; lane_count * 2]; len];
+    for revision in 1..len {
+        let parent = history.parent(revision);
+        let lane = lanes[revision] * 2;
+        for line in &mut grid[parent + 1..revision] {
+            line[lane] = '│';
+        }
+        if lanes[revision] != lanes[parent] {
+            for cell in &mut grid[parent][lanes[parent] * 2 + 1..lane] {
+                *cell = '─';
+            }
+            grid[parent][lane] = '╯';
+        }
+    }
+
+    let current = history.current_revision();
+    let now = Instant::now();
+
+    (0..len)
+        .rev()
+        .map(|revision| {
+            let line = &mut grid[revision];
+            line[lanes[revision] * 2] = if revision == current { '●' } else { '○' };
+            let graph = line.iter().collect::<String>().trim_end().to_string();
+
+            let age = format_age(now.saturating_duration_since(history.timestamp(revision)));
+            let summary = if revision == 0 {
+                "original".to_string()
+            } else {
+                summarize(history.changes(revision))
+            };
+
+            Row {
+                revision,
+                graph,
+                label: format!("{:>3} {:>4} {}", revision, age, summary),
+            }
+        })
+        .collect()
+}
+
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Short description of a revision: how many characters it inserts and deletes, and the start
+/// of the first insertion.
+fn summarize(changes: &ChangeSet) -> String {
+    const PREVIEW_LEN: usize = 16;
+
+    let mut inserted = 0;
+    let mut deleted = 0;
+    let mut preview = None;
+    for change in changes.changes() {
+        match change {
+            Operation::Insert(text) => {
+                inserted += text.chars().count();
+                preview.get_or_insert_with(|| {
+                    text.chars()
+                        .take(PREVIEW_LEN)
+                        .flat_map(char::escape_default)
+                        .collect::<String>()
+                });
+            }
+            Operation::Delete(n) => deleted += n,
+            Operation::Retain(_) => (),
+        }
+    }
+
+    let mut summary = format!("+{} -{}", inserted, deleted);
+    if let Some(preview) = preview {
+        summary.push_str(&format!(" \"{}\"", preview));
+    }
+    summary
+}
+
+impl Component for UndoTree {
+    fn handle_event(&mut self, event: Event, cx: &mut Context) -> EventResult {
+        let key_event = match event {
+            Event::Key(event) => event,
+            Event::Resize(..) => return EventResult::Consumed(None),
+            _ => return EventResult::Ignored,
+        };
+
+        let close_fn = EventResult::Consumed(Some(Box::new(
+            |compositor: &mut Compositor, _cx: &mut Context| {
+                // remove the layer
+                compositor.pop();
+            },
+        )));
+
+        match key_event {
+            KeyEvent {
+                code: KeyCode::Up, ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('k'),
+                modifiers: KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: KeyModifiers::CONTROL,
+            } => {
+                self.move_up();
+                self.jump_to(cx, self.rows[self.cursor].revision);
+            }
+            KeyEvent {
+                code: KeyCode::Down,
+                ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('j'),
+                modifiers: KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::CONTROL,
+            } => {
+                self.move_down();
+                self.jump_to(cx, self.rows[self.cursor].revision);
+            }
+            KeyEvent {
+                code: KeyCode::Esc, ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+            } => {
+                self.jump_to(cx, self.original);
+                return close_fn;
+            }
+            KeyEvent {
+                code: KeyCode::Enter,
+                ..
+            } => {
+                return close_fn;
+            }
+            _ => (),
+        }
+
+        EventResult::Consumed(None)
+    }
+
+    fn render(&self, viewport: Rect, surface: &mut Surface, cx: &mut Context) {
+        let area = self.area(viewport);
+
+        let style = cx.editor.theme.get("ui.popup");
+        let text_style = cx.editor.theme.get("ui.text");
+        let selected = cx.editor.theme.get("ui.menu.selected");
+
+        surface.clear_with(area, style);
+
+        let block = Block::default()
+            .title("Undo tree")
+            .borders(Borders::ALL)
+            .border_style(style);
+        let inner = block.inner(area);
+        block.render(area, surface);
+
+        if inner.height == 0 {
+            return;
+        }
+
+        let rows = inner.height as usize;
+        let offset = self.cursor / rows * rows;
+
+        for (i, row) in self.rows.iter().skip(offset).take(rows).enumerate() {
+            let y = inner.y + i as u16;
+            let style = if offset + i == self.cursor {
+                surface.set_style(Rect::new(inner.x, y, inner.width, 1), selected);
+                selected
+            } else {
+                text_style
+            };
+
+            let (x, _) = surface.set_stringn(inner.x, y, &row.graph, inner.width as usize, style);
+            let width = (inner.right().saturating_sub(x + 1)) as usize;
+            surface.set_stringn(x + 1, y, &row.label, width, style);
+        }
+    }
+}
+
+#[test]
+fn build_rows_of_branched_history() {
+    use helix_core::{Rope, State, Transaction};
+
+    let mut history = History::default();
+    let mut state = State::new(Rope::from("a"));
+    fn commit(history: &mut History, state: &mut State, change: helix_core::Change) {
+        let transaction = Transaction::change(&state.doc, std::iter::once(change));
+        history.commit_revision(&transaction, state);
+        transaction.apply(&mut state.doc);
+    }
+    fn undo(history: &mut History, state: &mut State) {
+        history.undo().unwrap().apply(&mut state.doc);
+    }
+
+    // 1 and 2 in a line, 3 forks off 1 and 4 off the original
+    commit(&mut history, &mut state, (1, 1, Some("b".into())));
+    commit(&mut history, &mut state, (2, 2, Some("c".into())));
+    undo(&mut history, &mut state);
+    commit(&mut history, &mut state, (2, 2, Some("d".into())));
+    undo(&mut history, &mut state);
+    undo(&mut history, &mut state);
+    commit(&mut history, &mut state, (0, 1, None));
+
+    let rows = build_rows(&history);
+    assert_eq!(
+        rows.iter().map(|row| row.revision).collect::<Vec<_>>(),
+        vec![4, 3, 2, 1, 0]
+    );
+    assert_eq!(
+        rows.iter()
+            .map(|row| row.graph.as_str())
+            .collect::<Vec<_>>(),
+        vec!["    ●", "  ○ │", "○ │ │", "○─╯ │", "○───╯"]
+    );
+    assert!(rows[0].label.starts_with("  4 "));
+    assert!(rows[0].label.ends_with(" +0 -1"));
+    assert!(rows[1].label.ends_with(" +1 -0 \"d\""));
+    assert!(rows[4].label.ends_with(" original"));
+}
+
+#[test]
+fn summarize_revision() {
+    use helix_core::{Rope, Transaction};
+
+    let doc = Rope::from("abc");
+    let transaction = Transaction::change(
+        &doc,
+        std::iter::once((0, 3, Some("hello\nworld, this is long".into()))),
+    );
+    assert_eq!(
+        summarize(transaction.changes()),
+        r#"+25 -3 "hello\nworld, thi""#
+    );
+
+    let transaction = Transaction::change(&doc, std::iter::once((1, 2, None)));
+    assert_eq!(summarize(transaction.changes()), "+0 -1");
+
+    assert_eq!(format_age(Duration::from_secs(90)), "1m");
+    assert_eq!(format_age(Duration::from_secs(2 * 86400)), "2d");
+}
@@ -9,16 +9,18 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use helix_core::{
+    auto_pairs::{AutoPairs, DEFAULT_AUTO_PAIRS},
     history::History,
     indent::{auto_detect_indent_style, IndentStyle},
     line_ending::auto_detect_line_ending,
+    snippets::ActiveSnippet,
     syntax::{self, LanguageConfiguration},
     ChangeSet, Diagnostic, LineEnding, Rope, RopeBuilder, Selection, State, Syntax, Transaction,
     DEFAULT_LINE_ENDING,
 };
 use helix_lsp::util::LspFormatting;
 
-use crate::{DocumentId, Theme, ViewId};
+use crate::{marks::Marks, DocumentId, Theme, ViewId};
 
 const BUF_SIZE: usize = 8192;
 
@@ -63,13 +65,90 @@ impl<'de> Deserialize<'de> for Mode {
     }
 }
 
+/// The encoding of a document's file. `encoding_rs` implements the encodings of the Encoding
+/// Standard, which leaves out UTF-32.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    Standard(&'static encoding_rs::Encoding),
+    Utf32Le,
+    Utf32Be,
+}
+
+impl Encoding {
+    /// Looks up an encoding by one of the labels of the Encoding Standard, or `utf-32`,
+    /// `utf-32le` and `utf-32be`.
+    pub fn for_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "utf-32" | "utf-32le" | "utf32" | "utf32le" => Some(Self::Utf32Le),
+            "utf-32be" | "utf32be" => Some(Self::Utf32Be),
+            _ => encoding_rs::Encoding::for_label(label.as_bytes()).map(Self::Standard),
+        }
+    }
+
+    /// Detects the encoding from the byte order mark at the start of `bytes`, returning it with
+    /// the length of the mark.
+    pub fn for_bom(bytes: &[u8]) -> Option<(Self, usize)> {
+        // the UTF-32LE mark starts with the UTF-16LE one
+        if bytes.starts_with(&[0xFF, 0xFE, 0x00, 0x00]) {
+            Some((Self::Utf32Le, 4))
+        } else if bytes.starts_with(&[0x00, 0x00, 0xFE, 0xFF]) {
+            Some((Self::Utf32Be, 4))
+        } else {
+            encoding_rs::Encoding::for_bom(bytes)
+                .map(|(encoding, len)| (Self::Standard(encoding), len))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Standard(encoding) => encoding.name(),
+            Self::Utf32Le => "UTF-32LE",
+            Self::Utf32Be => "UTF-32BE",
+        }
+    }
+
+    /// The byte order mark of the encoding, empty for the encodings without one.
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            Self::Standard(encoding) if *encoding == encoding_rs::UTF_8 => &[0xEF, 0xBB, 0xBF],
+            Self::Standard(encoding) if *encoding == encoding_rs::UTF_16LE => &[0xFF, 0xFE],
+            Self::Standard(encoding) if *encoding == encoding_rs::UTF_16BE => &[0xFE, 0xFF],
+            Self::Standard(_) => &[],
+            Self::Utf32Le => &[0xFF, 0xFE, 0x00, 0x00],
+            Self::Utf32Be => &[0x00, 0x00, 0xFE, 0xFF],
+        }
+    }
+}
+
+impl From<&'static encoding_rs::Encoding> for Encoding {
+    fn from(encoding: &'static encoding_rs::Encoding) -> Self {
+        Self::Standard(encoding)
+    }
+}
+
+/// Changes applied to preview their result, see [`Document::preview`].
+struct Preview {
+    /// The transaction reverting the changes.
+    revert: Transaction,
+    // the state mapped through the changes, restored as it was before them
+    selections: HashMap<ViewId, Selection>,
+    marks: Marks,
+    snippet: Option<ActiveSnippet>,
+}
+
 pub struct Document {
     pub(crate) id: DocumentId,
     text: Rope,
     pub(crate) selections: HashMap<ViewId, Selection>,
+    /// Named selections, kept in sync with the edits.
+    marks: Marks,
+    /// Snippet whose tabstops are being filled in.
+    pub snippet: Option<ActiveSnippet>,
 
     path: Option<PathBuf>,
-    encoding: &'static encoding_rs::Encoding,
+    encoding: Encoding,
+    /// Whether the file starts with a byte order mark, it's written back on save.
+    has_bom: bool,
 
     /// Current editing mode.
     pub mode: Mode,
@@ -94,6 +173,10 @@ pub struct Document {
     // it back as it separated from the edits. We could split out the parts manually but that will
     // be more troublesome.
     history: Cell<History>,
+    /// Whether committing changes to the history is put off, see [`Document::hold_history`].
+    history_held: bool,
+    /// The changes of the current preview, see [`Document::preview`].
+    preview: Option<Preview>,
     last_saved_revision: usize,
     version: i32, // should be usize?
 
@@ -110,6 +193,7 @@ impl fmt::Debug for Document {
             .field("selections", &self.selections)
             .field("path", &self.path)
             .field("encoding", &self.encoding)
+            .field("has_bom", &self.has_bom)
             .field("mode", &self.mode)
             .field("restore_cursor", &self.restore_cursor)
             .field("syntax", &self.syntax)
@@ -128,13 +212,14 @@ impl fmt::Debug for Document {
 // The documentation and implementation of this function should be up-to-date with
 // its sibling function, `to_writer()`.
 //
-/// Decodes a stream of bytes into UTF-8, returning a `Rope` and the
-/// encoding it was decoded as. The optional `encoding` parameter can
-/// be used to override encoding auto-detection.
+/// Decodes a stream of bytes into UTF-8, returning a `Rope`, the encoding
+/// it was decoded as and whether the stream started with a byte order mark.
+/// The optional `encoding` parameter can be used to override encoding
+/// auto-detection.
 pub fn from_reader<R: std::io::Read + ?Sized>(
     reader: &mut R,
-    encoding: Option<&'static encoding_rs::Encoding>,
-) -> Result<(Rope, &'static encoding_rs::Encoding), Error> {
+    encoding: Option<Encoding>,
+) -> Result<(Rope, Encoding, bool), Error> {
     // These two buffers are 8192 bytes in size each and are used as
     // intermediaries during the decoding process. Text read into `buf`
     // from `reader` is decoded into `buf_out` as UTF-8. Once either
@@ -144,26 +229,43 @@ pub fn from_reader<R: std::io::Read + ?Sized>(
     let mut buf_out = [0u8; BUF_SIZE];
     let mut builder = RopeBuilder::new();
 
-    // By default, the encoding of the text is auto-detected via the
-    // `chardetng` crate which requires sample data from the reader.
-    // As a manual override to this auto-detection is possible, the
-    // same data is read into `buf` to ensure symmetry in the upcoming
-    // loop.
-    let (encoding, mut decoder, mut slice, mut is_empty) = {
-        let read = reader.read(&mut buf)?;
-        let is_empty = read == 0;
-        let encoding = encoding.unwrap_or_else(|| {
+    // By default, the encoding of the text is detected from its byte
+    // order mark, or auto-detected via the `chardetng` crate which
+    // requires sample data from the reader. As a manual override to
+    // this auto-detection is possible, the same data is read into `buf`
+    // to ensure symmetry in the upcoming loop.
+    let read = reader.read(&mut buf)?;
+    let mut is_empty = read == 0;
+    let bom = Encoding::for_bom(&buf[..read]);
+    let encoding = encoding
+        .or_else(|| bom.map(|(encoding, _)| encoding))
+        .unwrap_or_else(|| {
             let mut encoding_detector = chardetng::EncodingDetector::new();
-            encoding_detector.feed(&buf, is_empty);
-            encoding_detector.guess(None, true)
+            encoding_detector.feed(&buf[..read], is_empty);
+            Encoding::Standard(encoding_detector.guess(None, true))
         });
-        let decoder = encoding.new_decoder();
-
-        // If the amount of bytes read from the reader is less than
-        // `buf.len()`, it is undesirable to read the bytes afterwards.
-        let slice = &buf[..read];
-        (encoding, decoder, slice, is_empty)
+    // The byte order mark is only skipped when it belongs to the encoding
+    // the text is decoded as.
+    let bom_len = match bom {
+        Some((bom_encoding, len)) if bom_encoding == encoding => len,
+        _ => 0,
     };
+    let has_bom = bom_len > 0;
+
+    let mut decoder = match encoding {
+        Encoding::Standard(encoding) => encoding.new_decoder_without_bom_handling(),
+        // `encoding_rs` doesn't implement UTF-32, it's decoded all at once.
+        Encoding::Utf32Le | Encoding::Utf32Be => {
+            let mut bytes = buf[bom_len..read].to_vec();
+            reader.read_to_end(&mut bytes)?;
+            let text = decode_utf32(&bytes, encoding == Encoding::Utf32Be);
+            return Ok((Rope::from(text), encoding, has_bom));
+        }
+    };
+
+    // If the amount of bytes read from the reader is less than
+    // `buf.len()`, it is undesirable to read the bytes afterwards.
+    let mut slice = &buf[bom_len..read];
 
     // `RopeBuilder::append()` expects a `&str`, so this is the "real"
     // output buffer. When decoding, the number of bytes in the output
@@ -230,20 +332,83 @@ pub fn from_reader<R: std::io::Read + ?Sized>(
         is_empty = read == 0;
     }
     let rope = builder.finish();
-    Ok((rope, encoding))
+    Ok((rope, encoding, has_bom))
+}
+
+/// Decodes UTF-32 `bytes`, invalid code points and a trailing partial one are replaced with
+/// U+FFFD.
+fn decode_utf32(bytes: &[u8], big_endian: bool) -> String {
+    bytes
+        .chunks(4)
+        .map(|unit| {
+            let unit = match std::convert::TryInto::<[u8; 4]>::try_into(unit) {
+                Ok(unit) => unit,
+                Err(_) => return char::REPLACEMENT_CHARACTER,
+            };
+            let code_point = if big_endian {
+                u32::from_be_bytes(unit)
+            } else {
+                u32::from_le_bytes(unit)
+            };
+            char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER)
+        })
+        .collect()
+}
+
+/// Encodes `text` as UTF-16 or UTF-32, `encoding_rs` only decodes UTF-16 and doesn't implement
+/// UTF-32. Returns `None` for the other encodings.
+fn encode_utf16_or_utf32(text: &str, encoding: Encoding) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 2);
+    match encoding {
+        Encoding::Standard(encoding) if encoding == encoding_rs::UTF_16LE => text
+            .encode_utf16()
+            .for_each(|unit| bytes.extend_from_slice(&unit.to_le_bytes())),
+        Encoding::Standard(encoding) if encoding == encoding_rs::UTF_16BE => text
+            .encode_utf16()
+            .for_each(|unit| bytes.extend_from_slice(&unit.to_be_bytes())),
+        Encoding::Standard(_) => return None,
+        Encoding::Utf32Le => text
+            .chars()
+            .for_each(|ch| bytes.extend_from_slice(&(ch as u32).to_le_bytes())),
+        Encoding::Utf32Be => text
+            .chars()
+            .for_each(|ch| bytes.extend_from_slice(&(ch as u32).to_be_bytes())),
+    }
+    Some(bytes)
 }
 
 // The documentation and implementation of this function should be up-to-date with
 // its sibling function, `from_reader()`.
 //
 /// Encodes the text inside `rope` into the given `encoding` and writes the
-/// encoded output into `writer.` As a `Rope` can only contain valid UTF-8,
+/// encoded output into `writer`, preceded by the byte order mark of the
+/// encoding if `has_bom` is set. As a `Rope` can only contain valid UTF-8,
 /// replacement characters may appear in the encoded text.
 pub async fn to_writer<'a, W: tokio::io::AsyncWriteExt + Unpin + ?Sized>(
     writer: &'a mut W,
-    encoding: &'static encoding_rs::Encoding,
+    encoding: Encoding,
+    has_bom: bool,
     rope: &'a Rope,
 ) -> Result<(), Error> {
+    if has_bom {
+        writer.write_all(encoding.bom()).await?;
+    }
+
+    // The encoders of `encoding_rs` write UTF-16 as UTF-8 and there's none
+    // for UTF-32, so these are encoded a chunk at a time instead.
+    let encoding = match encoding {
+        Encoding::Standard(standard) if encode_utf16_or_utf32("", encoding).is_none() => standard,
+        _ => {
+            for chunk in rope.chunks() {
+                if let Some(bytes) = encode_utf16_or_utf32(chunk, encoding) {
+                    writer.write_all(&bytes).await?;
+                }
+            }
+            writer.flush().await?;
+            return Ok(());
+        }
+    };
+
     // Text inside a `Rope` is stored as non-contiguous blocks of data called
     // chunks. The absolute size of each chunk is unknown, thus it is impossible
     // to predict the end of the chunk iterator ahead of time. Instead, it is
@@ -401,8 +566,8 @@ use helix_lsp::lsp;
 use url::Url;
 
 impl Document {
-    pub fn from(text: Rope, encoding: Option<&'static encoding_rs::Encoding>) -> Self {
-        let encoding = encoding.unwrap_or(encoding_rs::UTF_8);
+    pub fn from(text: Rope, encoding: Option<Encoding>) -> Self {
+        let encoding = encoding.unwrap_or(Encoding::Standard(encoding_rs::UTF_8));
         let changes = ChangeSet::new(&text);
         let old_state = None;
 
@@ -410,8 +575,11 @@ impl Document {
             id: DocumentId::default(),
             path: None,
             encoding,
+            has_bom: false,
             text,
             selections: HashMap::default(),
+            marks: Marks::new(),
+            snippet: None,
             indent_style: IndentStyle::Spaces(4),
             mode: Mode::Normal,
             restore_cursor: false,
@@ -422,6 +590,8 @@ impl Document {
             diagnostics: Vec::new(),
             version: 0,
             history: Cell::new(History::default()),
+            history_held: false,
+            preview: None,
             last_saved_revision: 0,
             language_server: None,
             line_ending: DEFAULT_LINE_ENDING,
@@ -433,20 +603,21 @@ impl Document {
     /// overwritten with the `encoding` parameter.
     pub fn open(
         path: PathBuf,
-        encoding: Option<&'static encoding_rs::Encoding>,
+        encoding: Option<Encoding>,
         theme: Option<&Theme>,
-        config_loader: Option<&syntax::Loader>,
+        config_loader: Option<Arc<syntax::Loader>>,
     ) -> Result<Self, Error> {
-        let (rope, encoding) = if path.exists() {
+        let (rope, encoding, has_bom) = if path.exists() {
             let mut file =
                 std::fs::File::open(&path).context(format!("unable to open {:?}", path))?;
             from_reader(&mut file, encoding)?
         } else {
-            let encoding = encoding.unwrap_or(encoding_rs::UTF_8);
-            (Rope::from(DEFAULT_LINE_ENDING.as_str()), encoding)
+            let encoding = encoding.unwrap_or(Encoding::Standard(encoding_rs::UTF_8));
+            (Rope::from(DEFAULT_LINE_ENDING.as_str()), encoding, false)
         };
 
         let mut doc = Self::from(rope, Some(encoding));
+        doc.has_bom = has_bom;
 
         // set the path and try detecting the language
         doc.set_path(&path)?;
@@ -456,6 +627,16 @@ impl Document {
 
         doc.detect_indent_and_line_ending();
 
+        // restore the undo history from a previous session if the file hasn't changed since
+        if let Some(history) = doc
+            .path()
+            .filter(|path| path.exists())
+            .and_then(|path| History::load_from_cache(path, doc.text()))
+        {
+            doc.last_saved_revision = history.current_revision();
+            doc.history.set(history);
+        }
+
         Ok(doc)
     }
 
@@ -528,7 +709,20 @@ impl Document {
         // mark changes up to now as saved
         self.reset_modified();
 
+        // uncommitted changes aren't part of the history yet, so it can't describe the saved text
+        let history = self.history.take();
+        let mut cached_history = if self.changes.is_empty() {
+            history
+                .serialize_for_cache(&path, &text)
+                .map_err(|err| log::warn!("failed to serialize history for {:?}: {}", path, err))
+                .ok()
+        } else {
+            None
+        };
+        self.history.set(history);
+
         let encoding = self.encoding;
+        let has_bom = self.has_bom;
 
         // We encode the file according to the `Document`'s encoding.
         async move {
@@ -543,16 +737,27 @@ impl Document {
             }
 
             if let Some(fmt) = formatting {
+                let unformatted = text.clone();
                 let success = Transaction::from(fmt.await).changes().apply(&mut text);
                 if !success {
                     // This shouldn't happen, because the transaction changes were generated
                     // from the same text we're saving.
                     log::error!("failed to apply format changes before saving");
                 }
+                // the history no longer describes the saved text
+                if text != unformatted {
+                    cached_history = None;
+                }
             }
 
-            let mut file = File::create(path).await?;
-            to_writer(&mut file, encoding, &text).await?;
+            let mut file = File::create(&path).await?;
+            to_writer(&mut file, encoding, has_bom, &text).await?;
+
+            if let Some(data) = cached_history {
+                if let Err(err) = History::write_to_cache(&path, &data) {
+                    log::warn!("failed to save history for {:?}: {}", path, err);
+                }
+            }
 
             if let Some(language_server) = language_server {
                 language_server
@@ -564,11 +769,11 @@ impl Document {
         }
     }
 
-    pub fn detect_language(&mut self, theme: Option<&Theme>, config_loader: &syntax::Loader) {
-        if let Some(path) = &self.path {
-            let language_config = config_loader.language_config_for_file_name(path);
-            self.set_language(theme, language_config);
-        }
+    /// Detect the language from a modeline, the file name, a shebang or the first line.
+    pub fn detect_language(&mut self, theme: Option<&Theme>, config_loader: Arc<syntax::Loader>) {
+        let language_config =
+            config_loader.language_config_for_file(self.path.as_deref(), self.text.slice(..));
+        self.set_language(theme, language_config, config_loader);
     }
 
     pub fn detect_indent_and_line_ending(&mut self) {
@@ -585,7 +790,7 @@ impl Document {
 
     /// Reload the document from its path.
     pub fn reload(&mut self, view_id: ViewId) -> Result<(), Error> {
-        let encoding = &self.encoding;
+        let encoding = self.encoding;
         let path = self.path().filter(|path| path.exists());
 
         // If there is no path or the path no longer exists.
@@ -594,7 +799,8 @@ impl Document {
         }
 
         let mut file = std::fs::File::open(path.unwrap())?;
-        let (rope, ..) = from_reader(&mut file, Some(encoding))?;
+        let (rope, _, has_bom) = from_reader(&mut file, Some(encoding))?;
+        self.has_bom = has_bom;
 
         let transaction = helix_core::diff::compare_ropes(self.text(), &rope);
         self.apply(&transaction, view_id);
@@ -608,18 +814,25 @@ impl Document {
 
     /// Sets the [`Document`]'s encoding with the encoding correspondent to `label`.
     pub fn set_encoding(&mut self, label: &str) -> Result<(), Error> {
-        match encoding_rs::Encoding::for_label(label.as_bytes()) {
+        match Encoding::for_label(label) {
             Some(encoding) => self.encoding = encoding,
             None => return Err(anyhow::anyhow!("unknown encoding")),
         }
+        // the byte order mark is kept for the encodings that have one
+        self.has_bom = self.has_bom && !self.encoding.bom().is_empty();
         Ok(())
     }
 
     /// Returns the [`Document`]'s current encoding.
-    pub fn encoding(&self) -> &'static encoding_rs::Encoding {
+    pub fn encoding(&self) -> Encoding {
         self.encoding
     }
 
+    /// Whether the [`Document`]'s file starts with a byte order mark.
+    pub fn has_bom(&self) -> bool {
+        self.has_bom
+    }
+
     pub fn set_path(&mut self, path: &Path) -> Result<(), std::io::Error> {
         let path = canonicalize_path(path)?;
 
@@ -634,11 +847,12 @@ impl Document {
         &mut self,
         theme: Option<&Theme>,
         language_config: Option<Arc<helix_core::syntax::LanguageConfiguration>>,
+        config_loader: Arc<syntax::Loader>,
     ) {
         if let Some(language_config) = language_config {
             let scopes = theme.map(|theme| theme.scopes()).unwrap_or(&[]);
             if let Some(highlight_config) = language_config.highlight_config(scopes) {
-                let syntax = Syntax::new(&self.text, highlight_config);
+                let syntax = Syntax::new(&self.text, highlight_config, config_loader);
                 self.syntax = Some(syntax);
                 // TODO: config.configure(scopes) is now delayed, is that ok?
             }
@@ -658,7 +872,7 @@ impl Document {
     ) {
         let language_config = config_loader.language_config_for_scope(scope);
 
-        self.set_language(theme, language_config);
+        self.set_language(theme, language_config, config_loader);
     }
 
     pub fn set_language_server(&mut self, language_server: Option<Arc<helix_lsp::Client>>) {
@@ -671,7 +885,12 @@ impl Document {
             .insert(view_id, selection.ensure_invariants(self.text().slice(..)));
     }
 
-    fn apply_impl(&mut self, transaction: &Transaction, view_id: ViewId) -> bool {
+    fn apply_impl(
+        &mut self,
+        transaction: &Transaction,
+        view_id: ViewId,
+        emit_lsp_notification: bool,
+    ) -> bool {
         let old_doc = self.text().clone();
 
         let success = transaction.changes().apply(&mut self.text);
@@ -686,6 +905,12 @@ impl Document {
                     .ensure_invariants(self.text.slice(..));
             }
 
+            self.marks.apply(transaction.changes(), self.text.slice(..));
+
+            if let Some(snippet) = &mut self.snippet {
+                snippet.map(transaction.changes());
+            }
+
             // if specified, the current selection should instead be replaced by transaction.selection
             if let Some(selection) = transaction.selection() {
                 self.selections.insert(
@@ -716,18 +941,21 @@ impl Document {
             //     diagnostic.line = self.text.char_to_line(diagnostic.range.start);
             // }
 
-            // emit lsp notification
-            if let Some(language_server) = &self.language_server {
-                let notify = language_server.text_document_did_change(
-                    self.versioned_identifier(),
-                    &old_doc,
-                    self.text(),
-                    transaction.changes(),
-                );
-
-                if let Some(notify) = notify {
-                    tokio::spawn(notify);
-                } //.expect("failed to emit textDocument/didChange");
+            // emit lsp notification, previews are reverted before the language server would see
+            // any other change
+            if emit_lsp_notification {
+                if let Some(language_server) = &self.language_server {
+                    let notify = language_server.text_document_did_change(
+                        self.versioned_identifier(),
+                        &old_doc,
+                        self.text(),
+                        transaction.changes(),
+                    );
+
+                    if let Some(notify) = notify {
+                        tokio::spawn(notify);
+                    } //.expect("failed to emit textDocument/didChange");
+                }
             }
         }
         success
@@ -743,7 +971,7 @@ impl Document {
             });
         }
 
-        let success = self.apply_impl(transaction, view_id);
+        let success = self.apply_impl(transaction, view_id, true);
 
         if !transaction.changes().is_empty() {
             // Compose this transaction with the previous one
@@ -757,7 +985,7 @@ impl Document {
     pub fn undo(&mut self, view_id: ViewId) {
         let mut history = self.history.take();
         let success = if let Some(transaction) = history.undo() {
-            self.apply_impl(transaction, view_id)
+            self.apply_impl(transaction, view_id, true)
         } else {
             false
         };
@@ -772,7 +1000,7 @@ impl Document {
     pub fn redo(&mut self, view_id: ViewId) {
         let mut history = self.history.take();
         let success = if let Some(transaction) = history.redo() {
-            self.apply_impl(transaction, view_id)
+            self.apply_impl(transaction, view_id, true)
         } else {
             false
         };
@@ -787,20 +1015,107 @@ impl Document {
     pub fn earlier(&mut self, view_id: ViewId, uk: helix_core::history::UndoKind) {
         let txns = self.history.get_mut().earlier(uk);
         for txn in txns {
-            self.apply_impl(&txn, view_id);
+            self.apply_impl(&txn, view_id, true);
         }
     }
 
     pub fn later(&mut self, view_id: ViewId, uk: helix_core::history::UndoKind) {
         let txns = self.history.get_mut().later(uk);
         for txn in txns {
-            self.apply_impl(&txn, view_id);
+            self.apply_impl(&txn, view_id, true);
+        }
+    }
+
+    /// Jump to an arbitrary revision of the undo tree.
+    pub fn jump_to_revision(&mut self, view_id: ViewId, revision: usize) {
+        let txns = self.history.get_mut().jump_to(revision);
+        for txn in txns {
+            self.apply_impl(&txn, view_id, true);
         }
+        // reset changeset to fix len
+        self.changes = ChangeSet::new(self.text());
+    }
+
+    /// Gives access to the undo tree without taking it out of the document.
+    pub fn with_history<T>(&self, f: impl FnOnce(&History) -> T) -> T {
+        let history = self.history.take();
+        let result = f(&history);
+        self.history.set(history);
+        result
+    }
+
+    /// Writes the history to the cache as of the last save, for when the file is opened again
+    /// after the document is closed. Changes that weren't saved are kept to be redone.
+    pub fn persist_history(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut history = self.with_history(History::clone);
+        if history.len() == 1 {
+            return;
+        }
+
+        // uncommitted changes aren't part of the history yet
+        let mut text = match &self.old_state {
+            Some(state) if !self.changes.is_empty() => state.doc.clone(),
+            _ => self.text.clone(),
+        };
+        for transaction in history.jump_to(self.last_saved_revision) {
+            transaction.apply(&mut text);
+        }
+
+        let result = history
+            .serialize_for_cache(path, &text)
+            .and_then(|data| History::write_to_cache(path, &data));
+        if let Err(err) = result {
+            log::warn!("failed to save history for {:?}: {}", path, err);
+        }
+    }
+
+    /// Apply a transaction to preview its result. The changes aren't recorded in the history nor
+    /// sent to the language server, and get reverted by [`Document::revert_preview`], which has to
+    /// happen before the next preview as `transaction` applies to the text without the current one.
+    pub fn preview(&mut self, transaction: &Transaction, view_id: ViewId) {
+        debug_assert!(self.preview.is_none());
+
+        let preview = Preview {
+            revert: transaction.invert(self.text()),
+            selections: self.selections.clone(),
+            marks: self.marks.clone(),
+            snippet: self.snippet.clone(),
+        };
+        if self.apply_impl(transaction, view_id, false) {
+            self.preview = Some(preview);
+        }
+    }
+
+    /// Revert the changes of the current preview, if any. The selections, marks and snippet
+    /// are restored as they were before the preview rather than mapped back through it.
+    pub fn revert_preview(&mut self, view_id: ViewId) {
+        if let Some(preview) = self.preview.take() {
+            self.apply_impl(&preview.revert, view_id, false);
+            self.selections = preview.selections;
+            self.marks = preview.marks;
+            self.snippet = preview.snippet;
+        }
+    }
+
+    /// Keep the changes out of the history until [`Document::release_history`], so the changes
+    /// of several commands become a single undo step.
+    pub fn hold_history(&mut self) {
+        self.history_held = true;
+    }
+
+    /// Commit the changes made since [`Document::hold_history`] to history.
+    pub fn release_history(&mut self, view_id: ViewId) {
+        self.history_held = false;
+        self.append_changes_to_history(view_id);
     }
 
     /// Commit pending changes to history
     pub fn append_changes_to_history(&mut self, view_id: ViewId) {
-        if self.changes.is_empty() {
+        if self.changes.is_empty() || self.history_held {
             return;
         }
 
@@ -852,6 +1167,35 @@ impl Document {
         self.language.as_deref()
     }
 
+    /// The language configuration at `pos`: the innermost language injected there, or the
+    /// document's language.
+    pub fn language_config_at(&self, pos: usize) -> Option<&LanguageConfiguration> {
+        let byte_pos = self.text.char_to_byte(pos);
+        self.syntax
+            .as_ref()
+            .and_then(|syntax| {
+                syntax
+                    .layers_for_byte_range(byte_pos, byte_pos)
+                    .find_map(|layer| layer.language_config())
+            })
+            .map(AsRef::as_ref)
+            .or_else(|| self.language_config())
+    }
+
+    /// The pairs to auto close at `pos`, `None` if the language disables them.
+    pub fn auto_pairs_at(&self, pos: usize) -> Option<&AutoPairs> {
+        match self.language_config_at(pos) {
+            Some(config) => config.auto_pairs.as_ref(),
+            None => Some(&DEFAULT_AUTO_PAIRS),
+        }
+    }
+
+    /// Whether long lines are soft wrapped, configured per language.
+    pub fn soft_wrap(&self) -> bool {
+        self.language_config()
+            .map_or(false, |config| config.soft_wrap)
+    }
+
     /// Current document version, incremented at each change.
     pub fn version(&self) -> i32 {
         self.version
@@ -941,6 +1285,16 @@ impl Document {
     pub fn set_diagnostics(&mut self, diagnostics: Vec<Diagnostic>) {
         self.diagnostics = diagnostics;
     }
+
+    #[inline]
+    pub fn marks(&self) -> &Marks {
+        &self.marks
+    }
+
+    #[inline]
+    pub fn marks_mut(&mut self) -> &mut Marks {
+        &mut self.marks
+    }
 }
 
 impl Default for Document {
@@ -1068,6 +1422,109 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_snippet_tabstops_follow_edits() {
+        use helix_core::snippets;
+
+        let mut doc = Document::from(Rope::from("\n"), None);
+        let view = ViewId::default();
+        doc.set_selection(view, Selection::point(0));
+        let snippet = snippets::parse("fn ${1:name}($2) {}").render("\n", |_| None);
+        let transaction = Transaction::insert(
+            doc.text(),
+            &Selection::point(0),
+            snippet.text.as_str().into(),
+        );
+        doc.apply(&transaction, view);
+        doc.snippet = ActiveSnippet::new(&snippet, 0);
+        doc.set_selection(view, doc.snippet.as_ref().unwrap().selection());
+        assert_eq!(
+            doc.selection(view).primary().fragment(doc.text().slice(..)),
+            "name"
+        );
+
+        // type over the placeholder, then some more at its end
+        let transaction =
+            Transaction::change(doc.text(), vec![(3, 7, Some("ma".into()))].into_iter());
+        doc.apply(&transaction, view);
+        let transaction = Transaction::insert(doc.text(), &Selection::point(5), "in".into());
+        doc.apply(&transaction, view);
+        assert_eq!(doc.text(), &Rope::from("fn main() {}\n"));
+
+        let snippet = doc.snippet.as_mut().unwrap();
+        assert_eq!(snippet.selection(), Selection::single(7, 3));
+        snippet.next_tabstop();
+        assert_eq!(snippet.selection(), Selection::point(8));
+    }
+
+    #[test]
+    fn test_hold_history() {
+        let mut doc = Document::from(Rope::from("hello"), None);
+        let view = ViewId::default();
+        doc.set_selection(view, Selection::single(5, 5));
+
+        doc.hold_history();
+        for text in &[" big", " world"] {
+            let transaction = Transaction::insert(doc.text(), doc.selection(view), (*text).into());
+            doc.apply(&transaction, view);
+            doc.append_changes_to_history(view);
+        }
+        assert_eq!(doc.with_history(|history| history.len()), 1);
+        doc.release_history(view);
+        assert_eq!(doc.with_history(|history| history.len()), 2);
+
+        // both insertions are undone at once
+        doc.undo(view);
+        assert_eq!(doc.text(), &Rope::from("hello"));
+    }
+
+    #[test]
+    fn test_preview() {
+        let mut doc = Document::from(Rope::from("hello"), None);
+        let view = ViewId::default();
+        doc.set_selection(view, Selection::single(5, 5));
+
+        let transaction = Transaction::insert(doc.text(), doc.selection(view), " world".into());
+        doc.preview(&transaction, view);
+        assert_eq!(doc.text(), &Rope::from("hello world"));
+        assert!(!doc.is_modified());
+
+        doc.revert_preview(view);
+        let transaction = Transaction::change(doc.text(), vec![(0, 5, None)].into_iter());
+        doc.preview(&transaction, view);
+        assert_eq!(doc.text(), &Rope::from(""));
+
+        doc.revert_preview(view);
+        assert_eq!(doc.text(), &Rope::from("hello"));
+        assert_eq!(doc.selection(view), &Selection::single(5, 5));
+        assert!(!doc.is_modified());
+    }
+
+    #[test]
+    fn test_preview_restores_marks() {
+        let mut doc = Document::from(Rope::from("hello world"), None);
+        let view = ViewId::default();
+        let other = {
+            let mut views = slotmap::HopSlotMap::<ViewId, ()>::with_key();
+            views.insert(());
+            views.insert(())
+        };
+        doc.set_selection(view, Selection::point(0));
+        doc.set_selection(other, Selection::single(7, 9));
+        doc.marks_mut().set('a', Selection::single(8, 10));
+
+        // a deletion around the mark and the other view's selection collapses them
+        let transaction = Transaction::change(doc.text(), vec![(5, 11, None)].into_iter());
+        doc.preview(&transaction, view);
+        assert_eq!(doc.text(), &Rope::from("hello"));
+        assert_eq!(doc.marks().get('a'), Some(&Selection::point(5)));
+
+        doc.revert_preview(view);
+        assert_eq!(doc.text(), &Rope::from("hello world"));
+        assert_eq!(doc.marks().get('a'), Some(&Selection::single(8, 10)));
+        assert_eq!(doc.selection(other), &Selection::single(7, 9));
+    }
+
     #[test]
     fn test_line_ending() {
         assert_eq!(
@@ -1076,9 +1533,57 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_detect_encoding() {
+        for (encoding, sample) in &[
+            (
+                encoding_rs::SHIFT_JIS,
+                "日本語の文章です。文字コードを自動で判定します。",
+            ),
+            (
+                encoding_rs::GBK,
+                "这是一段中文文本，编辑器会自动检测它的编码。",
+            ),
+        ] {
+            let (bytes, ..) = encoding.encode(sample);
+            let (text, detected, has_bom) = from_reader(&mut bytes.as_ref(), None).unwrap();
+            assert_eq!(detected, Encoding::Standard(encoding));
+            assert_eq!(text, *sample);
+            assert!(!has_bom);
+        }
+
+        let mut bytes: &[u8] = b"caf\xe9 na\xefve r\xe9sum\xe9\n";
+        let (text, encoding, _) = from_reader(&mut bytes, None).unwrap();
+        assert_eq!(encoding.name(), "windows-1252");
+        assert_eq!(text, "café naïve résumé\n");
+    }
+
+    #[test]
+    fn test_bom_round_trip() {
+        let text = Rope::from("héllo 😀\n");
+        for label in &["utf-8", "utf-16le", "utf-16be", "utf-32le", "utf-32be"] {
+            let encoding = Encoding::for_label(label).unwrap();
+            let mut buf: Vec<u8> = Vec::new();
+            helix_lsp::block_on(to_writer(&mut buf, encoding, true, &text)).unwrap();
+            assert!(buf.starts_with(encoding.bom()));
+
+            let (decoded, detected, has_bom) = from_reader(&mut buf.as_slice(), None).unwrap();
+            assert_eq!(decoded, text);
+            assert_eq!(detected, encoding);
+            assert!(has_bom);
+        }
+
+        // a mark of another encoding is kept in the text
+        let mut bytes: &[u8] = b"\xef\xbb\xbfa";
+        let encoding = Encoding::for_label("windows-1252");
+        let (decoded, _, has_bom) = from_reader(&mut bytes, encoding).unwrap();
+        assert_eq!(decoded, "ï»¿a");
+        assert!(!has_bom);
+    }
+
     macro_rules! test_decode {
         ($label:expr, $label_override:expr) => {
-            let encoding = encoding_rs::Encoding::for_label($label_override.as_bytes()).unwrap();
+            let encoding = Encoding::for_label($label_override).unwrap();
             let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/encoding");
             let path = base_path.join(format!("{}_in.txt", $label));
             let ref_path = base_path.join(format!("{}_in_ref.txt", $label));
@@ -1097,7 +1602,7 @@ mod test {
 
     macro_rules! test_encode {
         ($label:expr, $label_override:expr) => {
-            let encoding = encoding_rs::Encoding::for_label($label_override.as_bytes()).unwrap();
+            let encoding = Encoding::for_label($label_override).unwrap();
             let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/encoding");
             let path = base_path.join(format!("{}_out.txt", $label));
             let ref_path = base_path.join(format!("{}_out_ref.txt", $label));
@@ -1106,7 +1611,7 @@ mod test {
 
             let text = Rope::from_str(&std::fs::read_to_string(path).unwrap());
             let mut buf: Vec<u8> = Vec::new();
-            helix_lsp::block_on(to_writer(&mut buf, encoding, &text)).unwrap();
+            helix_lsp::block_on(to_writer(&mut buf, encoding, false, &text)).unwrap();
 
             let expectation = std::fs::read(ref_path).unwrap();
             assert_eq!(buf, expectation);
@@ -1,13 +1,15 @@
 use crate::{
     clipboard::{get_clipboard_provider, ClipboardProvider},
     graphics::{CursorKind, Rect},
+    input::KeyEvent,
+    marks::is_global_mark,
     theme::{self, Theme},
     tree::Tree,
     Document, DocumentId, RegisterSelection, View, ViewId,
 };
 
 use futures_util::future;
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 
 use slotmap::SlotMap;
 
@@ -16,7 +18,7 @@ use anyhow::Error;
 pub use helix_core::diagnostic::Severity;
 pub use helix_core::register::Registers;
 use helix_core::syntax;
-use helix_core::Position;
+use helix_core::{Position, Range, Selection};
 
 use serde::Deserialize;
 
@@ -45,6 +47,14 @@ pub struct Editor {
     pub count: Option<std::num::NonZeroUsize>,
     pub selected_register: RegisterSelection,
     pub registers: Registers,
+    /// The register and the keys of the macro being recorded.
+    pub macro_recording: Option<(char, Vec<KeyEvent>)>,
+    /// The registers of the macros being replayed, a macro can't replay itself.
+    pub macro_replaying: Vec<char>,
+    /// The global marks `A-Z` of files that were closed, along with their paths. The marks of
+    /// open documents are kept in the documents, they're moved here when the document is closed
+    /// and back when the file is opened again.
+    pub global_marks: HashMap<char, (PathBuf, Selection)>,
     pub theme: Theme,
     pub language_servers: helix_lsp::Registry,
     pub clipboard_provider: Box<dyn ClipboardProvider>,
@@ -77,16 +87,22 @@ impl Editor {
         // HAXX: offset the render area height by 1 to account for prompt/commandline
         area.height -= 1;
 
+        let theme = themes.default();
+        config_loader.set_scopes(theme.scopes().to_vec());
+
         Self {
             tree: Tree::new(area),
             documents: SlotMap::with_key(),
             count: None,
             selected_register: RegisterSelection::default(),
-            theme: themes.default(),
+            theme,
             language_servers,
             syn_loader: config_loader,
             theme_loader: themes,
             registers: Registers::default(),
+            macro_recording: None,
+            macro_replaying: Vec::new(),
+            global_marks: HashMap::new(),
             clipboard_provider: get_clipboard_provider(),
             status_msg: None,
             config,
@@ -106,14 +122,7 @@ impl Editor {
     }
 
     pub fn set_theme(&mut self, theme: Theme) {
-        let scopes = theme.scopes();
-        for config in self
-            .syn_loader
-            .language_configs_iter()
-            .filter(|cfg| cfg.is_highlight_initialized())
-        {
-            config.reconfigure(scopes);
-        }
+        self.syn_loader.set_scopes(theme.scopes().to_vec());
 
         self.theme = theme;
         self._refresh();
@@ -138,7 +147,6 @@ impl Editor {
 
     pub fn switch(&mut self, id: DocumentId, action: Action) {
         use crate::tree::Layout;
-        use helix_core::Selection;
 
         if !self.documents.contains_key(id) {
             log::error!("cannot switch to document that does not exist (anymore)");
@@ -158,6 +166,7 @@ impl Editor {
                 view.last_accessed_doc = Some(view.doc);
                 view.doc = id;
                 view.first_line = 0;
+                view.first_row = 0;
 
                 let (view, doc) = current!(self);
 
@@ -216,7 +225,8 @@ impl Editor {
         let id = if let Some(id) = id {
             id
         } else {
-            let mut doc = Document::open(path, None, Some(&self.theme), Some(&self.syn_loader))?;
+            let mut doc =
+                Document::open(path, None, Some(&self.theme), Some(self.syn_loader.clone()))?;
 
             // try to find a language server based on the language name
             let language_server = doc
@@ -241,6 +251,27 @@ impl Editor {
                 ));
             }
 
+            // restore the global marks set in the file before it was closed
+            let path = doc.path().cloned();
+            let len = doc.text().len_chars();
+            let text = doc.text().slice(..);
+            let mut marks = Vec::new();
+            self.global_marks.retain(|name, (mark_path, selection)| {
+                if path.as_ref() != Some(mark_path) {
+                    return true;
+                }
+                // the file may have been changed since
+                let selection = selection
+                    .clone()
+                    .transform(|range| Range::new(range.anchor.min(len), range.head.min(len)))
+                    .ensure_invariants(text);
+                marks.push((*name, selection));
+                false
+            });
+            for (name, selection) in marks {
+                doc.marks_mut().set(name, selection);
+            }
+
             let id = self.documents.insert(doc);
             self.documents[id].id = id;
             id
@@ -267,6 +298,16 @@ impl Editor {
             if let Some(language_server) = language_server {
                 tokio::spawn(language_server.text_document_did_close(doc.identifier()));
             }
+            doc.persist_history();
+            // keep the global marks so they can be jumped to after reopening the file
+            if let Some(path) = doc.path() {
+                for (name, selection) in doc.marks().iter() {
+                    if is_global_mark(name) {
+                        self.global_marks
+                            .insert(name, (path.clone(), selection.clone()));
+                    }
+                }
+            }
             self.documents.remove(view.doc);
         }
 
@@ -274,6 +315,45 @@ impl Editor {
         self._refresh();
     }
 
+    /// Saves `selection` of the document `doc_id` as the mark `name`. A global mark is moved away
+    /// from the document or file it was previously set in.
+    pub fn set_mark(&mut self, name: char, doc_id: DocumentId, selection: Selection) {
+        if is_global_mark(name) {
+            self.global_marks.remove(&name);
+            for doc in self.documents.values_mut() {
+                doc.marks_mut().remove(name);
+            }
+        }
+
+        if let Some(doc) = self.documents.get_mut(doc_id) {
+            doc.marks_mut().set(name, selection);
+        }
+    }
+
+    /// Looks up the mark `name` as seen from the document `doc_id`. Returns the document the mark
+    /// is in, which is `doc_id` unless it's a global mark, and its selection. Global marks of
+    /// closed files are only found once [`Editor::open_global_mark`] opened them.
+    pub fn mark(&self, name: char, doc_id: DocumentId) -> Option<(DocumentId, &Selection)> {
+        if !is_global_mark(name) {
+            let selection = self.documents.get(doc_id)?.marks().get(name)?;
+            return Some((doc_id, selection));
+        }
+
+        self.documents.iter().find_map(|(id, doc)| {
+            let selection = doc.marks().get(name)?;
+            Some((id, selection))
+        })
+    }
+
+    /// Opens the file of the global mark `name` in the background if it was closed, which moves
+    /// the mark back into the document. Does nothing for the other marks.
+    pub fn open_global_mark(&mut self, name: char) -> Result<(), Error> {
+        if let Some((path, _)) = self.global_marks.get(&name).cloned() {
+            self.open(path, Action::Load)?;
+        }
+        Ok(())
+    }
+
     pub fn resize(&mut self, area: Rect) {
         if self.tree.resize(area) {
             self._refresh();
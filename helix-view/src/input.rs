@@ -208,6 +208,42 @@ impl std::str::FromStr for KeyEvent {
     }
 }
 
+/// Parses the keys of a macro: characters stand for themselves and other keys are written like
+/// in the keymap within angle brackets, e.g. `i<C-w>x<esc>`.
+pub fn parse_macro(keys: &str) -> Result<Vec<KeyEvent>, Error> {
+    let mut remaining = keys;
+    let mut events = Vec::new();
+    while let Some(ch) = remaining.chars().next() {
+        let (key, len) = if ch == '<' {
+            let end = remaining
+                .find('>')
+                .ok_or_else(|| anyhow!("Unclosed '<' in macro '{}'", keys))?;
+            (remaining[1..end].parse()?, end + 1)
+        } else {
+            (
+                KeyEvent {
+                    code: KeyCode::Char(ch),
+                    modifiers: KeyModifiers::NONE,
+                },
+                ch.len_utf8(),
+            )
+        };
+        events.push(key);
+        remaining = &remaining[len..];
+    }
+    Ok(events)
+}
+
+/// Writes `keys` in the format read by [`parse_macro`].
+pub fn serialize_macro(keys: &[KeyEvent]) -> String {
+    keys.iter()
+        .map(|key| match key.to_string() {
+            key if key.chars().count() == 1 => key,
+            key => format!("<{}>", key),
+        })
+        .collect()
+}
+
 impl<'de> Deserialize<'de> for KeyEvent {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -230,6 +266,16 @@ impl From<crossterm::event::KeyEvent> for KeyEvent {
     }
 }
 
+#[cfg(feature = "term")]
+impl From<KeyEvent> for crossterm::event::KeyEvent {
+    fn from(KeyEvent { code, modifiers }: KeyEvent) -> Self {
+        crossterm::event::KeyEvent {
+            code: code.into(),
+            modifiers: modifiers.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -315,4 +361,43 @@ mod test {
         assert!(str::parse::<KeyEvent>("123").is_err());
         assert!(str::parse::<KeyEvent>("S--").is_err());
     }
+
+    #[test]
+    fn parsing_macros() {
+        let keys = parse_macro("iä<C-w><lt><ret><esc>").unwrap();
+        assert_eq!(
+            keys,
+            [
+                KeyEvent {
+                    code: KeyCode::Char('i'),
+                    modifiers: KeyModifiers::NONE
+                },
+                KeyEvent {
+                    code: KeyCode::Char('ä'),
+                    modifiers: KeyModifiers::NONE
+                },
+                KeyEvent {
+                    code: KeyCode::Char('w'),
+                    modifiers: KeyModifiers::CONTROL
+                },
+                KeyEvent {
+                    code: KeyCode::Char('<'),
+                    modifiers: KeyModifiers::NONE
+                },
+                KeyEvent {
+                    code: KeyCode::Enter,
+                    modifiers: KeyModifiers::NONE
+                },
+                KeyEvent {
+                    code: KeyCode::Esc,
+                    modifiers: KeyModifiers::NONE
+                },
+            ]
+        );
+        assert_eq!(serialize_macro(&keys), "iä<C-w><lt><ret><esc>");
+        assert_eq!(parse_macro("").unwrap(), []);
+
+        assert!(parse_macro("a<esc").is_err());
+        assert!(parse_macro("<foo>").is_err());
+    }
 }
@@ -8,6 +8,7 @@ pub mod graphics;
 pub mod info;
 pub mod input;
 pub mod keyboard;
+pub mod marks;
 pub mod register_selection;
 pub mod theme;
 pub mod tree;
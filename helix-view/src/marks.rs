@@ -0,0 +1,99 @@
+use helix_core::{ChangeSet, RopeSlice, Selection};
+use std::collections::BTreeMap;
+
+/// Whether `name` can be used for a mark: lowercase marks `a-z` are local to a document,
+/// uppercase marks `A-Z` are global and jump across files.
+pub fn is_valid_mark(name: char) -> bool {
+    name.is_ascii_alphabetic()
+}
+
+pub fn is_global_mark(name: char) -> bool {
+    name.is_ascii_uppercase()
+}
+
+/// Named selections of a document. They're remapped through every change applied to the
+/// document, so they keep pointing at the same text.
+#[derive(Debug, Default, Clone)]
+pub struct Marks {
+    marks: BTreeMap<char, Selection>,
+}
+
+impl Marks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: char) -> Option<&Selection> {
+        self.marks.get(&name)
+    }
+
+    pub fn set(&mut self, name: char, selection: Selection) {
+        self.marks.insert(name, selection);
+    }
+
+    pub fn remove(&mut self, name: char) -> Option<Selection> {
+        self.marks.remove(&name)
+    }
+
+    /// Iterates over the marks, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (char, &Selection)> {
+        self.marks
+            .iter()
+            .map(|(name, selection)| (*name, selection))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    /// Map the marks through `changes`, `text` being the document after the changes.
+    pub fn apply(&mut self, changes: &ChangeSet, text: RopeSlice) {
+        for selection in self.marks.values_mut() {
+            *selection = selection.clone().map(changes).ensure_invariants(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use helix_core::{Range, Rope, Transaction};
+
+    #[test]
+    fn marks_follow_changes() {
+        let mut doc = Rope::from("hello world\nfoo bar\n");
+        let mut marks = Marks::new();
+        marks.set('a', Selection::single(6, 11));
+        marks.set(
+            'B',
+            Selection::new(vec![Range::new(12, 15), Range::new(16, 19)].into(), 1),
+        );
+
+        // insert before both marks and delete the start of the first one
+        let transaction = Transaction::change(
+            &doc,
+            vec![(0, 0, Some("> ".into())), (6, 8, None)].into_iter(),
+        );
+        transaction.apply(&mut doc);
+        marks.apply(transaction.changes(), doc.slice(..));
+
+        assert_eq!(doc, "> hello rld\nfoo bar\n");
+        assert_eq!(marks.get('a'), Some(&Selection::single(8, 11)));
+        let b = marks.get('B').unwrap();
+        assert_eq!(b.ranges(), &[Range::new(12, 15), Range::new(16, 19)]);
+        assert_eq!(b.primary_index(), 1);
+
+        // deleting the text of a mark collapses it to a single character
+        let transaction = Transaction::change(&doc, vec![(6, 11, None)].into_iter());
+        transaction.apply(&mut doc);
+        marks.apply(transaction.changes(), doc.slice(..));
+        assert_eq!(marks.get('a'), Some(&Selection::single(6, 7)));
+
+        assert_eq!(
+            marks.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            vec!['B', 'a']
+        );
+        assert!(marks.remove('a').is_some());
+        assert!(marks.get('a').is_none());
+    }
+}
@@ -5,9 +5,13 @@ use helix_core::{
     coords_at_pos,
     graphemes::{grapheme_width, RopeGraphemes},
     line_ending::line_end_char_index,
+    wrap::WrappedLine,
     Position, RopeSlice, Selection,
 };
 
+// TODO: not ideal
+const OFFSET: u16 = 7; // 1 diagnostic + 5 linenr + 1 gutter
+
 type Jump = (DocumentId, Selection);
 
 #[derive(Debug)]
@@ -62,11 +66,17 @@ pub struct View {
     pub id: ViewId,
     pub doc: DocumentId,
     pub first_line: usize,
+    /// The rows of `first_line` scrolled past, for soft wrapped lines taller than the view.
+    pub first_row: usize,
     pub first_col: usize,
     pub area: Rect,
     pub jumps: JumpList,
     /// the last accessed file before the current one
     pub last_accessed_doc: Option<DocumentId>,
+    /// The selections before each syntax node expansion, shrinking restores them.
+    pub object_selections: Vec<Selection>,
+    /// The selections before each match added to them, removing the last match restores them.
+    pub match_selections: Vec<Selection>,
 }
 
 impl View {
@@ -75,14 +85,21 @@ impl View {
             id: ViewId::default(),
             doc,
             first_line: 0,
+            first_row: 0,
             first_col: 0,
             area: Rect::default(), // will get calculated upon inserting into tree
             jumps: JumpList::new((doc, Selection::point(0))), // TODO: use actual sel
             last_accessed_doc: None,
+            object_selections: Vec::new(),
+            match_selections: Vec::new(),
         }
     }
 
     pub fn ensure_cursor_in_view(&mut self, doc: &Document, scrolloff: usize) {
+        if let Some(width) = self.wrap_width(doc) {
+            return self.ensure_cursor_in_wrapped_view(doc, scrolloff, width);
+        }
+
         let cursor = doc
             .selection(self.id)
             .primary()
@@ -95,9 +112,8 @@ impl View {
 
         let scrolloff = scrolloff.min(self.area.height as usize / 2);
 
-        // TODO: not ideal
-        const OFFSET: usize = 7; // 1 diagnostic + 5 linenr + 1 gutter
-        let last_col = (self.first_col + self.area.width as usize).saturating_sub(OFFSET + 1);
+        let last_col =
+            (self.first_col + self.area.width as usize).saturating_sub(OFFSET as usize + 1);
 
         if line > last_line.saturating_sub(scrolloff) {
             // scroll down
@@ -116,14 +132,119 @@ impl View {
         }
     }
 
+    /// Scrolls so that the visual row of the cursor stays `scrolloff` rows away from the edges of
+    /// the view. The view scrolls by whole lines, and by rows within the line of the cursor when
+    /// it doesn't fit otherwise.
+    fn ensure_cursor_in_wrapped_view(&mut self, doc: &Document, scrolloff: usize, width: usize) {
+        let text = doc.text().slice(..);
+        let cursor = doc.selection(self.id).primary().cursor(text);
+        let line = text.char_to_line(cursor);
+        let row = WrappedLine::new(text.line(line), width, doc.tab_width())
+            .visual_coords(cursor - text.line_to_char(line))
+            .row;
+
+        let height = self.area.height.saturating_sub(1) as usize; // - 1 for statusline
+        let scrolloff = scrolloff.min(height / 2);
+        self.first_col = 0;
+
+        // every line takes at least a row, lines further away can't be in view
+        if line < self.first_line || line >= self.first_line + height {
+            self.first_line = line.saturating_sub(height.saturating_sub(1));
+            self.first_row = 0;
+        }
+        self.first_row = self.skipped_rows(doc);
+        if line == self.first_line && row < self.first_row {
+            self.first_row = 0;
+        }
+
+        // the rows between the top of the view and the cursor
+        let mut rows_above = row
+            + (self.first_line..line)
+                .map(|line| self.line_height(doc, line))
+                .sum::<usize>()
+            - self.first_row;
+
+        while rows_above < scrolloff && (self.first_line > 0 || self.first_row > 0) {
+            // scroll up
+            if self.first_row > 0 {
+                rows_above += self.first_row;
+                self.first_row = 0;
+            } else {
+                self.first_line -= 1;
+                rows_above += self.line_height(doc, self.first_line);
+            }
+        }
+
+        let max_rows_above = height.saturating_sub(scrolloff + 1);
+        while rows_above > max_rows_above && self.first_line < line {
+            // scroll down
+            rows_above -= self.line_height(doc, self.first_line) - self.first_row;
+            self.first_line += 1;
+            self.first_row = 0;
+        }
+        // the line of the cursor is taller than the view, scroll within it
+        if rows_above > max_rows_above {
+            self.first_row += rows_above - max_rows_above;
+        }
+    }
+
+    /// The number of columns available to display text.
+    pub fn text_width(&self) -> usize {
+        self.area.width.saturating_sub(OFFSET) as usize
+    }
+
+    /// The width lines of `doc` are wrapped at, `None` if they aren't soft wrapped.
+    pub fn wrap_width(&self, doc: &Document) -> Option<usize> {
+        if doc.soft_wrap() {
+            Some(self.text_width().max(1))
+        } else {
+            None
+        }
+    }
+
+    /// The rows of the first line scrolled past, see [`View::first_row`]. Always 0 when lines
+    /// aren't soft wrapped, and less than the rows of the line when it got shorter since.
+    pub fn skipped_rows(&self, doc: &Document) -> usize {
+        if self.first_row == 0 || self.wrap_width(doc).is_none() {
+            return 0;
+        }
+        self.first_row
+            .min(self.line_height(doc, self.first_line).saturating_sub(1))
+    }
+
+    /// The number of rows `line` takes on screen.
+    pub fn line_height(&self, doc: &Document, line: usize) -> usize {
+        match self.wrap_width(doc) {
+            Some(width) => WrappedLine::new(doc.text().line(line), width, doc.tab_width()).rows(),
+            None => 1,
+        }
+    }
+
     /// Calculates the last visible line on screen
     #[inline]
     pub fn last_line(&self, doc: &Document) -> usize {
         let height = self.area.height.saturating_sub(1); // - 1 for statusline
+        let last_line = doc.text().len_lines().saturating_sub(1);
+
+        if self.wrap_width(doc).is_some() {
+            // the last line with at least its first row on screen
+            let mut rows = 0;
+            let height = height as usize + self.skipped_rows(doc);
+            let mut line = self.first_line;
+            while line < last_line {
+                rows += self.line_height(doc, line);
+                if rows >= height {
+                    break;
+                }
+                line += 1;
+            }
+            return line.min(last_line);
+        }
+
         std::cmp::min(
             // Saturating subs to make it inclusive zero indexing.
             (self.first_line + height as usize).saturating_sub(1),
-            doc.text().len_lines().saturating_sub(1),
+            last_line,
         )
     }
 
@@ -144,9 +265,26 @@ impl View {
         }
 
         let line_start = text.line_to_char(line);
+        let tab_width = doc.tab_width();
+
+        if let Some(width) = self.wrap_width(doc) {
+            let coords =
+                WrappedLine::new(text.line(line), width, tab_width).visual_coords(pos - line_start);
+            // rows of the first line scrolled past aren't visible either
+            let row = (coords.row
+                + (self.first_line..line)
+                    .map(|line| self.line_height(doc, line))
+                    .sum::<usize>())
+            .checked_sub(self.skipped_rows(doc))?;
+            if row >= self.area.height.saturating_sub(1) as usize {
+                // the row of a partially visible line is below the view
+                return None;
+            }
+            return Some(Position::new(row, coords.col));
+        }
+
         let line_slice = text.slice(line_start..pos);
         let mut col = 0;
-        let tab_width = doc.tab_width();
 
         for grapheme in RopeGraphemes::new(line_slice) {
             if grapheme == "\t" {
@@ -164,16 +302,16 @@ impl View {
         Some(Position::new(row, col))
     }
 
+    /// Translates a screen position to a position in `text`. Soft wrapped lines are wrapped at the
+    /// [text width](Self::text_width) of the view.
     pub fn text_pos_at_screen_coords(
         &self,
         text: &RopeSlice,
         row: u16,
         column: u16,
         tab_width: usize,
+        soft_wrap: bool,
     ) -> Option<usize> {
-        // TODO: not ideal
-        const OFFSET: u16 = 7; // 1 diagnostic + 5 linenr + 1 gutter
-
         // 2 for status
         if row < self.area.top() || row > self.area.bottom().saturating_sub(2) {
             return None;
@@ -183,6 +321,24 @@ impl View {
             return None;
         }
 
+        let target = (column - OFFSET - self.area.x) as usize;
+
+        if soft_wrap {
+            let width = self.text_width().max(1);
+            let mut row = (row - self.area.y) as usize;
+            for line in self.first_line..text.len_lines() {
+                let wrapped = WrappedLine::new(text.line(line), width, tab_width);
+                if line == self.first_line {
+                    row += self.first_row.min(wrapped.rows() - 1);
+                }
+                if row < wrapped.rows() {
+                    return Some(text.line_to_char(line) + wrapped.offset_at(row, target));
+                }
+                row -= wrapped.rows();
+            }
+            return Some(text.len_chars());
+        }
+
         let line_number = (row - self.area.y) as usize + self.first_line;
 
         if line_number > text.len_lines() - 1 {
@@ -193,7 +349,7 @@ impl View {
 
         let current_line = text.line(line_number);
 
-        let target = (column - OFFSET - self.area.x) as usize + self.first_col;
+        let target = target + self.first_col;
         let mut selected = 0;
 
         for grapheme in RopeGraphemes::new(current_line) {
@@ -215,7 +371,13 @@ impl View {
     /// Translates a screen position to position in the text document.
     /// Returns a usize typed position in bounds of the text if found in this view, None if out of view.
     pub fn pos_at_screen_coords(&self, doc: &Document, row: u16, column: u16) -> Option<usize> {
-        self.text_pos_at_screen_coords(&doc.text().slice(..), row, column, doc.tab_width())
+        self.text_pos_at_screen_coords(
+            &doc.text().slice(..),
+            row,
+            column,
+            doc.tab_width(),
+            doc.soft_wrap(),
+        )
     }
     // pub fn traverse<F>(&self, text: RopeSlice, start: usize, end: usize, fun: F)
     // where
@@ -252,43 +414,58 @@ mod tests {
         let rope = Rope::from_str("abc\n\tdef");
         let text = rope.slice(..);
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 2, 4), None);
+        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 2, 4, false), None);
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 41, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 40, 41, 4, false),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 2, 4), None);
+        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 2, 4, false), None);
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 49, 4), None);
+        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 49, 4, false), None);
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 41, 4), None);
+        assert_eq!(view.text_pos_at_screen_coords(&text, 0, 41, 4, false), None);
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 81, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 40, 81, 4, false),
+            None
+        );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 78, 41, 4), None);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 78, 41, 4, false),
+            None
+        );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 3, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 3, 4, false),
             Some(3)
         );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 40, 80, 4), Some(3));
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 40, 80, 4, false),
+            Some(3)
+        );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 1, 4),
+            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 1, 4, false),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 4, 4),
+            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 4, 4, false),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 7, 4),
+            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 7, 4, false),
             Some(8)
         );
 
-        assert_eq!(view.text_pos_at_screen_coords(&text, 41, 80, 4), Some(8));
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 41, 80, 4, false),
+            Some(8)
+        );
     }
 
     #[test]
@@ -299,27 +476,27 @@ mod tests {
         let text = rope.slice(..);
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 0, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 0, 4, false),
             Some(0)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 5, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 5, 4, false),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 6, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 6, 4, false),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 7, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 7, 4, false),
             Some(6)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 8, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 8, 4, false),
             Some(6)
         );
     }
@@ -332,28 +509,113 @@ mod tests {
         let text = rope.slice(..);
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 0, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 0, 4, false),
             Some(0)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 1, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 1, 4, false),
             Some(1)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 2, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 2, 4, false),
             Some(3)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 3, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 3, 4, false),
             Some(5)
         );
 
         assert_eq!(
-            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 4, 4),
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 4, 4, false),
             Some(7)
         );
     }
+
+    #[test]
+    fn test_text_pos_at_screen_coords_soft_wrap() {
+        let mut view = View::new(DocumentId::default());
+        view.area = Rect::new(40, 40, 10 + OFFSET, 40);
+        // "one two " | "↪ three" | "four"
+        let rope = Rope::from_str("one two three\nfour");
+        let text = rope.slice(..);
+
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 40, 40 + OFFSET + 4, 4, true),
+            Some(4)
+        );
+
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 2, 4, true),
+            Some(8)
+        );
+
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 41, 40 + OFFSET + 9, 4, true),
+            Some(13)
+        );
+
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 42, 40 + OFFSET + 1, 4, true),
+            Some(15)
+        );
+
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 43, 40 + OFFSET, 4, true),
+            Some(18)
+        );
+    }
+
+    #[test]
+    fn test_scroll_within_wrapped_line() {
+        let mut doc = Document::from(Rope::from(format!("{}\nend", "x".repeat(100))), None);
+        doc.language = Some(std::sync::Arc::new(
+            toml::from_str(
+                r#"
+                name = "text"
+                scope = "text.plain"
+                file-types = []
+                roots = []
+                soft-wrap = true
+                "#,
+            )
+            .unwrap(),
+        ));
+        let mut view = View::new(DocumentId::default());
+        // 10 columns of text and 5 rows, the first line takes 13: the rows after the first one
+        // hold 8 chars after the wrap indicator
+        view.area = Rect::new(0, 0, 10 + OFFSET, 6);
+        let text = doc.text().clone();
+        let text = text.slice(..);
+
+        // the last row of the line is at the bottom of the view
+        doc.set_selection(view.id, Selection::point(95));
+        view.ensure_cursor_in_view(&doc, 0);
+        assert_eq!((view.first_line, view.first_row), (0, 7));
+        assert_eq!(
+            view.screen_coords_at_pos(&doc, text, 95),
+            Some(Position::new(4, 7))
+        );
+        assert_eq!(view.screen_coords_at_pos(&doc, text, 0), None);
+        assert_eq!(view.last_line(&doc), 0);
+        assert_eq!(
+            view.text_pos_at_screen_coords(&text, 0, OFFSET + 2, 4, true),
+            Some(58)
+        );
+
+        // with a scrolloff
+        view.ensure_cursor_in_view(&doc, 1);
+        assert_eq!((view.first_line, view.first_row), (0, 8));
+        assert_eq!(
+            view.screen_coords_at_pos(&doc, text, 95),
+            Some(Position::new(3, 7))
+        );
+
+        // back to the start of the line
+        doc.set_selection(view.id, Selection::point(0));
+        view.ensure_cursor_in_view(&doc, 0);
+        assert_eq!((view.first_line, view.first_row), (0, 0));
+    }
 }
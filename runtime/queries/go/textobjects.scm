@@ -0,0 +1,33 @@
+(function_declaration
+  body: (block) @function.inside) @function.around
+
+(method_declaration
+  body: (block) @function.inside) @function.around
+
+(func_literal
+  body: (block) @function.inside) @function.around
+
+(type_declaration
+  (type_spec
+    type: (struct_type
+      (field_declaration_list) @class.inside))) @class.around
+
+(type_declaration
+  (type_spec
+    type: (interface_type
+      (method_spec_list) @class.inside))) @class.around
+
+(parameter_list
+  (_) @parameter.inside)
+
+(argument_list
+  (_) @parameter.inside)
+
+(comment) @comment.inside @comment.around
+
+(
+  (function_declaration
+    name: (identifier) @_name
+    body: (block) @test.inside) @test.around
+  (#match? @_name "^Test")
+)
@@ -0,0 +1,40 @@
+(function_declaration
+  body: (_) @function.inside) @function.around
+
+(function
+  body: (_) @function.inside) @function.around
+
+(arrow_function
+  body: (_) @function.inside) @function.around
+
+(method_definition
+  body: (_) @function.inside) @function.around
+
+(generator_function_declaration
+  body: (_) @function.inside) @function.around
+
+(generator_function
+  body: (_) @function.inside) @function.around
+
+(class_declaration
+  body: (class_body) @class.inside) @class.around
+
+(class
+  body: (class_body) @class.inside) @class.around
+
+(formal_parameters
+  (_) @parameter.inside)
+
+(arguments
+  (_) @parameter.inside)
+
+(comment) @comment.inside @comment.around
+
+(
+  (call_expression
+    function: (identifier) @_name
+    arguments: (arguments
+      (_)
+      [(arrow_function) (function)] @test.inside)) @test.around
+  (#match? @_name "^(describe|it|test)$")
+)
@@ -0,0 +1,26 @@
+(function_definition
+  body: (block) @function.inside) @function.around
+
+(lambda
+  body: (_) @function.inside) @function.around
+
+(class_definition
+  body: (block) @class.inside) @class.around
+
+(parameters
+  (_) @parameter.inside)
+
+(lambda_parameters
+  (_) @parameter.inside)
+
+(argument_list
+  (_) @parameter.inside)
+
+(comment) @comment.inside @comment.around
+
+(
+  (function_definition
+    name: (identifier) @_name
+    body: (block) @test.inside) @test.around
+  (#match? @_name "^test_")
+)
@@ -0,0 +1,45 @@
+(function_item
+  body: (_) @function.inside) @function.around
+
+(closure_expression
+  body: (_) @function.inside) @function.around
+
+(struct_item
+  body: (_) @class.inside) @class.around
+
+(enum_item
+  body: (_) @class.inside) @class.around
+
+(union_item
+  body: (_) @class.inside) @class.around
+
+(trait_item
+  body: (_) @class.inside) @class.around
+
+(impl_item
+  body: (_) @class.inside) @class.around
+
+(parameters
+  (_) @parameter.inside)
+
+(closure_parameters
+  (_) @parameter.inside)
+
+(type_parameters
+  (_) @parameter.inside)
+
+(arguments
+  (_) @parameter.inside)
+
+[
+  (line_comment)
+  (block_comment)
+] @comment.inside @comment.around
+
+(
+  (attribute_item (meta_item (identifier) @_test_attribute))
+  .
+  (function_item
+    body: (_) @test.inside) @test.around
+  (#eq? @_test_attribute "test")
+)
@@ -0,0 +1,16 @@
+; inherits: javascript
+
+(interface_declaration
+  body: (_) @class.inside) @class.around
+
+(abstract_class_declaration
+  body: (class_body) @class.inside) @class.around
+
+(enum_declaration
+  body: (_) @class.inside) @class.around
+
+(type_parameters
+  (_) @parameter.inside)
+
+(type_arguments
+  (_) @parameter.inside)